@@ -1,3 +1,11 @@
+// Debuggee used to try out debugito, its variables only exist to be inspected
+#![allow(
+    unused_variables,
+    unused_assignments,
+    clippy::excessive_precision,
+    clippy::unnecessary_cast
+)]
+
 fn main() {
     let boolean = false;
     let float = 3.1234567890123456789012345678901234567890;
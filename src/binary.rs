@@ -0,0 +1,355 @@
+use anyhow::{Context, anyhow};
+use nix::libc::{major, minor};
+use std::{
+    env, fs,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    BreakpointLocation, LoadedBinary, LoadedLibrary, PendingBreakpoint, ProgramContext,
+    RunningProgram, UserBreakpoint,
+    breakpoints::{add_user_breakpoint, arm_breakpoint, written_location},
+    dwarf::DwarfInfo,
+    registers::Registers,
+};
+
+pub fn reload_binary(context: &mut ProgramContext) -> anyhow::Result<String> {
+    let Some(binary) = &context.binary else {
+        anyhow::bail!("You need to load a binary first");
+    };
+    if context.running_program.is_some() {
+        anyhow::bail!("The program is running the old binary, kill it first");
+    }
+    let binary_path = binary.binary_path.to_string_lossy().into_owned();
+    replace_binary(context, &binary_path, false)
+}
+
+// Like in a shell, names without a slash that aren't in the current
+// directory are looked up in the PATH
+fn find_binary(path: &str) -> anyhow::Result<PathBuf> {
+    if !path.contains('/')
+        && !Path::new(path).exists()
+        && let Some(found) = env::var_os("PATH")
+            .iter()
+            .flat_map(env::split_paths)
+            .map(|directory| directory.join(path))
+            .find(|candidate| {
+                fs::metadata(candidate)
+                    .is_ok_and(|metadata| metadata.is_file() && metadata.mode() & 0o111 != 0)
+            })
+    {
+        return Ok(found.canonicalize()?);
+    }
+    PathBuf::from(path)
+        .canonicalize()
+        .with_context(|| format!("Couldn't find the binary {}", path))
+}
+
+pub fn load_binary(context: &mut ProgramContext, path: &str) -> anyhow::Result<String> {
+    replace_binary(context, path, true)
+}
+
+// The breakpoints of the previous binary are added again from their
+// locations. The ones that don't have code in the new one are kept as
+// pending if keep_missing, like when loading another binary, or deleted.
+// Breakpoints in libraries are kept as they are, they don't depend on the
+// binary
+fn replace_binary(
+    context: &mut ProgramContext,
+    path: &str,
+    keep_missing: bool,
+) -> anyhow::Result<String> {
+    let locations = match &context.binary {
+        Some(binary) => context
+            .breakpoints
+            .iter()
+            .map(|breakpoint| written_location(binary, breakpoint))
+            .collect::<anyhow::Result<Vec<_>>>()?,
+        None => Vec::new(),
+    };
+    let binary_path = find_binary(path)?;
+    let file_buffer = fs::read(&binary_path).context("Failed to read the file")?;
+    let dwarf = DwarfInfo::new(file_buffer, &binary_path)?;
+    // Registers, breakpoints and the stack are handled as in x86-64, other
+    // architectures like aarch64 aren't supported
+    if dwarf.get_architecture() != object::Architecture::X86_64 {
+        anyhow::bail!(
+            "The binary is for {:?}, which isn't supported. Only x86-64 binaries can be debugged",
+            dwarf.get_architecture()
+        );
+    }
+    let possible_breakpoints = dwarf.get_breakpoints_from_dwarf()?;
+    // Stripped binaries or the ones compiled without -g don't have line tables
+    let message = if possible_breakpoints.is_empty() {
+        "Binary loaded, but it doesn't have debug information, so breakpoints can only be set \
+        at addresses. Recompile it with -g to debug it"
+    } else {
+        "Binary loaded"
+    };
+
+    context.binary = Some(LoadedBinary {
+        binary_path,
+        dwarf,
+        possible_breakpoints,
+    });
+    let mut messages = vec![String::from(message)];
+    let pending_breakpoints = std::mem::take(&mut context.pending_breakpoints);
+    // The breakpoints that are added again keep their numbers
+    let last_number = context.last_breakpoint_number;
+    let breakpoints = std::mem::take(&mut context.breakpoints);
+    for (breakpoint, location) in breakpoints.into_iter().zip(locations) {
+        let Some(location) = location else {
+            context.breakpoints.push(breakpoint);
+            continue;
+        };
+        let breakpoint_count = context.breakpoints.len();
+        let reason = match add_user_breakpoint(context, &location, breakpoint.temporary) {
+            Ok(_) if context.breakpoints.len() > breakpoint_count => {
+                let added = context.breakpoints.last_mut().unwrap();
+                added.number = breakpoint.number;
+                added.enabled = breakpoint.enabled;
+                continue;
+            }
+            Ok(message) => message,
+            Err(err) => err.to_string(),
+        };
+        if keep_missing {
+            messages.push(format!(
+                "Breakpoint {} at {} is pending: {}",
+                breakpoint.number, location, reason
+            ));
+            context.pending_breakpoints.push(PendingBreakpoint {
+                location,
+                temporary: breakpoint.temporary,
+            });
+        } else {
+            messages.push(format!(
+                "Breakpoint {} at {} was deleted: {}",
+                breakpoint.number, location, reason
+            ));
+        }
+    }
+    context.last_breakpoint_number = last_number;
+    for pending in pending_breakpoints {
+        let breakpoint_count = context.breakpoints.len();
+        match add_user_breakpoint(context, &pending.location, pending.temporary) {
+            Ok(message) if context.breakpoints.len() > breakpoint_count => messages.push(message),
+            Ok(message) => messages.push(format!(
+                "The pending breakpoint at {} wasn't added: {}",
+                pending.location, message
+            )),
+            Err(err) => messages.push(format!(
+                "The pending breakpoint at {} wasn't added: {}",
+                pending.location, err
+            )),
+        }
+    }
+    Ok(messages.join("\n"))
+}
+
+// Reads the debug info of the shared libraries mapped since the last call,
+// and sets the enabled breakpoints in them
+pub fn load_libraries(
+    running_program: &mut RunningProgram,
+    binary: &LoadedBinary,
+    breakpoints: &[UserBreakpoint],
+) -> anyhow::Result<()> {
+    let maps = rsprocmaps::from_pid(running_program.pid.as_raw())
+        .context("Couldn't read the memory maps of the program")?;
+    let loaded_libraries = running_program.libraries.len();
+    for map in maps.filter_map(Result::ok) {
+        let rsprocmaps::Pathname::Path(path) = &map.pathname else {
+            continue;
+        };
+        let path = PathBuf::from(path);
+        if !map.permissions.executable
+            || map.address_range == running_program.proc_map.address_range
+            || path == binary.binary_path
+            || running_program
+                .libraries
+                .iter()
+                .any(|library| library.path == path)
+        {
+            continue;
+        }
+        // Libraries that can't be read are skipped, they can't have breakpoints
+        let Ok(dwarf) = fs::read(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|buffer| DwarfInfo::new(buffer, Path::new(&path)))
+        else {
+            continue;
+        };
+        let Some(offset) = dwarf.get_code_address_from_file_offset(map.offset) else {
+            continue;
+        };
+        let mut proc_map = map;
+        proc_map.offset = offset;
+        running_program.libraries.push(LoadedLibrary {
+            possible_breakpoints: dwarf.get_breakpoints_from_dwarf()?,
+            path,
+            proc_map,
+            dwarf,
+        });
+    }
+    let new_libraries = running_program.libraries[loaded_libraries..]
+        .iter()
+        .map(|library| library.path.clone())
+        .collect::<Vec<_>>();
+    for breakpoint in breakpoints.iter().filter(|breakpoint| {
+        breakpoint.enabled
+            && matches!(&breakpoint.location, BreakpointLocation::LibraryLine { library, .. }
+                if new_libraries.contains(library))
+    }) {
+        if let Some(virtual_address) = breakpoint.virtual_address(binary, running_program)? {
+            arm_breakpoint(running_program, virtual_address)?;
+        }
+    }
+    Ok(())
+}
+
+// The debug info of the binary or of the shared library whose code is at
+// the address, and the address relative to it
+pub fn find_debug_info<'a>(
+    running_program: &'a RunningProgram,
+    binary: &'a LoadedBinary,
+    address: u64,
+) -> (&'a DwarfInfo, u64) {
+    for library in &running_program.libraries {
+        if map_contains(&library.proc_map, address) {
+            return (
+                &library.dwarf,
+                virtual_address_to_relative(address, &library.proc_map),
+            );
+        }
+    }
+    (
+        &binary.dwarf,
+        virtual_address_to_relative(address, &running_program.proc_map),
+    )
+}
+
+// Like find_debug_info, but returns the load bias needed to find the
+// variables of the frame
+pub fn find_frame_debug_info<'a>(
+    running_program: &'a RunningProgram,
+    binary: &'a LoadedBinary,
+    frame: &Registers,
+) -> (&'a DwarfInfo, u64) {
+    let (dwarf, relative_address) = find_debug_info(running_program, binary, frame.general.rip);
+    (dwarf, frame.general.rip - relative_address)
+}
+
+pub fn map_contains(proc_map: &rsprocmaps::Map, address: u64) -> bool {
+    proc_map.address_range.begin <= address && address < proc_map.address_range.end
+}
+
+// Whether the address is in the code of the binary or of a library loaded
+// by the running program
+pub fn is_mapped_code(running_program: &RunningProgram, address: u64) -> bool {
+    map_contains(&running_program.proc_map, address)
+        || running_program
+            .libraries
+            .iter()
+            .any(|library| map_contains(&library.proc_map, address))
+}
+
+// The rest of the ELF is loaded with the same offset as its code, so
+// addresses outside of the mapping can be translated too. Addresses that
+// don't belong to the ELF result in addresses without debug info
+pub fn virtual_address_to_relative(address: u64, proc_map: &rsprocmaps::Map) -> u64 {
+    address
+        .wrapping_sub(proc_map.address_range.begin)
+        .wrapping_add(proc_map.offset)
+}
+
+// Only used for code, which is modified to set breakpoints, so the result
+// must be inside the mapping
+pub fn relative_address_to_virtual(
+    address: u64,
+    proc_map: &rsprocmaps::Map,
+) -> anyhow::Result<u64> {
+    let virtual_address = address
+        .checked_add(proc_map.address_range.begin)
+        .and_then(|address| address.checked_sub(proc_map.offset))
+        .filter(|&address| map_contains(proc_map, address))
+        .ok_or(anyhow!(
+            "The address {:#x} isn't in the code mapped at {:#x}-{:#x}",
+            address,
+            proc_map.address_range.begin,
+            proc_map.address_range.end
+        ))?;
+    Ok(virtual_address)
+}
+
+// The map is identified by the device and inode of the binary, the path can
+// be reported differently, for example when the binary is behind a symlink
+pub fn get_range_for_program_source_code(
+    pid: u64,
+    binary: &LoadedBinary,
+) -> anyhow::Result<rsprocmaps::Map> {
+    let metadata = fs::metadata(&binary.binary_path)?;
+    let executable_pathname =
+        rsprocmaps::Pathname::Path(binary.binary_path.to_string_lossy().into_owned());
+    let maps =
+        rsprocmaps::from_pid(pid as i32).context("Couldn't read the memory maps of the program")?;
+    let mut map = maps
+        .filter_map(Result::ok)
+        .find(|map| {
+            let same_file = map.inode == metadata.ino()
+                && map.device.major == major(metadata.dev()) as u64
+                && map.device.minor == minor(metadata.dev()) as u64;
+            map.permissions.executable && (same_file || map.pathname == executable_pathname)
+        })
+        .ok_or(anyhow!(
+            "Couldn't find where the code of the program is loaded"
+        ))?;
+    // The offset is replaced by the address the ELF expects for it, so the
+    // translated addresses match the ones in the DWARF even if the binary
+    // isn't loaded at the same addresses as its file offsets
+    map.offset = binary
+        .dwarf
+        .get_code_address_from_file_offset(map.offset)
+        .ok_or(anyhow!("The code of the program isn't in any ELF segment"))?;
+    Ok(map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Code mapped like the one of a PIE, after the first page of the ELF
+    fn code_mapping() -> rsprocmaps::Map {
+        rsprocmaps::from_str("555555555000-555555556000 r-xp 00001000 08:01 1234 /tmp/program\n")
+            .next()
+            .unwrap()
+            .unwrap()
+    }
+
+    #[test]
+    fn translates_addresses_of_the_code_mapping() {
+        let map = code_mapping();
+        assert_eq!(
+            relative_address_to_virtual(0x1139, &map).unwrap(),
+            0x555555555139
+        );
+        assert_eq!(virtual_address_to_relative(0x555555555139, &map), 0x1139);
+    }
+
+    #[test]
+    fn rejects_addresses_before_the_offset_of_the_mapping() {
+        let mut map = code_mapping();
+        map.address_range.begin = 0x1000;
+        map.address_range.end = 0x2000;
+        map.offset = 0x3000;
+        assert!(relative_address_to_virtual(0x1000, &map).is_err());
+    }
+
+    #[test]
+    fn rejects_addresses_outside_of_the_mapping() {
+        let map = code_mapping();
+        assert!(relative_address_to_virtual(0x2000, &map).is_err());
+        assert!(relative_address_to_virtual(0xfff, &map).is_err());
+        assert!(relative_address_to_virtual(u64::MAX, &map).is_err());
+    }
+}
@@ -0,0 +1,738 @@
+use anyhow::{Context, anyhow};
+use nix::{
+    libc::{SI_KERNEL, TRAP_BRKPT},
+    sys::{ptrace, signal::Signal::SIGTRAP, wait::WaitStatus},
+};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    Address, Breakpoint, BreakpointLocation, LoadedBinary, PendingBreakpoint, ProgramContext,
+    RunningProgram, SetBreakpoint, UserBreakpoint, binary::load_libraries, dwarf::DwarfInfo,
+    execution::get_last_instruction_address, output::Output, tracee::Tracee,
+};
+
+// The location as it would be written in the break command, None for the
+// breakpoints in libraries. Function breakpoints are given by name, the
+// function can be at another line after recompiling
+pub fn written_location(
+    binary: &LoadedBinary,
+    breakpoint: &UserBreakpoint,
+) -> anyhow::Result<Option<String>> {
+    Ok(match &breakpoint.location {
+        BreakpointLocation::Line(line) => {
+            let function = match breakpoint.requested_line {
+                Some(_) => None,
+                None => binary
+                    .dwarf
+                    .get_function_from_address(breakpoint.relative_address(binary)?.unwrap()),
+            };
+            Some(match function {
+                Some(function) => function.to_owned(),
+                None => format!(
+                    "{}:{}",
+                    line.file.to_string_lossy(),
+                    breakpoint.requested_line.unwrap_or(line.line_number)
+                ),
+            })
+        }
+        BreakpointLocation::Address(address) => Some(format!("*{:#x}", address)),
+        BreakpointLocation::LibraryLine { .. } => None,
+    })
+}
+
+// Tools need the number of the new breakpoint to refer to it later
+pub fn add_numbered_breakpoint(
+    context: &mut ProgramContext,
+    breakpoint_str: &str,
+    temporary: bool,
+) -> anyhow::Result<Output> {
+    let breakpoint_count = context.breakpoints.len();
+    let output = Output::new(add_user_breakpoint(context, breakpoint_str, temporary)?);
+    Ok(if context.breakpoints.len() > breakpoint_count {
+        output.with("breakpoint", context.breakpoints.last().unwrap().number)
+    } else {
+        output
+    })
+}
+
+pub fn add_user_breakpoint(
+    context: &mut ProgramContext,
+    breakpoint_str: &str,
+    temporary: bool,
+) -> anyhow::Result<String> {
+    let Some(loaded_binary) = context.binary.as_ref() else {
+        context.pending_breakpoints.push(PendingBreakpoint {
+            location: breakpoint_str.to_owned(),
+            temporary,
+        });
+        return Ok(format!(
+            "There is no binary loaded, the breakpoint at {} will be added when loading one",
+            breakpoint_str
+        ));
+    };
+    if let Some(address_str) = breakpoint_str.strip_prefix('*') {
+        let address = parse_address(address_str)?;
+        if !loaded_binary.dwarf.is_code_address(address) {
+            anyhow::bail!("The address {:#x} isn't in the code of the binary", address);
+        }
+        let (kind, _, note) = add_breakpoint_at(
+            context,
+            BreakpointLocation::Address(address),
+            temporary,
+            None,
+        )?;
+        return Ok(format!("{} added to {:#x}{}", kind, address, note));
+    }
+    let is_line = breakpoint_str.contains(':');
+    let requested_breakpoint = if is_line {
+        breakpoint_str.parse()?
+    } else {
+        find_function_breakpoint(&loaded_binary.dwarf, breakpoint_str)?
+    };
+    let requested_line = requested_breakpoint.line_number;
+    let (location, breakpoint) = match find_breakpoint(
+        &loaded_binary.possible_breakpoints,
+        requested_breakpoint.clone(),
+    )? {
+        Some(breakpoint) => (BreakpointLocation::Line(breakpoint.clone()), breakpoint),
+        None => match find_library_breakpoint(
+            context.running_program.as_mut(),
+            loaded_binary,
+            &context.breakpoints,
+            requested_breakpoint.clone(),
+        )? {
+            Some((library, breakpoint)) => (
+                BreakpointLocation::LibraryLine {
+                    library,
+                    line: breakpoint.clone(),
+                },
+                breakpoint,
+            ),
+            None if is_line => {
+                return explain_missing_line(context, &requested_breakpoint);
+            }
+            None => return Ok("Not a valid breakpoint position".to_owned()),
+        },
+    };
+    let is_library_line = matches!(location, BreakpointLocation::LibraryLine { .. });
+    let (kind, address, note) = add_breakpoint_at(
+        context,
+        location,
+        temporary,
+        is_line.then_some(requested_line),
+    )?;
+    let mut message = if breakpoint.line_number == requested_line {
+        format!("{} added to {}", kind, breakpoint_str)
+    } else {
+        format!(
+            "Line {} has no code, {} added to {}:{}",
+            requested_line,
+            kind.to_lowercase(),
+            breakpoint.file.to_string_lossy(),
+            breakpoint.line_number
+        )
+    };
+    if let Some(address) = address {
+        message += &format!(" at {:#x}", address);
+    }
+    // The address can belong to a later line, like when the prologue of a
+    // function is skipped
+    let binary = context.binary.as_ref().unwrap();
+    if !is_library_line
+        && let Some(line) = binary
+            .dwarf
+            .get_line_from_address(binary.possible_breakpoints[&breakpoint])
+            .ok()
+            .filter(|line| line.line_number as u64 != breakpoint.line_number)
+    {
+        message += &format!(", the code there belongs to line {}", line.line_number);
+    }
+    Ok(message + &note)
+}
+
+// Returns the kind of breakpoint added and its address, to report them, and
+// a note listing the other breakpoints at the same address. The address is
+// the one in the running program if there is one
+fn add_breakpoint_at(
+    context: &mut ProgramContext,
+    location: BreakpointLocation,
+    temporary: bool,
+    requested_line: Option<u64>,
+) -> anyhow::Result<(&'static str, Option<Address>, String)> {
+    let binary = context.binary.as_ref().unwrap();
+    let breakpoint = UserBreakpoint {
+        number: context.last_breakpoint_number + 1,
+        location,
+        enabled: true,
+        temporary,
+        requested_line,
+    };
+    let relative_address = breakpoint.relative_address(binary)?;
+    let same_address = context
+        .breakpoints
+        .iter()
+        .filter(|b| {
+            b.location == breakpoint.location
+                || relative_address.is_some()
+                    && b.relative_address(binary).ok().flatten() == relative_address
+        })
+        .map(|b| b.number.to_string())
+        .collect::<Vec<_>>();
+    let mut address = relative_address;
+    if let Some(running_program) = &mut context.running_program
+        && let Some(virtual_address) = breakpoint.virtual_address(binary, running_program)?
+    {
+        arm_breakpoint(running_program, virtual_address)?;
+        address = Some(virtual_address);
+    }
+    context.last_breakpoint_number = breakpoint.number;
+    context.breakpoints.push(breakpoint);
+    let kind = if temporary {
+        "Temporary breakpoint"
+    } else {
+        "Breakpoint"
+    };
+    let note = match same_address.as_slice() {
+        [] => String::new(),
+        [number] => format!(", breakpoint {} is also there", number),
+        numbers => format!(", breakpoints {} are also there", numbers.join(", ")),
+    };
+    Ok((kind, address, note))
+}
+
+// Breakpoints in shared libraries can only be set once the running program
+// has mapped them. Returns the path of the library and the breakpoint
+fn find_library_breakpoint(
+    running_program: Option<&mut RunningProgram>,
+    binary: &LoadedBinary,
+    breakpoints: &[UserBreakpoint],
+    breakpoint: Breakpoint,
+) -> anyhow::Result<Option<(PathBuf, Breakpoint)>> {
+    let Some(running_program) = running_program else {
+        return Ok(None);
+    };
+    load_libraries(running_program, binary, breakpoints)?;
+    for library in &running_program.libraries {
+        if let Some(found) = find_breakpoint(&library.possible_breakpoints, breakpoint.clone())? {
+            return Ok(Some((library.path.clone(), found)));
+        }
+    }
+    Ok(None)
+}
+
+// Sets the breakpoints of the libraries that weren't loaded when the
+// program started. The memory maps are only read if there are any
+pub fn arm_library_breakpoints(
+    running_program: &mut RunningProgram,
+    binary: &LoadedBinary,
+    breakpoints: &[UserBreakpoint],
+) -> anyhow::Result<()> {
+    if !breakpoints.iter().any(|breakpoint| {
+        breakpoint.enabled && matches!(breakpoint.location, BreakpointLocation::LibraryLine { .. })
+    }) {
+        return Ok(());
+    }
+    load_libraries(running_program, binary, breakpoints)
+}
+
+// Hexadecimal with the 0x prefix, or decimal
+pub fn parse_address(address: &str) -> anyhow::Result<Address> {
+    match address.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => address.parse(),
+    }
+    .context("Couldn't parse the address")
+}
+
+// The breakpoint at the line where the function starts, which is moved after
+// its prologue when loading the binary
+pub fn find_function_breakpoint(dwarf: &DwarfInfo, function: &str) -> anyhow::Result<Breakpoint> {
+    let mut locations = dwarf
+        .get_function_addresses(function)
+        .into_iter()
+        .filter_map(|address| dwarf.get_line_from_address(address).ok())
+        .map(|line_pos| Breakpoint {
+            file: line_pos.path,
+            line_number: line_pos.line_number as u64,
+        })
+        .collect::<Vec<_>>();
+    locations.sort_by(|a, b| (&a.file, a.line_number).cmp(&(&b.file, b.line_number)));
+    locations.dedup();
+    // Functions with the same name in dependencies, whose sources usually
+    // aren't available, shouldn't get in the way of the user's own
+    let local_locations = locations
+        .iter()
+        .filter(|location| location.file.exists())
+        .collect::<Vec<_>>();
+    if let [location] = local_locations.as_slice() {
+        return Ok((*location).clone());
+    }
+    match locations.as_slice() {
+        [] => anyhow::bail!("There is no function called {}", function),
+        [location] => Ok(location.clone()),
+        _ => anyhow::bail!(
+            "Ambiguous function name, it could refer to:\n{}",
+            locations
+                .iter()
+                .map(|location| format!(
+                    "{}:{}",
+                    location.file.to_string_lossy(),
+                    location.line_number
+                ))
+                .collect::<Vec<_>>()
+                .join("\n")
+        ),
+    }
+}
+
+// Matches the breakpoint given by the user with one of the positions found
+// in the DWARF. If the paths can't be compared directly, because either the
+// given path or the sources don't exist in this filesystem, it matches the
+// files whose path ends with the given one. Lines without code are moved to
+// the next line that has some, like gdb does
+pub fn find_breakpoint(
+    possible_breakpoints: &HashMap<Breakpoint, Address>,
+    breakpoint: Breakpoint,
+) -> anyhow::Result<Option<Breakpoint>> {
+    let Some(file) = find_breakpoint_file(possible_breakpoints, &breakpoint.file)? else {
+        return Ok(None);
+    };
+    Ok(possible_breakpoints
+        .keys()
+        .filter(|candidate| {
+            candidate.file == file && candidate.line_number >= breakpoint.line_number
+        })
+        .min_by_key(|candidate| candidate.line_number)
+        .cloned())
+}
+
+// Why there's no code at or after the line, for the file of the binary or
+// of a loaded library that has it
+fn explain_missing_line(
+    context: &ProgramContext,
+    breakpoint: &Breakpoint,
+) -> anyhow::Result<String> {
+    let binary = context.binary.as_ref().unwrap();
+    let libraries = context
+        .running_program
+        .iter()
+        .flat_map(|running_program| &running_program.libraries)
+        .map(|library| &library.possible_breakpoints);
+    for possible_breakpoints in std::iter::once(&binary.possible_breakpoints).chain(libraries) {
+        let Some(file) = find_breakpoint_file(possible_breakpoints, &breakpoint.file)? else {
+            continue;
+        };
+        // The source isn't always available, like when it was moved
+        if let Ok(source) = fs::read_to_string(&file)
+            && breakpoint.line_number as usize > source.lines().count()
+        {
+            return Ok(format!(
+                "{} only has {} lines",
+                file.to_string_lossy(),
+                source.lines().count()
+            ));
+        }
+        let closest_line = possible_breakpoints
+            .keys()
+            .filter(|candidate| candidate.file == file)
+            .map(|candidate| candidate.line_number)
+            .max();
+        return Ok(match closest_line {
+            Some(line) => format!(
+                "Line {} has no code and neither do the ones after it, the last line with code is {}",
+                breakpoint.line_number, line
+            ),
+            None => format!("Line {} has no code", breakpoint.line_number),
+        });
+    }
+    Ok(format!(
+        "There is no code from {} in the binary",
+        breakpoint.file.to_string_lossy()
+    ))
+}
+
+pub fn find_breakpoint_file(
+    possible_breakpoints: &HashMap<Breakpoint, Address>,
+    file: &Path,
+) -> anyhow::Result<Option<PathBuf>> {
+    if let Ok(canonical_file) = file.canonicalize()
+        && possible_breakpoints
+            .keys()
+            .any(|candidate| candidate.file == canonical_file)
+    {
+        return Ok(Some(canonical_file));
+    }
+    let mut candidates = possible_breakpoints
+        .keys()
+        .map(|candidate| &candidate.file)
+        .filter(|candidate| candidate.ends_with(file))
+        .collect::<Vec<_>>();
+    candidates.sort();
+    candidates.dedup();
+    match candidates.as_slice() {
+        [] => Ok(None),
+        [candidate] => Ok(Some(candidate.to_path_buf())),
+        _ => anyhow::bail!(
+            "Ambiguous breakpoint position, it could refer to:\n{}",
+            candidates
+                .iter()
+                .map(|candidate| candidate.to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("\n")
+        ),
+    }
+}
+
+pub fn change_breakpoint_enabled(
+    context: &mut ProgramContext,
+    number: usize,
+    enabled: bool,
+) -> anyhow::Result<String> {
+    let breakpoint = context
+        .breakpoints
+        .iter_mut()
+        .find(|breakpoint| breakpoint.number == number)
+        .ok_or(anyhow!("There is no breakpoint {}", number))?;
+    if breakpoint.enabled == enabled {
+        return Ok(String::from("Nothing to do"));
+    }
+    if let Some(running_program) = &mut context.running_program {
+        let binary = context.binary.as_ref().unwrap(); // If there's a pid, there's a binary
+        match breakpoint.virtual_address(binary, running_program)? {
+            Some(virtual_address) if enabled => arm_breakpoint(running_program, virtual_address)?,
+            Some(virtual_address) => disarm_breakpoint(running_program, virtual_address)?,
+            None => {}
+        }
+    }
+    breakpoint.enabled = enabled;
+    Ok(format!(
+        "Breakpoint {} {}",
+        number,
+        if enabled { "enabled" } else { "disabled" }
+    ))
+}
+
+// Each line is the command that adds the breakpoint, followed by disabled
+// if it is, so the files can also be read and edited by hand
+pub fn save_breakpoints(context: &ProgramContext, path: &str) -> anyhow::Result<String> {
+    let mut contents = String::new();
+    for breakpoint in &context.breakpoints {
+        let location = match &breakpoint.location {
+            BreakpointLocation::Line(line) | BreakpointLocation::LibraryLine { line, .. } => {
+                format!("{}:{}", line.file.to_string_lossy(), line.line_number)
+            }
+            BreakpointLocation::Address(address) => format!("*{:#x}", address),
+        };
+        contents.push_str(if breakpoint.temporary {
+            "tbreak "
+        } else {
+            "break "
+        });
+        contents.push_str(&location);
+        if !breakpoint.enabled {
+            contents.push_str(" disabled");
+        }
+        contents.push('\n');
+    }
+    fs::write(path, contents).with_context(|| format!("Failed to write {}", path))?;
+    Ok(format!(
+        "Saved {} breakpoints to {}",
+        context.breakpoints.len(),
+        path
+    ))
+}
+
+// The breakpoints are checked against the loaded binary, the ones that
+// can't be set anymore are skipped
+pub fn load_breakpoints(context: &mut ProgramContext, path: &str) -> anyhow::Result<String> {
+    if context.binary.is_none() {
+        anyhow::bail!("Please load a binary first");
+    }
+    let contents = fs::read_to_string(path).with_context(|| format!("Failed to read {}", path))?;
+    let mut loaded = 0;
+    let mut messages = Vec::new();
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (location, enabled) = match line.strip_suffix(" disabled") {
+            Some(location) => (location, false),
+            None => (line, true),
+        };
+        let (location, temporary) = match location.split_once(' ') {
+            Some(("break" | "b", location)) => (location.trim(), false),
+            Some(("tbreak", location)) => (location.trim(), true),
+            _ => {
+                messages.push(format!("Line {}: expected break or tbreak", index + 1));
+                continue;
+            }
+        };
+        let count = context.breakpoints.len();
+        match add_user_breakpoint(context, location, temporary) {
+            Ok(_) if context.breakpoints.len() > count => {
+                loaded += 1;
+                if !enabled {
+                    let number = context.breakpoints.last().unwrap().number;
+                    change_breakpoint_enabled(context, number, false)?;
+                }
+            }
+            Ok(message) => messages.push(format!("Line {}: {}", index + 1, message)),
+            Err(error) => messages.push(format!("Line {}: {}", index + 1, error)),
+        }
+    }
+    messages.push(format!("Loaded {} breakpoints from {}", loaded, path));
+    Ok(messages.join("\n"))
+}
+
+// The code at the address, without the trap instructions of the breakpoints
+pub fn read_original_word(running_program: &RunningProgram, address: u64) -> anyhow::Result<i64> {
+    let mut bytes = running_program.thread.read_word(address)?.to_ne_bytes();
+    for (index, byte) in bytes.iter_mut().enumerate() {
+        if let Some(breakpoint) = running_program
+            .set_breakpoints
+            .get(&(address + index as u64))
+        {
+            *byte = breakpoint.original_word.to_ne_bytes()[0];
+        }
+    }
+    Ok(i64::from_ne_bytes(bytes))
+}
+
+// Returns the original word at the address
+// Fails if the memory isn't mapped or writable, or if the trap instruction
+// isn't there after writing it, since the breakpoint would never be hit
+fn setup_breakpoint(tracee: impl Tracee, virtual_address: u64) -> anyhow::Result<i64> {
+    let original_word = tracee.read_word(virtual_address).with_context(|| {
+        format!(
+            "Can't set a breakpoint at {:#x}, the address isn't mapped",
+            virtual_address
+        )
+    })?;
+    write_trap_instruction(tracee, virtual_address).with_context(|| {
+        format!(
+            "Can't set a breakpoint at {:#x}, the memory isn't writable",
+            virtual_address
+        )
+    })?;
+    let word = tracee.read_word(virtual_address)?;
+    if word != add_trap_instruction(word) {
+        anyhow::bail!(
+            "Can't set a breakpoint at {:#x}, the memory didn't change after writing to it",
+            virtual_address
+        );
+    }
+    Ok(original_word)
+}
+
+pub fn stopped_at_breakpoint(running_program: &RunningProgram) -> bool {
+    let WaitStatus::Stopped(pid, SIGTRAP) = running_program.last_status else {
+        return false;
+    };
+    // Single steps also stop with a SIGTRAP, only the ones coming from a
+    // trap instruction mean that the program stopped at a breakpoint
+    let Ok(signal_info) = ptrace::getsiginfo(pid) else {
+        return false;
+    };
+    if signal_info.si_code != SI_KERNEL && signal_info.si_code != TRAP_BRKPT {
+        return false;
+    }
+    get_last_instruction_address(pid)
+        .is_ok_and(|address| running_program.set_breakpoints.contains_key(&address))
+}
+
+pub fn remove_temporary_breakpoint(running_program: &mut RunningProgram) -> anyhow::Result<()> {
+    if let Some(address) = running_program.temporary_breakpoint.take() {
+        disarm_breakpoint(running_program, address)?;
+    }
+    Ok(())
+}
+
+// Sets the breakpoint in the running program, or adds an owner to it if
+// it's already set
+pub fn arm_breakpoint(
+    running_program: &mut RunningProgram,
+    virtual_address: u64,
+) -> anyhow::Result<()> {
+    add_breakpoint_owner(
+        &mut running_program.set_breakpoints,
+        running_program.thread,
+        virtual_address,
+    )
+}
+
+// The breakpoint is only removed from the program once it has no owners
+pub fn disarm_breakpoint(
+    running_program: &mut RunningProgram,
+    virtual_address: u64,
+) -> anyhow::Result<()> {
+    let pid = running_program.thread;
+    let stopped_at_this_breakpoint = stopped_at_breakpoint(running_program)
+        && get_last_instruction_address(pid)? == virtual_address;
+    let restored =
+        remove_breakpoint_owner(&mut running_program.set_breakpoints, pid, virtual_address)?;
+    // The trap instruction was already executed, but the original one
+    // still needs to be
+    if restored && stopped_at_this_breakpoint {
+        let mut registers = pid.get_regs()?;
+        registers.rip -= 1;
+        pid.set_regs(registers)?;
+    }
+    Ok(())
+}
+
+pub fn add_breakpoint_owner(
+    set_breakpoints: &mut HashMap<Address, SetBreakpoint>,
+    tracee: impl Tracee,
+    virtual_address: u64,
+) -> anyhow::Result<()> {
+    if let Some(breakpoint) = set_breakpoints.get_mut(&virtual_address) {
+        breakpoint.owners += 1;
+        return Ok(());
+    }
+    let original_word = setup_breakpoint(tracee, virtual_address)?;
+    set_breakpoints.insert(
+        virtual_address,
+        SetBreakpoint {
+            original_word,
+            owners: 1,
+        },
+    );
+    Ok(())
+}
+
+// Returns whether the original instruction was restored, which happens
+// when the last owner is removed
+fn remove_breakpoint_owner(
+    set_breakpoints: &mut HashMap<Address, SetBreakpoint>,
+    tracee: impl Tracee,
+    virtual_address: u64,
+) -> anyhow::Result<bool> {
+    let Some(breakpoint) = set_breakpoints.get_mut(&virtual_address) else {
+        return Ok(false);
+    };
+    breakpoint.owners -= 1;
+    if breakpoint.owners > 0 {
+        return Ok(false);
+    }
+    let original_word = breakpoint.original_word;
+    set_breakpoints.remove(&virtual_address);
+    restore_original_instruction(tracee, virtual_address, original_word)?;
+    Ok(true)
+}
+
+fn add_trap_instruction(word: i64) -> i64 {
+    const TRAP_INSTRUCTION: i64 = 0xCC;
+    // Only valid for x86
+    (word & (!0xFF)) | TRAP_INSTRUCTION
+}
+
+// Breakpoints closer than a word share bytes, so only the first byte of
+// the word is modified, keeping the traps of the following addresses
+pub fn write_trap_instruction(tracee: impl Tracee, address: u64) -> anyhow::Result<()> {
+    let word = tracee.read_word(address)?;
+    tracee.write_word(address, add_trap_instruction(word))
+}
+
+pub fn restore_original_instruction(
+    tracee: impl Tracee,
+    address: u64,
+    original_word: i64,
+) -> anyhow::Result<()> {
+    let word = tracee.read_word(address)?;
+    tracee.write_word(address, (word & !0xFF) | (original_word & 0xFF))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tracee::mock::{CODE, CODE_ADDRESS, MockTracee};
+
+    #[test]
+    fn writes_the_trap_instruction_in_the_first_byte() {
+        let tracee = MockTracee::with_code(CODE_ADDRESS, &CODE);
+        write_trap_instruction(&tracee, CODE_ADDRESS + 4).unwrap();
+        assert_eq!(tracee.byte(CODE_ADDRESS + 4), 0xcc);
+        // The rest of the word is left as it was
+        for offset in (0..CODE.len()).filter(|&offset| offset != 4) {
+            assert_eq!(tracee.byte(CODE_ADDRESS + offset as u64), CODE[offset]);
+        }
+    }
+
+    #[test]
+    fn sets_up_a_breakpoint_and_restores_the_instruction() {
+        let tracee = MockTracee::with_code(CODE_ADDRESS, &CODE);
+        let original_word = setup_breakpoint(&tracee, CODE_ADDRESS).unwrap();
+        assert_eq!(original_word as u8, 0x55);
+        assert_eq!(tracee.byte(CODE_ADDRESS), 0xcc);
+        restore_original_instruction(&tracee, CODE_ADDRESS, original_word).unwrap();
+        assert_eq!(tracee.byte(CODE_ADDRESS), 0x55);
+    }
+
+    // Their words overlap, restoring one keeps the trap of the other
+    #[test]
+    fn restores_breakpoints_closer_than_a_word() {
+        let tracee = MockTracee::with_code(CODE_ADDRESS, &CODE);
+        let first = setup_breakpoint(&tracee, CODE_ADDRESS).unwrap();
+        setup_breakpoint(&tracee, CODE_ADDRESS + 1).unwrap();
+        restore_original_instruction(&tracee, CODE_ADDRESS, first).unwrap();
+        assert_eq!(tracee.byte(CODE_ADDRESS), 0x55);
+        assert_eq!(tracee.byte(CODE_ADDRESS + 1), 0xcc);
+    }
+
+    #[test]
+    fn reports_breakpoints_at_unmapped_addresses() {
+        let tracee = MockTracee::with_code(CODE_ADDRESS, &CODE);
+        let error = setup_breakpoint(&tracee, 0x1000).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Can't set a breakpoint at 0x1000, the address isn't mapped"
+        );
+    }
+
+    #[test]
+    fn reports_breakpoints_in_read_only_memory() {
+        let tracee = MockTracee {
+            read_only: true,
+            ..MockTracee::with_code(CODE_ADDRESS, &CODE)
+        };
+        let error = setup_breakpoint(&tracee, CODE_ADDRESS).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Can't set a breakpoint at 0x401000, the memory isn't writable"
+        );
+        assert_eq!(tracee.byte(CODE_ADDRESS), 0x55);
+    }
+
+    #[test]
+    fn reports_writes_that_do_nothing() {
+        let tracee = MockTracee {
+            ignore_writes: true,
+            ..MockTracee::with_code(CODE_ADDRESS, &CODE)
+        };
+        let error = setup_breakpoint(&tracee, CODE_ADDRESS).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Can't set a breakpoint at 0x401000, the memory didn't change after writing to it"
+        );
+    }
+
+    // Like two breakpoints at the same line
+    #[test]
+    fn keeps_the_trap_until_the_last_breakpoint_at_an_address_is_removed() {
+        let tracee = MockTracee::with_code(CODE_ADDRESS, &CODE);
+        let mut set_breakpoints = HashMap::new();
+        add_breakpoint_owner(&mut set_breakpoints, &tracee, CODE_ADDRESS).unwrap();
+        add_breakpoint_owner(&mut set_breakpoints, &tracee, CODE_ADDRESS).unwrap();
+        assert_eq!(set_breakpoints[&CODE_ADDRESS].original_word as u8, 0x55);
+        assert!(!remove_breakpoint_owner(&mut set_breakpoints, &tracee, CODE_ADDRESS).unwrap());
+        assert_eq!(tracee.byte(CODE_ADDRESS), 0xcc);
+        assert!(remove_breakpoint_owner(&mut set_breakpoints, &tracee, CODE_ADDRESS).unwrap());
+        assert_eq!(tracee.byte(CODE_ADDRESS), 0x55);
+        assert!(set_breakpoints.is_empty());
+    }
+}
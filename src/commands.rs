@@ -0,0 +1,557 @@
+// The commands of the REPL, they parse their arguments and call the
+// operations of ProgramContext
+use std::path::Path;
+
+use debugito::{PrintOptions, ProgramContext, Radix, output::Output};
+
+use crate::repl::{Repl, ask_for_confirmation};
+
+// Runs the commands of the script, or reads them from the terminal if
+// there's none
+pub fn run_repl(context: ProgramContext, script: Option<&Path>) -> anyhow::Result<()> {
+    let mut repl = Repl::new(context)
+        .add_command(
+            clap::Command::new("load")
+                .visible_alias("l")
+                .arg(
+                    clap::Arg::new("binary_path")
+                        .required(true)
+                        .value_hint(clap::ValueHint::FilePath)
+                        .help("the path to the executable binary"),
+                )
+                .about("load a binary to prepare for debugging"),
+            load_program,
+        )
+        .add_command(
+            clap::Command::new("reload")
+                .about("Load the binary again from its path, like after recompiling it"),
+            reload_binary,
+        )
+        .add_command(
+            clap::Command::new("add-inferior")
+                .about("Add another program to debug, with its own binary, breakpoints and run"),
+            add_inferior,
+        )
+        .add_command(
+            clap::Command::new("inferior")
+                .arg(
+                    clap::Arg::new("number")
+                        .required(true)
+                        .value_parser(clap::value_parser!(usize))
+                        .help("as shown by info inferiors"),
+                )
+                .about("Select the program that the rest of the commands act on"),
+            select_inferior,
+        )
+        .add_command(
+            clap::Command::new("breakpoint")
+                .visible_alias("b")
+                .arg(
+                    clap::Arg::new("where")
+                        .required(true)
+                        .help("in the form \"source_file:line_number\", the name of a function or *address"),
+                )
+                .about("set a breakpoint"),
+            add_breakpoint,
+        )
+        .add_command(
+            clap::Command::new("tbreak")
+                .arg(
+                    clap::Arg::new("where")
+                        .required(true)
+                        .help("in the form \"source_file:line_number\", the name of a function or *address"),
+                )
+                .about("set a breakpoint that is deleted after being hit once"),
+            add_temporary_breakpoint,
+        )
+        .add_command(
+            clap::Command::new("run")
+                .visible_alias("r")
+                .arg(
+                    clap::Arg::new("program_args")
+                        .trailing_var_arg(true)
+                        .num_args(0..)
+                        .help("arguments for the program, \"< file\", \"> file\" and \"2> file\" redirect its input and output"),
+                )
+                .arg(
+                    clap::Arg::new("stop-at-entry")
+                        .long("stop-at-entry")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("stop at the entry point of the binary, before any breakpoint"),
+                )
+                .about("run the specified binary until finding a breakpoint"),
+            run_program,
+        )
+        .add_command(
+            clap::Command::new("rerun")
+                .visible_alias("rr")
+                .about("Restart the program with the arguments of the last run, without asking"),
+            rerun_program,
+        )
+        .add_command(
+            clap::Command::new("continue")
+                .visible_alias("c")
+                .arg(
+                    clap::Arg::new("discard-signal")
+                        .long("discard-signal")
+                        .short('d')
+                        .action(clap::ArgAction::SetTrue)
+                        .help("don't deliver the signal that stopped the program"),
+                )
+                .about("Keep running the program until a breakpoint"),
+            continue_program,
+        )
+        .add_command(
+            clap::Command::new("until")
+                .visible_alias("u")
+                .arg(
+                    clap::Arg::new("where")
+                        .required(true)
+                        .help("in the form \"source_file:line_number\""),
+                )
+                .about("Keep running the program until reaching a line or a breakpoint"),
+            run_until,
+        )
+        .add_command(
+            clap::Command::new("watch")
+                .arg(
+                    clap::Arg::new("var")
+                        .required(true)
+                        .help("name of the variable"),
+                )
+                .about("Stop the program when the value of a variable changes"),
+            add_watchpoint,
+        )
+        .add_command(
+            clap::Command::new("enable")
+                .arg(
+                    clap::Arg::new("number")
+                        .required(true)
+                        .value_parser(clap::value_parser!(usize))
+                        .help("number of the breakpoint, as shown by info breakpoints"),
+                )
+                .about("Enable a breakpoint"),
+            enable_breakpoint,
+        )
+        .add_command(
+            clap::Command::new("disable")
+                .arg(
+                    clap::Arg::new("number")
+                        .required(true)
+                        .value_parser(clap::value_parser!(usize))
+                        .help("number of the breakpoint, as shown by info breakpoints"),
+                )
+                .about("Disable a breakpoint without deleting it"),
+            disable_breakpoint,
+        )
+        .add_command(
+            clap::Command::new("save-breakpoints")
+                .arg(
+                    clap::Arg::new("file")
+                        .required(true)
+                        .help("path of the file, it's overwritten"),
+                )
+                .about("Save the breakpoints to a file, to load them in another session"),
+            save_breakpoints,
+        )
+        .add_command(
+            clap::Command::new("load-breakpoints")
+                .arg(
+                    clap::Arg::new("file")
+                        .required(true)
+                        .help("path of a file written by save-breakpoints"),
+                )
+                .about("Add the breakpoints saved in a file"),
+            load_breakpoints,
+        )
+        .add_command(
+            clap::Command::new("info")
+                .visible_alias("i")
+                .subcommand_required(true)
+                .subcommand(clap::Command::new("breakpoints").about("List the breakpoints"))
+                .subcommand(
+                    clap::Command::new("registers").about("Show the general purpose registers"),
+                )
+                .subcommand(
+                    clap::Command::new("locals")
+                        .about("Show the local variables of the current function"),
+                )
+                .subcommand(
+                    clap::Command::new("args")
+                        .about("Show the arguments of the current function"),
+                )
+                .subcommand(
+                    clap::Command::new("program")
+                        .about("Show whether the program is running or how it finished"),
+                )
+                .subcommand(
+                    clap::Command::new("inferiors")
+                        .about("List the programs being debugged, * marks the selected one"),
+                )
+                .subcommand(
+                    clap::Command::new("sharedlibrary")
+                        .about("List the shared libraries loaded by the program, with where they are loaded"),
+                )
+                .subcommand(
+                    clap::Command::new("functions")
+                        .arg(
+                            clap::Arg::new("filter")
+                                .help("only show the functions whose name contains it"),
+                        )
+                        .about("List the functions of the binary with their source files"),
+                )
+                .subcommand(
+                    clap::Command::new("line")
+                        .arg(
+                            clap::Arg::new("where")
+                                .required(true)
+                                .help("in the form \"source_file:line_number\", the name of a function or *address"),
+                        )
+                        .about("Show the address of a line, or the line of an address"),
+                )
+                .subcommand(
+                    clap::Command::new("line-table")
+                        .arg(
+                            clap::Arg::new("file")
+                                .required(true)
+                                .value_hint(clap::ValueHint::FilePath)
+                                .help("source file, its path can be cut from the left like in breakpoints"),
+                        )
+                        .about("Show the rows of the line table of a file, with their addresses in the binary and flags"),
+                )
+                .about("Show information about the program being debugged"),
+            show_info,
+        )
+        .add_command(
+            clap::Command::new("stepi")
+                .visible_alias("si")
+                .about("Execute a single machine instruction"),
+            step_instruction,
+        )
+        .add_command(
+            clap::Command::new("next")
+                .visible_alias("n")
+                .about("Execute until the next line, running the calls without stepping into them"),
+            next_line,
+        )
+        .add_command(
+            clap::Command::new("step")
+                .visible_alias("s")
+                .about("Execute until the next line, stepping into the calls"),
+            step_into_line,
+        )
+        .add_command(
+            clap::Command::new("backtrace")
+                .visible_alias("bt")
+                .about("Show the chain of function calls that led to the current one"),
+            show_backtrace,
+        )
+        .add_command(
+            clap::Command::new("frame")
+                .visible_alias("f")
+                .arg(
+                    clap::Arg::new("index")
+                        .required(true)
+                        .value_parser(clap::value_parser!(usize))
+                        .help("number of the frame, as shown by backtrace"),
+                )
+                .about("Select the frame used to look up variables"),
+            select_frame,
+        )
+        .add_command(
+            clap::Command::new("env")
+                .arg(
+                    clap::Arg::new("variable")
+                        .help("in the form \"KEY=VALUE\", lists the variables if missing"),
+                )
+                .about("Set an environment variable for the next runs of the program"),
+            set_environment_variable,
+        )
+        .add_command(
+            clap::Command::new("set")
+                .subcommand_required(true)
+                .subcommand(
+                    clap::Command::new("print")
+                        .subcommand_required(true)
+                        .subcommand(
+                            clap::Command::new("elements")
+                                .arg(
+                                    clap::Arg::new("limit")
+                                        .help("a number, 0 or \"unlimited\" to show all of them, shows the current limit if missing"),
+                                )
+                                .about("Limit how many elements of arrays and characters of strings are shown"),
+                        )
+                        .about("Change how values are shown"),
+                )
+                .about("Change a setting of the session"),
+            change_setting,
+        )
+        .add_command(
+            clap::Command::new("return")
+                .arg(
+                    clap::Arg::new("value")
+                        .allow_negative_numbers(true)
+                        .help("integer returned by the function, hexadecimal with the 0x prefix or decimal"),
+                )
+                .about("Return from the current function without running the rest of it"),
+            return_from_function,
+        )
+        .add_command(
+            clap::Command::new("finish")
+                .about("Run until the current function returns and show the value it returns"),
+            finish_function,
+        )
+        .add_command(
+            clap::Command::new("kill")
+                .about("Terminate the running program, keeping the binary and breakpoints"),
+            kill_program,
+        )
+        .add_command(
+            clap::Command::new("print")
+                .visible_alias("p")
+                .arg(
+                    clap::Arg::new("var")
+                        .required(true)
+                        .help("name of the variable, or of a register like $rax. &var prints its address and arr[i] one of its elements, p[-1] is the one before a pointer"),
+                )
+                .arg(
+                    clap::Arg::new("string")
+                        .long("string")
+                        .short('s')
+                        .action(clap::ArgAction::SetTrue)
+                        .help("print char arrays as strings"),
+                )
+                .arg(
+                    clap::Arg::new("format")
+                        .long("format")
+                        .short('f')
+                        .value_parser(["d", "x", "o", "b"])
+                        .help("radix of integers: decimal, hexadecimal, octal or binary"),
+                )
+                .arg(
+                    clap::Arg::new("depth")
+                        .long("depth")
+                        .short('d')
+                        .value_parser(clap::value_parser!(usize))
+                        .help("levels of pointers to structs that are followed, 1 by default"),
+                )
+                .about("Print the value of a variable, *var prints what a pointer points to"),
+            print_var,
+        );
+    match script {
+        Some(script) => repl.run_script(script),
+        None => repl.run(),
+    }
+}
+
+fn load_program(args: &clap::ArgMatches, context: &mut ProgramContext) -> anyhow::Result<String> {
+    if context.has_binary()
+        && !ask_for_confirmation(
+            "Another binary was already loaded, do you want to load a new one?",
+        )
+    {
+        return Ok(String::from("Kept original binary"));
+    }
+    context.load_binary(args.get_one::<String>("binary_path").unwrap())
+}
+
+fn reload_binary(_: &clap::ArgMatches, context: &mut ProgramContext) -> anyhow::Result<String> {
+    context.reload_binary()
+}
+
+fn add_inferior(_: &clap::ArgMatches, context: &mut ProgramContext) -> anyhow::Result<String> {
+    let number = context.add_inferior();
+    Ok(format!(
+        "Added inferior {}, select it with inferior {}",
+        number, number
+    ))
+}
+
+fn select_inferior(
+    args: &clap::ArgMatches,
+    context: &mut ProgramContext,
+) -> anyhow::Result<String> {
+    let number = *args.get_one::<usize>("number").unwrap();
+    let message = format!("Selected inferior {}", number);
+    Ok(match context.select_inferior(number)? {
+        Some(exit) => format!("{}\n{}", message, exit),
+        None => message,
+    })
+}
+
+fn add_breakpoint(args: &clap::ArgMatches, context: &mut ProgramContext) -> anyhow::Result<Output> {
+    context.add_numbered_breakpoint(args.get_one::<String>("where").unwrap(), false)
+}
+
+fn add_temporary_breakpoint(
+    args: &clap::ArgMatches,
+    context: &mut ProgramContext,
+) -> anyhow::Result<Output> {
+    context.add_numbered_breakpoint(args.get_one::<String>("where").unwrap(), true)
+}
+
+fn run_program(args: &clap::ArgMatches, context: &mut ProgramContext) -> anyhow::Result<String> {
+    if !context.has_binary() {
+        anyhow::bail!("You need to load a binary first");
+    }
+    if context.is_running()
+        && !ask_for_confirmation("A program is already being run, do you want to rerun it?")
+    {
+        return Ok("The original program is still running".to_owned());
+    }
+    let program_args = args
+        .get_many::<String>("program_args")
+        .map(|args| args.cloned().collect())
+        .unwrap_or_default();
+    if args.get_flag("stop-at-entry") {
+        context.run_to_entry_point(program_args)
+    } else {
+        context.run(program_args)
+    }
+}
+
+fn rerun_program(_: &clap::ArgMatches, context: &mut ProgramContext) -> anyhow::Result<String> {
+    context.rerun()
+}
+
+fn continue_program(
+    args: &clap::ArgMatches,
+    context: &mut ProgramContext,
+) -> anyhow::Result<String> {
+    if args.get_flag("discard-signal") {
+        context.continue_discarding_signal()
+    } else {
+        context.continue_execution()
+    }
+}
+
+fn run_until(args: &clap::ArgMatches, context: &mut ProgramContext) -> anyhow::Result<String> {
+    context.run_until(args.get_one::<String>("where").unwrap())
+}
+
+fn add_watchpoint(args: &clap::ArgMatches, context: &mut ProgramContext) -> anyhow::Result<String> {
+    context.watch(args.get_one::<String>("var").unwrap())
+}
+
+fn enable_breakpoint(
+    args: &clap::ArgMatches,
+    context: &mut ProgramContext,
+) -> anyhow::Result<String> {
+    context.set_breakpoint_enabled(*args.get_one::<usize>("number").unwrap(), true)
+}
+
+fn disable_breakpoint(
+    args: &clap::ArgMatches,
+    context: &mut ProgramContext,
+) -> anyhow::Result<String> {
+    context.set_breakpoint_enabled(*args.get_one::<usize>("number").unwrap(), false)
+}
+
+fn save_breakpoints(
+    args: &clap::ArgMatches,
+    context: &mut ProgramContext,
+) -> anyhow::Result<String> {
+    context.save_breakpoints(args.get_one::<String>("file").unwrap())
+}
+
+fn load_breakpoints(
+    args: &clap::ArgMatches,
+    context: &mut ProgramContext,
+) -> anyhow::Result<String> {
+    context.load_breakpoints(args.get_one::<String>("file").unwrap())
+}
+
+fn show_info(args: &clap::ArgMatches, context: &mut ProgramContext) -> anyhow::Result<String> {
+    match args.subcommand() {
+        Some(("breakpoints", _)) => Ok(context.show_breakpoints()),
+        Some(("registers", _)) => context.show_registers(),
+        Some(("locals", _)) => context.show_locals(),
+        Some(("args", _)) => context.show_args(),
+        Some(("program", _)) => Ok(context.show_program()),
+        Some(("line", args)) => context.show_line(args.get_one::<String>("where").unwrap()),
+        Some(("functions", args)) => {
+            context.show_functions(args.get_one::<String>("filter").map(String::as_str))
+        }
+        Some(("inferiors", _)) => Ok(context.show_inferiors()),
+        Some(("sharedlibrary", _)) => context.show_shared_libraries(),
+        Some(("line-table", args)) => {
+            context.show_line_table(args.get_one::<String>("file").unwrap())
+        }
+        _ => anyhow::bail!("Unknown info command"),
+    }
+}
+
+fn step_instruction(_: &clap::ArgMatches, context: &mut ProgramContext) -> anyhow::Result<String> {
+    context.step_instruction()
+}
+
+fn next_line(_: &clap::ArgMatches, context: &mut ProgramContext) -> anyhow::Result<String> {
+    context.step_over()
+}
+
+fn step_into_line(_: &clap::ArgMatches, context: &mut ProgramContext) -> anyhow::Result<String> {
+    context.step_into()
+}
+
+fn show_backtrace(_: &clap::ArgMatches, context: &mut ProgramContext) -> anyhow::Result<Output> {
+    context.backtrace()
+}
+
+fn select_frame(args: &clap::ArgMatches, context: &mut ProgramContext) -> anyhow::Result<Output> {
+    context.select_frame(*args.get_one::<usize>("index").unwrap())
+}
+
+fn set_environment_variable(
+    args: &clap::ArgMatches,
+    context: &mut ProgramContext,
+) -> anyhow::Result<String> {
+    match args.get_one::<String>("variable") {
+        Some(variable) => context.set_environment_variable(variable),
+        None => Ok(context.show_environment()),
+    }
+}
+
+fn change_setting(args: &clap::ArgMatches, context: &mut ProgramContext) -> anyhow::Result<String> {
+    let Some(("print", args)) = args.subcommand() else {
+        anyhow::bail!("Unknown setting");
+    };
+    let Some(("elements", args)) = args.subcommand() else {
+        anyhow::bail!("Unknown print setting");
+    };
+    context.set_print_elements(args.get_one::<String>("limit").map(String::as_str))
+}
+
+fn return_from_function(
+    args: &clap::ArgMatches,
+    context: &mut ProgramContext,
+) -> anyhow::Result<String> {
+    context.return_from_function(args.get_one::<String>("value").map(String::as_str))
+}
+
+fn finish_function(_: &clap::ArgMatches, context: &mut ProgramContext) -> anyhow::Result<String> {
+    context.finish()
+}
+
+fn kill_program(_: &clap::ArgMatches, context: &mut ProgramContext) -> anyhow::Result<String> {
+    context.kill()
+}
+
+fn print_var(args: &clap::ArgMatches, context: &mut ProgramContext) -> anyhow::Result<Output> {
+    let variable_name = args.get_one::<String>("var").unwrap();
+    let print_options = context.print_options();
+    let options = PrintOptions {
+        as_string: args.get_flag("string"),
+        radix: match args.get_one::<String>("format").map(String::as_str) {
+            Some("x") => Radix::Hexadecimal,
+            Some("o") => Radix::Octal,
+            Some("b") => Radix::Binary,
+            _ => Radix::Decimal,
+        },
+        max_depth: args
+            .get_one::<usize>("depth")
+            .copied()
+            .unwrap_or(print_options.max_depth),
+        ..print_options
+    };
+    context.print_with(variable_name, options)
+}
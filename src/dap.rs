@@ -0,0 +1,436 @@
+// A minimal Debug Adapter Protocol server, so editors can use the debugger.
+// The requests are mapped onto the same operations used by the commands
+// of the REPL. Only one thread is shown in the stack traces, the one that
+// stopped
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, Write},
+};
+
+use anyhow::{Context, anyhow};
+use nix::sys::wait::WaitStatus;
+
+use crate::{
+    BreakpointLocation, ProgramContext,
+    binary::{find_frame_debug_info, load_binary},
+    breakpoints::{add_user_breakpoint, change_breakpoint_enabled},
+    execution::{continue_execution, start_program, step_line, terminate},
+    frames::{format_frame, get_frames, get_selected_frame},
+    output::{self, Json, Output},
+    print::format_variable,
+};
+
+struct Server {
+    seq: i64,
+    // The breakpoints set for each source, they are disabled when the
+    // editor sends the new ones
+    source_breakpoints: HashMap<String, Vec<usize>>,
+}
+
+pub fn run(context: &mut ProgramContext) -> anyhow::Result<()> {
+    let mut server = Server {
+        seq: 1,
+        source_breakpoints: HashMap::new(),
+    };
+    let mut input = io::stdin().lock();
+    while let Some(request) = read_message(&mut input)? {
+        let command = request
+            .get("command")
+            .and_then(Json::as_str)
+            .unwrap_or_default()
+            .to_owned();
+        let arguments = request.get("arguments").unwrap_or(&Json::Null);
+        let result = server.handle_request(&command, arguments, context);
+        let succeeded = result.is_ok();
+        server.respond(&request, &command, result)?;
+        match command.as_str() {
+            "initialize" => server.send_event("initialized", Json::object(Vec::new()))?,
+            "configurationDone" | "continue" | "next" | "stepIn" if succeeded => {
+                server.report_stop(context, &command)?
+            }
+            // The program couldn't start, so the session is over
+            "configurationDone" => {
+                server.send_output(output::take_events())?;
+                server.send_event("terminated", Json::object(Vec::new()))?
+            }
+            "disconnect" => return Ok(()),
+            _ => server.send_output(output::take_events())?,
+        }
+    }
+    Ok(())
+}
+
+// Messages have a header with their length, like in HTTP
+fn read_message(input: &mut impl BufRead) -> anyhow::Result<Option<Json>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = Some(value.trim().parse::<usize>()?);
+        }
+    }
+    let mut body = vec![0; length.ok_or(anyhow!("Missing the Content-Length header"))?];
+    input.read_exact(&mut body)?;
+    Json::parse(&String::from_utf8(body)?).map(Some)
+}
+
+impl Server {
+    fn send(&mut self, kind: &str, mut fields: Vec<(&str, Json)>) -> anyhow::Result<()> {
+        fields.insert(0, ("seq", Json::from(self.seq)));
+        fields.insert(1, ("type", Json::from(kind)));
+        self.seq += 1;
+        let body = Json::object(fields).to_string();
+        let mut stdout = io::stdout().lock();
+        write!(stdout, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+        stdout.flush()?;
+        Ok(())
+    }
+
+    fn send_event(&mut self, event: &str, body: Json) -> anyhow::Result<()> {
+        self.send("event", vec![("event", Json::from(event)), ("body", body)])
+    }
+
+    fn respond(
+        &mut self,
+        request: &Json,
+        command: &str,
+        result: anyhow::Result<Json>,
+    ) -> anyhow::Result<()> {
+        let request_seq = request.get("seq").and_then(Json::as_i64).unwrap_or(0);
+        let mut fields = vec![
+            ("request_seq", Json::from(request_seq)),
+            ("command", Json::from(command)),
+            ("success", Json::from(result.is_ok())),
+        ];
+        match result {
+            Ok(body) => fields.push(("body", body)),
+            Err(err) => fields.push(("message", Json::from(err.to_string()))),
+        }
+        self.send("response", fields)
+    }
+
+    // The messages of the commands are shown in the debug console. The
+    // editor already shows where the program stopped
+    fn send_output(&mut self, events: Vec<(&'static str, Output)>) -> anyhow::Result<()> {
+        for (name, event) in events {
+            if name == "location" || event.text.is_empty() {
+                continue;
+            }
+            self.send_event(
+                "output",
+                Json::object(vec![
+                    ("category", Json::from("console")),
+                    ("output", Json::from(event.text + "\n")),
+                ]),
+            )?;
+        }
+        Ok(())
+    }
+
+    fn report_stop(&mut self, context: &ProgramContext, command: &str) -> anyhow::Result<()> {
+        let events = output::take_events();
+        let Some(running_program) = &context.running_program else {
+            self.send_output(events)?;
+            let exit_code = match context.last_exit {
+                Some(WaitStatus::Exited(_, code)) => code as i64,
+                _ => 1,
+            };
+            self.send_event(
+                "exited",
+                Json::object(vec![("exitCode", Json::from(exit_code))]),
+            )?;
+            return self.send_event("terminated", Json::object(Vec::new()));
+        };
+        let mut reason = if command == "next" || command == "stepIn" {
+            "step"
+        } else {
+            "pause"
+        };
+        let mut hit_breakpoints = Vec::new();
+        for (name, event) in &events {
+            match *name {
+                "signal" => reason = "exception",
+                "watchpoint" => reason = "data breakpoint",
+                // The breakpoint set by until isn't one of the user
+                "stop" => {
+                    if let Some((_, Json::Array(breakpoints))) =
+                        event.fields.iter().find(|(key, _)| *key == "breakpoints")
+                        && !breakpoints.is_empty()
+                    {
+                        reason = "breakpoint";
+                        hit_breakpoints.extend(breakpoints.iter().filter_map(Json::as_i64));
+                    }
+                }
+                _ => {}
+            }
+        }
+        self.send_output(events)?;
+        self.send_event(
+            "stopped",
+            Json::object(vec![
+                ("reason", Json::from(reason)),
+                (
+                    "threadId",
+                    Json::from(running_program.thread.as_raw() as i64),
+                ),
+                ("allThreadsStopped", Json::from(true)),
+                ("hitBreakpointIds", Json::from(hit_breakpoints)),
+            ]),
+        )
+    }
+
+    fn handle_request(
+        &mut self,
+        command: &str,
+        arguments: &Json,
+        context: &mut ProgramContext,
+    ) -> anyhow::Result<Json> {
+        match command {
+            "initialize" => Ok(Json::object(vec![(
+                "supportsConfigurationDoneRequest",
+                Json::from(true),
+            )])),
+            "launch" => launch(arguments, context),
+            "setBreakpoints" => self.set_breakpoints(arguments, context),
+            // The program starts once the editor has sent the breakpoints
+            "configurationDone" => {
+                start_program(context, false)?;
+                Ok(Json::object(Vec::new()))
+            }
+            "continue" => {
+                if context.running_program.is_none() {
+                    anyhow::bail!("You need to run a program first");
+                }
+                continue_execution(context, None)?;
+                Ok(Json::object(vec![(
+                    "allThreadsContinued",
+                    Json::from(true),
+                )]))
+            }
+            "next" | "stepIn" => {
+                if context.running_program.is_none() {
+                    anyhow::bail!("You need to run a program first");
+                }
+                step_line(context, command == "next")?;
+                Ok(Json::object(Vec::new()))
+            }
+            "threads" => Ok(threads(context)),
+            "stackTrace" => stack_trace(context),
+            "scopes" => scopes(arguments),
+            "variables" => variables(arguments, context),
+            "disconnect" => {
+                if let Some(running_program) = context.running_program.take() {
+                    terminate(&running_program, &mut context.queued_events)?;
+                }
+                Ok(Json::object(Vec::new()))
+            }
+            _ => anyhow::bail!("Unsupported request {}", command),
+        }
+    }
+
+    fn set_breakpoints(
+        &mut self,
+        arguments: &Json,
+        context: &mut ProgramContext,
+    ) -> anyhow::Result<Json> {
+        let path = arguments
+            .get("source")
+            .and_then(|source| source.get("path"))
+            .and_then(Json::as_str)
+            .ok_or(anyhow!("Missing the path of the source"))?;
+        for number in self.source_breakpoints.remove(path).unwrap_or_default() {
+            change_breakpoint_enabled(context, number, false)?;
+        }
+        let mut numbers = Vec::new();
+        let mut breakpoints = Vec::new();
+        for line in arguments
+            .get("breakpoints")
+            .and_then(Json::as_array)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|breakpoint| breakpoint.get("line").and_then(Json::as_i64))
+        {
+            let breakpoint_count = context.breakpoints.len();
+            let result = add_user_breakpoint(context, &format!("{}:{}", path, line), false);
+            let mut fields = vec![];
+            match result {
+                Ok(_) if context.breakpoints.len() > breakpoint_count => {
+                    let breakpoint = context.breakpoints.last().unwrap();
+                    numbers.push(breakpoint.number);
+                    fields.push(("id", Json::from(breakpoint.number)));
+                    fields.push(("verified", Json::from(true)));
+                    // Lines without code are moved to the next one
+                    let line = match &breakpoint.location {
+                        BreakpointLocation::Line(breakpoint)
+                        | BreakpointLocation::LibraryLine {
+                            line: breakpoint, ..
+                        } => breakpoint.line_number as i64,
+                        BreakpointLocation::Address(_) => line,
+                    };
+                    fields.push(("line", Json::from(line)));
+                }
+                Ok(message) => {
+                    fields.push(("verified", Json::from(false)));
+                    fields.push(("message", Json::from(message)));
+                }
+                Err(err) => {
+                    fields.push(("verified", Json::from(false)));
+                    fields.push(("message", Json::from(err.to_string())));
+                }
+            }
+            breakpoints.push(Json::object(fields));
+        }
+        self.source_breakpoints.insert(path.to_owned(), numbers);
+        Ok(Json::object(vec![(
+            "breakpoints",
+            Json::Array(breakpoints),
+        )]))
+    }
+}
+
+fn launch(arguments: &Json, context: &mut ProgramContext) -> anyhow::Result<Json> {
+    let program = arguments
+        .get("program")
+        .and_then(Json::as_str)
+        .ok_or(anyhow!("Missing the program to launch"))?;
+    load_binary(context, program)?;
+    // The standard streams of the debugger carry the protocol. The output
+    // of the program goes to the standard error instead, which editors
+    // show, unless the arguments redirect it
+    context.program_args = vec![String::from("</dev/null"), String::from(">/dev/stderr")];
+    context.program_args.extend(
+        arguments
+            .get("args")
+            .and_then(Json::as_array)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|arg| arg.as_str().map(String::from)),
+    );
+    Ok(Json::object(Vec::new()))
+}
+
+fn threads(context: &ProgramContext) -> Json {
+    let threads = context
+        .running_program
+        .iter()
+        .flat_map(|running_program| &running_program.threads)
+        .map(|thread| {
+            Json::object(vec![
+                ("id", Json::from(thread.as_raw() as i64)),
+                ("name", Json::from(format!("Thread {}", thread))),
+            ])
+        })
+        .collect::<Vec<_>>();
+    Json::object(vec![("threads", Json::Array(threads))])
+}
+
+fn stack_trace(context: &ProgramContext) -> anyhow::Result<Json> {
+    let running_program = context
+        .running_program
+        .as_ref()
+        .ok_or(anyhow!("You need to run a program first"))?;
+    let binary = context.binary.as_ref().unwrap(); // If there's a pid, there's a binary
+    let frames = get_frames(running_program, binary)?
+        .iter()
+        .enumerate()
+        .map(|(index, frame)| {
+            let frame = Json::object(format_frame(running_program, binary, index, frame).fields);
+            let mut fields = vec![
+                ("id", Json::from(index)),
+                (
+                    "name",
+                    Json::from(frame.get("function").and_then(Json::as_str).unwrap()),
+                ),
+                (
+                    "line",
+                    Json::from(frame.get("line").and_then(Json::as_i64).unwrap_or(0)),
+                ),
+                ("column", Json::Number(0)),
+            ];
+            if let Some(path) = frame.get("file").and_then(Json::as_str) {
+                fields.push(("source", Json::object(vec![("path", Json::from(path))])));
+            }
+            Json::object(fields)
+        })
+        .collect::<Vec<_>>();
+    Ok(Json::object(vec![
+        ("totalFrames", Json::from(frames.len())),
+        ("stackFrames", Json::Array(frames)),
+    ]))
+}
+
+// Each frame has two scopes, their references are 2 * frame + 1 for the
+// locals and 2 * frame + 2 for the arguments
+fn scopes(arguments: &Json) -> anyhow::Result<Json> {
+    let frame = arguments
+        .get("frameId")
+        .and_then(Json::as_i64)
+        .context("Missing the frame")?;
+    let scope = |name: &str, reference: i64| {
+        Json::object(vec![
+            ("name", Json::from(name)),
+            ("variablesReference", Json::from(reference)),
+            ("expensive", Json::from(false)),
+        ])
+    };
+    Ok(Json::object(vec![(
+        "scopes",
+        Json::Array(vec![
+            scope("Locals", frame * 2 + 1),
+            scope("Arguments", frame * 2 + 2),
+        ]),
+    )]))
+}
+
+fn variables(arguments: &Json, context: &mut ProgramContext) -> anyhow::Result<Json> {
+    let reference = arguments
+        .get("variablesReference")
+        .and_then(Json::as_i64)
+        .filter(|reference| *reference > 0)
+        .context("Missing the variables reference")?;
+    let running_program = context
+        .running_program
+        .as_mut()
+        .ok_or(anyhow!("You need to run a program first"))?;
+    let binary = context.binary.as_ref().unwrap(); // If there's a pid, there's a binary
+    let selected_frame = running_program.selected_frame;
+    running_program.selected_frame = (reference as usize - 1) / 2;
+    let frame = get_selected_frame(running_program, binary);
+    running_program.selected_frame = selected_frame;
+    let frame = frame?;
+    let pid = running_program.thread;
+    let (dwarf, load_bias) = find_frame_debug_info(running_program, binary, &frame);
+    let locals = dwarf.get_local_variables(
+        &frame,
+        load_bias,
+        reference % 2 == 0,
+        context.print_options.max_depth,
+    )?;
+    let variables = locals
+        .into_iter()
+        .map(|(name, variable)| {
+            let (value, type_name) = match variable {
+                Ok(variable) => (
+                    format_variable(pid, &variable, context.print_options)
+                        .unwrap_or_else(|err| format!("<{}>", err)),
+                    variable.type_name,
+                ),
+                Err(err) => (format!("<{}>", err), String::new()),
+            };
+            Json::object(vec![
+                ("name", Json::from(name)),
+                ("value", Json::from(value)),
+                ("type", Json::from(type_name)),
+                ("variablesReference", Json::Number(0)),
+            ])
+        })
+        .collect::<Vec<_>>();
+    Ok(Json::object(vec![("variables", Json::Array(variables))]))
+}
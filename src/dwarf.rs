@@ -1,293 +1,1523 @@
-use anyhow::{anyhow, bail};
-use gimli::{AttributeValue, DwAte, LittleEndian, Location, Reader};
-use nix::{sys::ptrace::getregs, unistd::Pid};
-use object::{Object, ObjectSection};
-use std::{collections::HashMap, path::PathBuf, rc::Rc};
+use anyhow::{Context, anyhow, bail};
+use gimli::{AttributeValue, DwAte, Location, Reader, RunTimeEndian, UnwindSection};
+use object::{Object, ObjectSection, ObjectSegment};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fs,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
 
-use crate::{Breakpoint, registers::get_register_value};
+use crate::{
+    Breakpoint,
+    print::low_bits_mask,
+    registers::{Registers, get_register_value},
+};
+
+type DwarfReader = gimli::EndianReader<RunTimeEndian, Rc<[u8]>>;
 
 pub struct DwarfInfo {
-    inner: gimli::Dwarf<gimli::EndianReader<LittleEndian, Rc<[u8]>>>,
+    inner: gimli::Dwarf<DwarfReader>,
+    units: Vec<gimli::Unit<DwarfReader, usize>>,
+    // The following indexes are built once when loading the binary, so the
+    // DWARF doesn't need to be walked again for every command
+    breakpoints: HashMap<Breakpoint, u64>,
+    // Each address maps to the line of the instructions starting there, None
+    // marks where a sequence of instructions ends
+    lines: BTreeMap<u64, Option<LinePosition>>,
+    // Addresses where a statement starts, the rows of the line table without
+    // is_stmt are in the middle of one
+    statements: BTreeSet<u64>,
+    variables: HashMap<String, Vec<VariableEntry>>,
+    functions: Vec<FunctionEntry>,
+    // Nested calls come after the ones containing them
+    inlined_calls: Vec<InlinedCallEntry>,
+    // Executable segments of the ELF, needed to know which addresses
+    // correspond to the code mapped into memory
+    code_segments: Vec<Segment>,
+    // The call frame information, the frame bases of gcc are computed from
+    // the canonical frame address it describes
+    debug_frame: gimli::DebugFrame<DwarfReader>,
+    eh_frame: gimli::EhFrame<DwarfReader>,
+    frame_section_bases: gimli::BaseAddresses,
+    architecture: object::Architecture,
+    entry_point: u64,
+}
+
+struct Segment {
+    file_offset: u64,
+    file_size: u64,
+    address: u64,
+}
+
+// Points to the DIE of a variable
+struct VariableEntry {
+    unit_index: usize,
+    offset: gimli::UnitOffset,
+    // The subprogram the variable belongs to, None for globals
+    function: Option<gimli::UnitOffset>,
+    // The innermost lexical block with code containing the variable, None if
+    // it's visible in the whole function
+    block: Option<gimli::UnitOffset>,
+    is_parameter: bool,
 }
 
+enum Scope {
+    Function(gimli::UnitOffset),
+    Block(gimli::UnitOffset),
+}
+
+struct FunctionEntry {
+    name: String,
+    ranges: Vec<gimli::Range>,
+    unit_index: usize,
+    offset: gimli::UnitOffset,
+}
+
+struct InlinedCallEntry {
+    call: InlinedCall,
+    ranges: Vec<gimli::Range>,
+}
+
+// A function whose code was copied into its caller, so it has no frame
+pub struct InlinedCall {
+    pub function: String,
+    // Where it's called from in the caller, None if the compiler didn't
+    // record it
+    pub call_position: Option<LinePosition>,
+}
+
+// A row of the line table, as in the DWARF
+pub struct LineTableRow {
+    pub address: u64,
+    pub line_number: u64,
+    pub is_stmt: bool,
+    pub prologue_end: bool,
+}
+
+#[derive(Clone)]
 pub struct LinePosition {
     pub path: PathBuf,
     pub line_number: usize,
 }
 
 pub struct VariableInfo {
-    pub address: u64,
-    pub base_type: BaseType,
-    pub size: u64,
+    pub location: VariableLocation,
+    pub type_info: TypeInfo,
+    // As written in the source, like "const char *"
+    pub type_name: String,
+}
+
+pub enum VariableLocation {
+    Address(u64),
+    // Variables stored in registers have no address, their value is read
+    // when looking them up
+    Value(u64),
+    // The compiler removed the variable, or it has no location at the
+    // current address
+    OptimizedOut,
+}
+
+pub enum TypeInfo {
+    Base {
+        base_type: BaseType,
+        // In bits
+        size: u64,
+    },
+    Array {
+        element_type: Box<TypeInfo>,
+        // None when the length isn't known, like for flexible array members
+        count: Option<u64>,
+    },
+    Pointer {
+        // None for void pointers or pointers to unsupported types
+        pointee_type: Option<Box<TypeInfo>>,
+        // Pointers to structs are only followed up to a depth, structs can
+        // point to themselves, like the nodes of a linked list
+        cut_off: bool,
+    },
+    Enum {
+        // Type of the underlying integer
+        base_type: BaseType,
+        // In bits
+        size: u64,
+        // Name and value of each variant
+        enumerators: Vec<(String, u64)>,
+    },
+    Struct {
+        // In bits
+        size: u64,
+        members: Vec<Member>,
+    },
+    // The members overlap, all of them start at offset 0
+    Union {
+        // In bits
+        size: u64,
+        members: Vec<Member>,
+    },
+    // UTF-8 text that isn't null-terminated, stored as a pointer to the
+    // bytes and their length, like the &str of Rust
+    Str {
+        // In bits
+        size: u64,
+        // In bits, from the start of the value
+        data_offset: u64,
+        length_offset: u64,
+    },
+}
+
+pub struct Member {
+    pub name: String,
+    pub type_info: TypeInfo,
+    pub type_name: String,
+    // In bits, from the start of the struct
+    pub offset: u64,
+    // Only for bitfields, which take fewer bits than their type
+    pub bit_size: Option<u64>,
 }
 
 pub enum BaseType {
     Boolean,
+    Char,
     Float,
     Signed,
     Unsigned,
 }
 
+impl TypeInfo {
+    // In bits
+    pub fn size(&self) -> u64 {
+        match self {
+            TypeInfo::Base { size, .. } => *size,
+            TypeInfo::Array {
+                element_type,
+                count,
+            } => element_type.size().saturating_mul(count.unwrap_or(0)),
+            // Only 64 bit targets are supported
+            TypeInfo::Pointer { .. } => 64,
+            TypeInfo::Enum { size, .. } => *size,
+            TypeInfo::Struct { size, .. } => *size,
+            TypeInfo::Union { size, .. } => *size,
+            TypeInfo::Str { size, .. } => *size,
+        }
+    }
+}
+
 impl DwarfInfo {
-    pub fn new(buffer: Vec<u8>) -> Self {
-        let obj_file = object::File::parse(buffer.as_slice()).expect("Failed to parse ELF file");
-
-        let dwarf = gimli::Dwarf::load(
-            |name| -> Result<gimli::EndianReader<LittleEndian, Rc<[u8]>>, ()> {
-                let section = obj_file
-                    .section_by_name(name.name())
-                    .and_then(|section| section.data().ok())
-                    .map(|data| gimli::EndianReader::new(data, LittleEndian))
-                    .unwrap_or(gimli::EndianReader::new(&[], LittleEndian))
-                    .to_vec();
-
-                Ok(gimli::EndianReader::new(Rc::from(section), LittleEndian))
-            },
-        )
-        .expect("Failed to load DWARF data");
+    // The path is needed to find the separate debug file of stripped
+    // binaries
+    pub fn new(buffer: Vec<u8>, path: &Path) -> anyhow::Result<Self> {
+        let obj_file = object::File::parse(buffer.as_slice()).context("Not a valid ELF file")?;
+        let endian = if obj_file.is_little_endian() {
+            RunTimeEndian::Little
+        } else {
+            RunTimeEndian::Big
+        };
 
-        DwarfInfo { inner: dwarf }
-    }
+        // The segments still come from the binary, in the debug file they
+        // don't have the code
+        let debug_buffer = if obj_file.section_by_name(".debug_info").is_none() {
+            find_debug_file(&obj_file, path)
+        } else {
+            None
+        };
+        let debug_file = debug_buffer
+            .as_deref()
+            .map(object::File::parse)
+            .transpose()
+            .context("The separate debug file isn't a valid ELF file")?;
+        let sections_file = debug_file.as_ref().unwrap_or(&obj_file);
 
-    pub fn get_breakpoints_from_dwarf(&self) -> Result<HashMap<Breakpoint, u64>, anyhow::Error> {
-        let mut breakpoints = HashMap::new();
-        let mut units = self.inner.units();
+        let dwarf = gimli::Dwarf::load(|name| -> gimli::Result<DwarfReader> {
+            Ok(load_section(sections_file, name.name(), endian))
+        })
+        .context("Failed to load the DWARF data")?;
 
-        while let Some(header) = units.next()? {
-            let unit = self.inner.unit(header.clone())?;
-            let comp_dir = unit.comp_dir.clone();
-            let comp_name = unit.name.clone();
-            let mut entries = unit.entries();
+        // .eh_frame is loaded into memory, so it stays in the binary
+        let mut debug_frame =
+            gimli::DebugFrame::from(load_section(sections_file, ".debug_frame", endian));
+        debug_frame.set_address_size(if obj_file.is_64() { 8 } else { 4 });
+        let eh_frame = gimli::EhFrame::from(load_section(&obj_file, ".eh_frame", endian));
+        let section_address = |name| {
+            obj_file
+                .section_by_name(name)
+                .map_or(0, |section| section.address())
+        };
+        let frame_section_bases = gimli::BaseAddresses::default()
+            .set_eh_frame(section_address(".eh_frame"))
+            .set_text(section_address(".text"));
 
-            while let Some((_, entry)) = entries.next_dfs()? {
-                if entry.tag() != gimli::constants::DW_TAG_compile_unit {
-                    continue;
+        let code_segments = obj_file
+            .segments()
+            .filter(|segment| match segment.flags() {
+                object::SegmentFlags::Elf { p_flags } => p_flags & object::elf::PF_X != 0,
+                _ => false,
+            })
+            .map(|segment| {
+                let (file_offset, file_size) = segment.file_range();
+                Segment {
+                    file_offset,
+                    file_size,
+                    address: segment.address(),
                 }
+            })
+            .collect();
 
-                let offset = match get_line_program_offset(entry) {
-                    Some(offset) => offset,
-                    None => continue,
-                };
+        let mut units = Vec::new();
+        let mut headers = dwarf.units();
+        while let Some(header) = headers.next().context("Failed to read the DWARF units")? {
+            let offset = header.offset();
+            units.push(dwarf.unit(header).with_context(|| {
+                format!(
+                    "Failed to read the DWARF unit at {}",
+                    describe_offset(offset)
+                )
+            })?);
+        }
+
+        let mut dwarf_info = DwarfInfo {
+            inner: dwarf,
+            units,
+            breakpoints: HashMap::new(),
+            lines: BTreeMap::new(),
+            statements: BTreeSet::new(),
+            variables: HashMap::new(),
+            functions: Vec::new(),
+            inlined_calls: Vec::new(),
+            code_segments,
+            debug_frame,
+            eh_frame,
+            frame_section_bases,
+            architecture: obj_file.architecture(),
+            entry_point: obj_file.entry(),
+        };
+        let prologue_ends = dwarf_info
+            .build_line_indexes()
+            .context("Failed to read the DWARF line programs")?;
+        dwarf_info
+            .build_entry_indexes()
+            .context("Failed to read the DWARF entries")?;
+        dwarf_info.move_breakpoints_after_prologues(&prologue_ends);
+        Ok(dwarf_info)
+    }
 
-                let line_program = self.inner.debug_line.program(
-                    offset,
-                    header.address_size(),
-                    comp_dir.clone(),
-                    comp_name.clone(),
-                )?;
+    // Returns the addresses where the prologue of a function ends
+    fn build_line_indexes(&mut self) -> anyhow::Result<BTreeSet<u64>> {
+        let mut prologue_ends = BTreeSet::new();
+        for unit in &self.units {
+            let Some(line_program) = unit.line_program.clone() else {
+                continue;
+            };
+            let context = || {
+                format!(
+                    "Failed to read the line program of the unit at {}",
+                    describe_offset(unit.header.offset())
+                )
+            };
+            let (program, sequences) = line_program.sequences().with_context(context)?;
 
-                let (program, sequences) = line_program.sequences()?;
+            for sequence in sequences {
+                self.breakpoints.extend(
+                    process_sequence(&self.inner, unit, &program, &sequence)
+                        .with_context(context)?,
+                );
 
-                for sequence in sequences {
-                    breakpoints.extend(process_sequence(&program, &sequence)?);
+                let mut rows = program.resume_from(&sequence);
+                while let Some((_, row)) = rows.next_row().with_context(context)? {
+                    if row.prologue_end() {
+                        prologue_ends.insert(row.address());
+                    }
+                    if row.is_stmt() && !row.end_sequence() {
+                        self.statements.insert(row.address());
+                    }
+                    let line_position = self.lines.entry(row.address()).or_insert(None);
+                    if row.end_sequence() || line_position.is_some() {
+                        continue;
+                    }
+
+                    let path =
+                        match extract_path(&self.inner, unit, program.header(), row.file_index()) {
+                            Some(p) => p,
+                            None => continue,
+                        };
+
+                    if let Some(line) = row.line() {
+                        *line_position = Some(LinePosition {
+                            path,
+                            line_number: line.get() as usize,
+                        });
+                    }
                 }
             }
         }
+        Ok(prologue_ends)
+    }
 
-        Ok(breakpoints)
+    // The arguments of a function aren't stored in their locations until the
+    // prologue is executed, so breakpoints at the first lines of a function are
+    // moved to its end. rustc and clang mark the prologue end in the line
+    // table, for compilers that don't, like gcc, the end is taken to be the
+    // first row of the second line of the function
+    fn move_breakpoints_after_prologues(&mut self, prologue_ends: &BTreeSet<u64>) {
+        for function in &self.functions {
+            let Some(begin) = function.ranges.iter().map(|range| range.begin).min() else {
+                continue;
+            };
+            let marked_end = prologue_ends
+                .range(begin..)
+                .next()
+                .copied()
+                .filter(|&address| function.ranges.iter().any(|range| address < range.end));
+            // gcc doesn't mark where the prologue ends, like gdb the breakpoint
+            // goes to the first row of another line then
+            let Some(prologue_end) =
+                marked_end.or_else(|| self.find_second_line_of_function(function, begin))
+            else {
+                continue;
+            };
+            for line_position in self.lines.range(begin..prologue_end).flat_map(|(_, l)| l) {
+                let breakpoint = Breakpoint {
+                    file: line_position.path.clone(),
+                    line_number: line_position.line_number as u64,
+                };
+                if let Some(address) = self.breakpoints.get_mut(&breakpoint)
+                    && (begin..prologue_end).contains(address)
+                {
+                    *address = prologue_end;
+                }
+            }
+        }
     }
 
-    pub fn get_line_from_address(&self, address: u64) -> anyhow::Result<LinePosition> {
-        let mut units = self.inner.units();
+    fn find_second_line_of_function(&self, function: &FunctionEntry, begin: u64) -> Option<u64> {
+        let first_line = self.lines.get(&begin)?.as_ref()?;
+        self.lines
+            .range(begin + 1..)
+            .take_while(|&(&address, _)| {
+                function
+                    .ranges
+                    .iter()
+                    .any(|range| range.begin <= address && address < range.end)
+            })
+            .find_map(|(&address, line_position)| {
+                line_position
+                    .as_ref()
+                    .filter(|line_position| {
+                        line_position.line_number != first_line.line_number
+                            || line_position.path != first_line.path
+                    })
+                    .map(|_| address)
+            })
+    }
 
-        while let Some(header) = units.next()? {
-            let unit = self.inner.unit(header.clone())?;
-            let comp_dir = unit.comp_dir.clone();
-            let comp_name = unit.name.clone();
+    fn build_entry_indexes(&mut self) -> anyhow::Result<()> {
+        let mut variables: HashMap<String, Vec<VariableEntry>> = HashMap::new();
+        let mut functions = Vec::new();
+        let mut inlined_calls = Vec::new();
+        for (unit_index, unit) in self.units.iter().enumerate() {
             let mut entries = unit.entries();
+            let mut depth = 0;
+            // The entries enclosing the current one that matter for its
+            // variables, with their depth. The functions provide the frame
+            // base, and the blocks with code restrict where they are visible
+            let mut scopes: Vec<(isize, Scope)> = Vec::new();
 
-            while let Some((_, entry)) = entries.next_dfs()? {
-                if entry.tag() != gimli::constants::DW_TAG_compile_unit {
+            while let Some((depth_delta, entry)) = entries.next_dfs().with_context(|| {
+                format!(
+                    "Failed to read the entries of the unit at {}",
+                    describe_offset(unit.header.offset())
+                )
+            })? {
+                depth += depth_delta;
+                scopes.retain(|(d, _)| *d < depth);
+                let context = || {
+                    format!(
+                        "Failed to read the DWARF entry at {}",
+                        describe_offset(entry.offset().to_unit_section_offset(unit))
+                    )
+                };
+                if entry.tag() == gimli::constants::DW_TAG_subprogram {
+                    scopes.push((depth, Scope::Function(entry.offset())));
+                    if let Some(name) = self.get_entry_name(unit, entry) {
+                        let ranges = self.get_entry_ranges(unit, entry).with_context(context)?;
+                        functions.push(FunctionEntry {
+                            name,
+                            ranges,
+                            unit_index,
+                            offset: entry.offset(),
+                        });
+                    }
+                    continue;
+                }
+                // Blocks without code don't restrict where their variables
+                // are visible. Inlined calls are blocks of the function they
+                // are inlined into, which provides the frame base
+                if matches!(
+                    entry.tag(),
+                    gimli::constants::DW_TAG_lexical_block
+                        | gimli::constants::DW_TAG_inlined_subroutine
+                ) && (entry
+                    .attr(gimli::DW_AT_low_pc)
+                    .with_context(context)?
+                    .is_some()
+                    || entry
+                        .attr(gimli::DW_AT_ranges)
+                        .with_context(context)?
+                        .is_some())
+                {
+                    scopes.push((depth, Scope::Block(entry.offset())));
+                    if entry.tag() == gimli::constants::DW_TAG_inlined_subroutine {
+                        inlined_calls.push(InlinedCallEntry {
+                            call: InlinedCall {
+                                function: self
+                                    .get_entry_name(unit, entry)
+                                    .unwrap_or_else(|| "??".to_owned()),
+                                call_position: self
+                                    .get_call_position(unit, entry)
+                                    .with_context(context)?,
+                            },
+                            ranges: self.get_entry_ranges(unit, entry).with_context(context)?,
+                        });
+                    }
                     continue;
                 }
 
-                let offset = match get_line_program_offset(entry) {
-                    Some(offset) => offset,
-                    None => continue,
+                let is_parameter = entry.tag() == gimli::constants::DW_TAG_formal_parameter;
+                if entry.tag() != gimli::constants::DW_TAG_variable && !is_parameter {
+                    continue;
+                }
+                let Some(name) = self.get_entry_name(unit, entry) else {
+                    continue;
+                };
+                let function = scopes.iter().rev().find_map(|(_, scope)| match scope {
+                    Scope::Function(offset) => Some(*offset),
+                    Scope::Block(_) => None,
+                });
+                // The innermost block is enough, the outer ones contain it.
+                // Blocks outside of the function can't contain the variable
+                let block = match scopes.last() {
+                    Some((_, Scope::Block(offset))) if function.is_some() => Some(*offset),
+                    _ => None,
                 };
+                variables.entry(name).or_default().push(VariableEntry {
+                    unit_index,
+                    offset: entry.offset(),
+                    function,
+                    block,
+                    is_parameter,
+                });
+            }
+        }
+        self.variables = variables;
+        self.functions = functions;
+        self.inlined_calls = inlined_calls;
+        Ok(())
+    }
 
-                let line_program = self.inner.debug_line.program(
-                    offset,
-                    header.address_size(),
-                    comp_dir.clone(),
-                    comp_name.clone(),
-                )?;
+    pub fn get_breakpoints_from_dwarf(&self) -> Result<HashMap<Breakpoint, u64>, anyhow::Error> {
+        Ok(self.breakpoints.clone())
+    }
 
-                let (program, sequences) = line_program.sequences()?;
+    pub fn get_architecture(&self) -> object::Architecture {
+        self.architecture
+    }
 
-                for sequence in sequences {
-                    let mut rows = program.resume_from(&sequence);
+    // The address the ELF expects the code at the given position of the file
+    // to be loaded at. Memory maps start at a page boundary, so the offset
+    // can be a bit before the segment
+    pub fn get_code_address_from_file_offset(&self, offset: u64) -> Option<u64> {
+        const PAGE_SIZE: u64 = 0x1000;
+        self.code_segments
+            .iter()
+            .find(|segment| {
+                segment.file_offset & !(PAGE_SIZE - 1) <= offset
+                    && offset < segment.file_offset + segment.file_size
+            })
+            .map(|segment| segment.address - segment.file_offset + offset)
+    }
 
-                    while let Ok(Some((_, row))) = rows.next_row() {
-                        if row.end_sequence() {
-                            continue;
-                        }
+    // Whether the address is inside one of the executable segments
+    pub fn is_code_address(&self, address: u64) -> bool {
+        self.code_segments.iter().any(|segment| {
+            segment.address <= address && address < segment.address + segment.file_size
+        })
+    }
 
-                        let path = match extract_path(&program, row.file_index()) {
-                            Some(p) => p,
-                            None => continue,
-                        };
+    // The first instruction that runs, after the dynamic loader
+    pub fn entry_point(&self) -> u64 {
+        self.entry_point
+    }
 
-                        if let Some(line) = row.line() {
-                            if address == row.address() {
-                                return Ok(LinePosition {
-                                    path,
-                                    line_number: line.get() as usize,
-                                });
-                            }
-                        }
-                    }
+    // Where each function with that name starts
+    pub fn get_function_addresses(&self, name: &str) -> Vec<u64> {
+        self.functions
+            .iter()
+            .filter(|function| function.name == name)
+            .filter_map(|function| function.ranges.iter().map(|range| range.begin).min())
+            .collect()
+    }
+
+    // The name and first address of every function with code
+    pub fn get_functions(&self) -> Vec<(&str, u64)> {
+        self.functions
+            .iter()
+            .filter_map(|function| {
+                let start = function.ranges.iter().map(|range| range.begin).min()?;
+                Some((function.name.as_str(), start))
+            })
+            .collect()
+    }
+
+    pub fn get_function_from_address(&self, address: u64) -> Option<&str> {
+        self.functions
+            .iter()
+            .find(|function| {
+                function
+                    .ranges
+                    .iter()
+                    .any(|range| range.begin <= address && address < range.end)
+            })
+            .map(|function| function.name.as_str())
+    }
+
+    // The inlined calls whose code contains the address, the innermost first
+    pub fn get_inlined_calls(&self, address: u64) -> Vec<&InlinedCall> {
+        self.inlined_calls
+            .iter()
+            .rev()
+            .filter(|inlined_call| {
+                inlined_call
+                    .ranges
+                    .iter()
+                    .any(|range| range.begin <= address && address < range.end)
+            })
+            .map(|inlined_call| &inlined_call.call)
+            .collect()
+    }
+
+    // The type returned by the function that contains the address, with its
+    // name, None for functions that don't return anything
+    pub fn get_return_type(
+        &self,
+        address: u64,
+        pointer_depth: usize,
+    ) -> anyhow::Result<Option<(TypeInfo, String)>> {
+        let function = self
+            .functions
+            .iter()
+            .find(|function| {
+                function
+                    .ranges
+                    .iter()
+                    .any(|range| range.begin <= address && address < range.end)
+            })
+            .ok_or(anyhow!("The address isn't in a known function"))?;
+        let unit = &self.units[function.unit_index];
+        let entry = unit.entry(function.offset)?;
+        let origin = self.get_origin_with(unit, &entry, gimli::DW_AT_type)?;
+        if origin
+            .as_ref()
+            .unwrap_or(&entry)
+            .attr(gimli::DW_AT_type)?
+            .is_none()
+        {
+            return Ok(None);
+        }
+        let type_info = self
+            .get_type_info(unit, &entry, pointer_depth)?
+            .ok_or(anyhow!("Unsupported return type"))?;
+        Ok(Some((type_info, self.get_type_name(unit, &entry)?)))
+    }
+
+    // First address of the function that contains the address
+    pub fn get_function_start(&self, address: u64) -> Option<u64> {
+        self.functions
+            .iter()
+            .find(|function| {
+                function
+                    .ranges
+                    .iter()
+                    .any(|range| range.begin <= address && address < range.end)
+            })
+            .and_then(|function| function.ranges.iter().map(|range| range.begin).min())
+    }
+
+    // The address doesn't need to be the first instruction of the line
+    pub fn get_line_from_address(&self, address: u64) -> anyhow::Result<LinePosition> {
+        self.lines
+            .range(..=address)
+            .next_back()
+            .and_then(|(_, line_position)| line_position.clone())
+            .ok_or(anyhow!("Couldn't find the source code for the address"))
+    }
+
+    // The rows of the line programs that belong to the file, sorted by
+    // address. The line table is walked again, only the indexes are kept
+    pub fn get_line_table(&self, file: &Path) -> anyhow::Result<Vec<LineTableRow>> {
+        let mut table = Vec::new();
+        for unit in &self.units {
+            let Some(line_program) = unit.line_program.clone() else {
+                continue;
+            };
+            let mut rows = line_program.rows();
+            while let Some((header, row)) = rows.next_row()? {
+                if row.end_sequence() {
+                    continue;
+                }
+                let Some(line) = row.line() else {
+                    continue;
+                };
+                if extract_path(&self.inner, unit, header, row.file_index())
+                    .is_some_and(|path| path == file)
+                {
+                    table.push(LineTableRow {
+                        address: row.address(),
+                        line_number: line.get(),
+                        is_stmt: row.is_stmt(),
+                        prologue_end: row.prologue_end(),
+                    });
                 }
             }
         }
-        bail!("Couldn't find the source code for the address")
+        table.sort_by_key(|row| row.address);
+        Ok(table)
     }
 
-    pub fn get_variable_info(&self, name: &str, pid: Pid) -> anyhow::Result<VariableInfo> {
-        let mut units = self.inner.units();
+    // Stepping by lines only stops at these addresses
+    pub fn is_statement_start(&self, address: u64) -> bool {
+        self.statements.contains(&address)
+    }
 
-        while let Some(header) = units.next()? {
-            let unit = self.inner.unit(header.clone())?;
-            let encoding = unit.encoding();
-            let mut entries = unit.entries();
-            let mut depth = 0;
-            let mut parents_stack = Vec::new();
+    // Locals of the function being executed take precedence over globals, so
+    // the registers of the frame are needed to find the right one and its
+    // frame base. The types of pointers to structs are read up to
+    // pointer_depth levels
+    pub fn get_variable_info(
+        &self,
+        name: &str,
+        registers: &Registers,
+        load_bias: u64,
+        pointer_depth: usize,
+    ) -> anyhow::Result<VariableInfo> {
+        let address = registers.general.rip - load_bias;
+        let mut global_variable = None;
+        let mut out_of_scope = false;
+        // Variables of inner blocks shadow the ones with the same name in
+        // the outer ones. Nested blocks come after the ones containing them
+        let mut local_variable: Option<&VariableEntry> = None;
 
-            while let Some((depth_delta, entry)) = entries.next_dfs()? {
-                depth += depth_delta;
-                parents_stack = parents_stack
-                    .into_iter()
-                    .filter(|(d, _)| *d < depth)
-                    .collect();
-                if entry.tag() == gimli::constants::DW_TAG_subprogram {
-                    // Save the current entry as a potential parent
-                    parents_stack.push((depth, entry.clone()));
-                    continue;
+        for variable in self.variables.get(name).into_iter().flatten() {
+            let unit = &self.units[variable.unit_index];
+            let entry = unit.entry(variable.offset)?;
+            match variable.function {
+                Some(function_offset) => {
+                    let function = unit.entry(function_offset)?;
+                    if !self.entry_contains_address(unit, &function, address)? {
+                        continue;
+                    }
+                    if !self.block_contains_address(unit, variable.block, address)? {
+                        out_of_scope = true;
+                        continue;
+                    }
+                    if local_variable.is_none_or(|local| {
+                        (local.block, local.offset) < (variable.block, variable.offset)
+                    }) {
+                        local_variable = Some(variable);
+                    }
+                }
+                // Declarations of globals defined somewhere else don't have
+                // a location
+                None if global_variable
+                    .as_ref()
+                    .is_none_or(|global: &VariableInfo| {
+                        matches!(global.location, VariableLocation::OptimizedOut)
+                    }) =>
+                {
+                    global_variable = Some(self.get_variable_info_from_entry(
+                        unit,
+                        &entry,
+                        None,
+                        registers,
+                        load_bias,
+                        pointer_depth,
+                    )?);
                 }
+                None => {}
+            }
+        }
+        if let Some(variable) = local_variable {
+            let unit = &self.units[variable.unit_index];
+            // Only variables of functions are local
+            let function = unit.entry(variable.function.unwrap())?;
+            return self.get_variable_info_from_entry(
+                unit,
+                &unit.entry(variable.offset)?,
+                Some(&function),
+                registers,
+                load_bias,
+                pointer_depth,
+            );
+        }
+        if out_of_scope && global_variable.is_none() {
+            bail!("The variable {} is not in scope here", name);
+        }
+        global_variable.ok_or(anyhow!("Couldn't find the variable"))
+    }
 
-                if entry.tag() != gimli::constants::DW_TAG_variable {
+    // Either the parameters or the rest of local variables of the function
+    // being executed, in the order they are declared
+    pub fn get_local_variables(
+        &self,
+        registers: &Registers,
+        load_bias: u64,
+        parameters: bool,
+        pointer_depth: usize,
+    ) -> anyhow::Result<Vec<(String, anyhow::Result<VariableInfo>)>> {
+        let address = registers.general.rip - load_bias;
+        let mut locals = Vec::new();
+        for (name, variables) in &self.variables {
+            for variable in variables {
+                let Some(function_offset) = variable.function else {
                     continue;
+                };
+                if variable.is_parameter != parameters {
+                    continue;
+                }
+                let unit = &self.units[variable.unit_index];
+                let function = unit.entry(function_offset)?;
+                if !self.entry_contains_address(unit, &function, address)?
+                    || !self.block_contains_address(unit, variable.block, address)?
+                {
+                    continue;
+                }
+                let entry = unit.entry(variable.offset)?;
+                let info = self.get_variable_info_from_entry(
+                    unit,
+                    &entry,
+                    Some(&function),
+                    registers,
+                    load_bias,
+                    pointer_depth,
+                );
+                locals.push(((variable.unit_index, variable.offset), name.clone(), info));
+            }
+        }
+        locals.sort_by_key(|(position, _, _)| *position);
+        Ok(locals
+            .into_iter()
+            .map(|(_, name, info)| (name, info))
+            .collect())
+    }
+
+    fn entry_contains_address(
+        &self,
+        unit: &gimli::Unit<DwarfReader, usize>,
+        entry: &gimli::DebuggingInformationEntry<'_, '_, DwarfReader, usize>,
+        address: u64,
+    ) -> anyhow::Result<bool> {
+        Ok(self
+            .get_entry_ranges(unit, entry)?
+            .iter()
+            .any(|range| range.begin <= address && address < range.end))
+    }
+
+    fn block_contains_address(
+        &self,
+        unit: &gimli::Unit<DwarfReader, usize>,
+        block: Option<gimli::UnitOffset>,
+        address: u64,
+    ) -> anyhow::Result<bool> {
+        match block {
+            Some(offset) => self.entry_contains_address(unit, &unit.entry(offset)?, address),
+            None => Ok(true),
+        }
+    }
+
+    fn get_entry_ranges(
+        &self,
+        unit: &gimli::Unit<DwarfReader, usize>,
+        entry: &gimli::DebuggingInformationEntry<'_, '_, DwarfReader, usize>,
+    ) -> anyhow::Result<Vec<gimli::Range>> {
+        let mut ranges = Vec::new();
+        let mut range_iter = self.inner.die_ranges(unit, entry)?;
+        while let Some(range) = range_iter.next()? {
+            ranges.push(range);
+        }
+        Ok(ranges)
+    }
+
+    fn get_entry_name(
+        &self,
+        unit: &gimli::Unit<DwarfReader, usize>,
+        entry: &gimli::DebuggingInformationEntry<'_, '_, DwarfReader, usize>,
+    ) -> Option<String> {
+        let origin = self.get_origin_with(unit, entry, gimli::DW_AT_name).ok()?;
+        let attribute_value = origin
+            .as_ref()
+            .unwrap_or(entry)
+            .attr_value(gimli::DW_AT_name)
+            .ok()??;
+        self.inner
+            .attr_string(unit, attribute_value)
+            .ok()?
+            .to_string()
+            .ok()
+            .map(|s| s.into_owned())
+    }
+
+    // Inlined code and the out of line copies of inlined functions only
+    // have their locations, the name and type are in the abstract instance.
+    // Definitions of what was declared somewhere else, like C++ methods or
+    // static members, refer to the declaration instead
+    fn get_origin<'a>(
+        &self,
+        unit: &'a gimli::Unit<DwarfReader, usize>,
+        entry: &gimli::DebuggingInformationEntry<'_, '_, DwarfReader, usize>,
+    ) -> anyhow::Result<Option<gimli::DebuggingInformationEntry<'a, 'a, DwarfReader, usize>>> {
+        let reference = match entry.attr_value(gimli::DW_AT_abstract_origin)? {
+            Some(reference) => Some(reference),
+            None => entry.attr_value(gimli::DW_AT_specification)?,
+        };
+        match reference {
+            Some(AttributeValue::UnitRef(offset)) => Ok(Some(unit.entry(offset)?)),
+            _ => Ok(None),
+        }
+    }
+
+    // The entry the attribute is taken from when the entry itself doesn't
+    // have it, following the chain of origins. None if the entry has it or
+    // no origin has it
+    fn get_origin_with<'a>(
+        &self,
+        unit: &'a gimli::Unit<DwarfReader, usize>,
+        entry: &gimli::DebuggingInformationEntry<'_, '_, DwarfReader, usize>,
+        attribute: gimli::DwAt,
+    ) -> anyhow::Result<Option<gimli::DebuggingInformationEntry<'a, 'a, DwarfReader, usize>>> {
+        // Like an inlined copy of a method, the chain is short unless the
+        // DWARF is broken and has a cycle
+        const MAX_DEPTH: usize = 8;
+        if entry.attr(attribute)?.is_some() {
+            return Ok(None);
+        }
+        let mut origin = self.get_origin(unit, entry)?;
+        for _ in 0..MAX_DEPTH {
+            match origin {
+                Some(entry) if entry.attr(attribute)?.is_some() => return Ok(Some(entry)),
+                Some(entry) => origin = self.get_origin(unit, &entry)?,
+                None => return Ok(None),
+            }
+        }
+        Ok(None)
+    }
+
+    fn get_call_position(
+        &self,
+        unit: &gimli::Unit<DwarfReader, usize>,
+        entry: &gimli::DebuggingInformationEntry<'_, '_, DwarfReader, usize>,
+    ) -> anyhow::Result<Option<LinePosition>> {
+        let (Some(file_index), Some(line_number), Some(line_program)) = (
+            entry
+                .attr(gimli::DW_AT_call_file)?
+                .and_then(|attr| attr.udata_value()),
+            entry
+                .attr(gimli::DW_AT_call_line)?
+                .and_then(|attr| attr.udata_value()),
+            &unit.line_program,
+        ) else {
+            return Ok(None);
+        };
+        Ok(
+            extract_path(&self.inner, unit, line_program.header(), file_index).map(|path| {
+                LinePosition {
+                    path,
+                    line_number: line_number as usize,
+                }
+            }),
+        )
+    }
+
+    // The function is needed to find the frame base of local variables
+    fn get_variable_info_from_entry(
+        &self,
+        unit: &gimli::Unit<DwarfReader, usize>,
+        entry: &gimli::DebuggingInformationEntry<'_, '_, DwarfReader, usize>,
+        function: Option<&gimli::DebuggingInformationEntry<'_, '_, DwarfReader, usize>>,
+        registers: &Registers,
+        load_bias: u64,
+        pointer_depth: usize,
+    ) -> anyhow::Result<VariableInfo> {
+        let encoding = unit.encoding();
+        let type_info = self
+            .get_type_info(unit, entry, pointer_depth)?
+            .ok_or_else(|| anyhow!("Couldn't find the type of the variable"))?;
+        let type_name = self.get_type_name(unit, entry)?;
+        let optimized_out = |type_info| {
+            Ok(VariableInfo {
+                location: VariableLocation::OptimizedOut,
+                type_info,
+                type_name: type_name.clone(),
+            })
+        };
+
+        let Some(attr) = entry.attr(gimli::DW_AT_location)? else {
+            return optimized_out(type_info);
+        };
+        let expr = match attr.value() {
+            gimli::AttributeValue::Exprloc(expr) => expr,
+            // In optimized code the location of a variable changes between
+            // ranges of addresses, and it doesn't need to have one everywhere
+            value => {
+                let address = registers.general.rip - load_bias;
+                let mut locations = self
+                    .inner
+                    .attr_locations(unit, value)?
+                    .ok_or(anyhow!("Unrecognized variable location info"))?;
+                let mut expr = None;
+                while let Some(location) = locations.next()? {
+                    if location.range.begin <= address && address < location.range.end {
+                        expr = Some(location.data);
+                        break;
+                    }
                 }
-                // TODO: Only relying on the variable name will lead to clashes
-                match self.get_variable_name_from_entry(entry) {
-                    Some(current_name) if current_name == name => {}
-                    _ => continue,
+                match expr {
+                    Some(expr) => expr,
+                    None => return optimized_out(type_info),
+                }
+            }
+        };
+        // Evaluate the expression to find the address
+        let mut evaluator = expr.evaluation(encoding);
+        let mut result = evaluator.evaluate()?;
+        loop {
+            result = match result {
+                gimli::EvaluationResult::Complete => break,
+                gimli::EvaluationResult::RequiresFrameBase => {
+                    let function =
+                        function.ok_or(anyhow!("Global variables don't have a frame base"))?;
+                    let frame_base =
+                        self.get_frame_base(function, encoding, registers, load_bias)?;
+                    evaluator.resume_with_frame_base(frame_base)?
+                }
+                gimli::EvaluationResult::RequiresCallFrameCfa => evaluator
+                    .resume_with_call_frame_cfa(self.get_call_frame_cfa(registers, load_bias)?)?,
+                gimli::EvaluationResult::RequiresRegister { register, .. } => {
+                    let value = get_register_value(registers, register)?;
+                    evaluator.resume_with_register(gimli::Value::Generic(value))?
+                }
+                // Addresses in the DWARF don't take into account where
+                // the binary is loaded into memory
+                gimli::EvaluationResult::RequiresRelocatedAddress(address) => {
+                    evaluator.resume_with_relocated_address(address + load_bias)?
+                }
+                _ => bail!("Unsupported location expression for the variable"),
+            };
+        }
+        // TODO: handle case with several pieces
+        let Some(piece) = evaluator.result().into_iter().next() else {
+            return optimized_out(type_info);
+        };
+        let location = match piece.location {
+            Location::Address { address } => VariableLocation::Address(address),
+            Location::Register { register } => {
+                VariableLocation::Value(get_register_value(registers, register)?)
+            }
+            // Computed by the expression, like with DW_OP_stack_value
+            Location::Value { value } => VariableLocation::Value(value.to_u64(u64::MAX)?),
+            Location::Empty => return optimized_out(type_info),
+            _ => bail!("Unsupported location for the variable"),
+        };
+        Ok(VariableInfo {
+            location,
+            type_info,
+            type_name,
+        })
+    }
+
+    fn get_frame_base(
+        &self,
+        function: &gimli::DebuggingInformationEntry<'_, '_, DwarfReader, usize>,
+        encoding: gimli::Encoding,
+        registers: &Registers,
+        load_bias: u64,
+    ) -> anyhow::Result<u64> {
+        let mut evaluator = match function
+            .attr(gimli::DW_AT_frame_base)?
+            .ok_or(anyhow!("The function doesn't have a frame base"))?
+            .value()
+        {
+            AttributeValue::Exprloc(expression) => expression.evaluation(encoding),
+            _ => {
+                bail!("Frame bases stored in something other than an expression are not supported")
+            }
+        };
+        let mut result = evaluator.evaluate()?;
+        loop {
+            result = match result {
+                gimli::EvaluationResult::Complete => break,
+                // gcc uses DW_OP_call_frame_cfa
+                gimli::EvaluationResult::RequiresCallFrameCfa => evaluator
+                    .resume_with_call_frame_cfa(self.get_call_frame_cfa(registers, load_bias)?)?,
+                gimli::EvaluationResult::RequiresRegister { register, .. } => {
+                    let value = get_register_value(registers, register)?;
+                    evaluator.resume_with_register(gimli::Value::Generic(value))?
                 }
+                _ => bail!("Unsupported frame base expression"),
+            };
+        }
+        // TODO: try to handle locations with offsets/different sizes
+        match evaluator.result().first().map(|piece| &piece.location) {
+            Some(Location::Register { register }) => get_register_value(registers, *register),
+            Some(Location::Address { address }) => Ok(*address),
+            Some(_) => bail!("Unsupported location of the frame base"),
+            None => bail!("The frame base expression is empty"),
+        }
+    }
 
-                let (base_type, size) = get_type_info(&unit, entry)?
-                    .ok_or_else(|| anyhow!("Couldn't find the type of the variable"))?;
+    // The canonical frame address is the value the stack pointer had before
+    // the call to the function of the frame
+    fn get_call_frame_cfa(&self, registers: &Registers, load_bias: u64) -> anyhow::Result<u64> {
+        let address = registers.general.rip - load_bias;
+        let bases = &self.frame_section_bases;
+        let mut context = gimli::UnwindContext::new();
+        // Binaries usually only have one of the sections, .debug_frame is
+        // preferred since it can describe more
+        let rule = match self.debug_frame.unwind_info_for_address(
+            bases,
+            &mut context,
+            address,
+            gimli::DebugFrame::cie_from_offset,
+        ) {
+            Ok(row) => row.cfa().clone(),
+            Err(_) => self
+                .eh_frame
+                .unwind_info_for_address(
+                    bases,
+                    &mut context,
+                    address,
+                    gimli::EhFrame::cie_from_offset,
+                )
+                .context("There is no call frame information for the address")?
+                .cfa()
+                .clone(),
+        };
+        match rule {
+            gimli::CfaRule::RegisterAndOffset { register, offset } => {
+                Ok(get_register_value(registers, register)?.wrapping_add_signed(offset))
+            }
+            gimli::CfaRule::Expression(_) => {
+                bail!("Canonical frame addresses computed by an expression are not supported")
+            }
+        }
+    }
 
-                if let Some(attr) = entry.attr(gimli::DW_AT_location)? {
-                    match attr.value() {
-                        gimli::AttributeValue::LocationListsRef(_) => {
-                            unreachable!("Support location lists for variables")
+    fn get_type_info(
+        &self,
+        unit: &gimli::Unit<DwarfReader, usize>,
+        entry: &gimli::DebuggingInformationEntry<'_, '_, DwarfReader, usize>,
+        pointer_depth: usize,
+    ) -> Result<Option<TypeInfo>, anyhow::Error> {
+        let origin = self.get_origin_with(unit, entry, gimli::DW_AT_type)?;
+        let entry = origin.as_ref().unwrap_or(entry);
+        if let Some(attr) = entry.attr(gimli::DW_AT_type)? {
+            let type_offset = match attr.value() {
+                AttributeValue::UnitRef(offset) => offset,
+                _ => bail!(
+                    "Unsupported reference to the type of the DWARF entry at {}",
+                    describe_offset(entry.offset().to_unit_section_offset(unit))
+                ),
+            };
+            let mut tree = unit.entries_tree(Some(type_offset))?;
+            let root = tree.root()?;
+            let entry = root.entry();
+            match entry.tag() {
+                gimli::constants::DW_TAG_base_type => {
+                    return Ok(get_base_type_info(entry)?
+                        .map(|(base_type, size)| TypeInfo::Base { base_type, size }));
+                }
+                gimli::constants::DW_TAG_array_type => {
+                    let element_type = match self.get_type_info(unit, entry, pointer_depth)? {
+                        Some(element_type) => element_type,
+                        None => return Ok(None),
+                    };
+                    let mut counts = Vec::new();
+                    let mut children = root.children();
+                    while let Some(child) = children.next()? {
+                        if child.entry().tag() == gimli::constants::DW_TAG_subrange_type {
+                            counts.push(get_subrange_count(child.entry())?);
+                        }
+                    }
+                    let count = match counts.as_slice() {
+                        [count] => *count,
+                        [] => None,
+                        _ => bail!("Multi-dimensional arrays are not supported"),
+                    };
+                    return Ok(Some(TypeInfo::Array {
+                        element_type: Box::new(element_type),
+                        count,
+                    }));
+                }
+                gimli::constants::DW_TAG_enumeration_type => {
+                    let base_type = match self.get_type_info(unit, entry, pointer_depth)? {
+                        Some(TypeInfo::Base { base_type, .. }) => base_type,
+                        _ => BaseType::Unsigned,
+                    };
+                    let size = match entry.attr(gimli::DW_AT_byte_size)? {
+                        Some(size) => match size.udata_value() {
+                            Some(value) => value * 8,
+                            None => bail!("Byte size stored in unexpected way"),
+                        },
+                        None => return Ok(None),
+                    };
+                    let mut enumerators = Vec::new();
+                    let mut children = root.children();
+                    while let Some(child) = children.next()? {
+                        let child = child.entry();
+                        if child.tag() != gimli::constants::DW_TAG_enumerator {
+                            continue;
+                        }
+                        let value = match child.attr(gimli::DW_AT_const_value)? {
+                            Some(value) => match value.value() {
+                                AttributeValue::Sdata(value) => value as u64,
+                                value => match value.udata_value() {
+                                    Some(value) => value,
+                                    None => continue,
+                                },
+                            },
+                            None => continue,
+                        };
+                        if let Some(name) = self.get_entry_name(unit, child) {
+                            // Negative values only keep the bits that are actually read
+                            enumerators.push((name, value & low_bits_mask(size)));
                         }
-                        gimli::AttributeValue::Exprloc(expr) => {
-                            // Evaluate the expression to find the address
-                            let mut evaluator = expr.evaluation(encoding);
-                            let parent_die = &parents_stack.last().unwrap().1;
-                            let frame_base = match get_frame_base_location(parent_die, encoding)? {
-                                Location::Register { register } => {
-                                    let regs = getregs(pid)?;
-                                    get_register_value(&regs, register)?
-                                }
-                                _ => unimplemented!("Frame base not stored in a register"),
-                            };
-                            evaluator.evaluate()?;
-                            // TODO: handle this properly instead of hardcoding the need for the frame base
-                            evaluator.resume_with_frame_base(frame_base)?;
-                            // TODO: handle case with several pieces or non addresses
-                            if let Location::Address { address } = evaluator.result()[0].location {
-                                return Ok(VariableInfo {
-                                    address,
-                                    base_type,
-                                    size,
-                                });
+                    }
+                    return Ok(Some(TypeInfo::Enum {
+                        base_type,
+                        size,
+                        enumerators,
+                    }));
+                }
+                gimli::constants::DW_TAG_pointer_type => {
+                    let pointer_depth = match self.points_to_struct(unit, entry)? {
+                        true if pointer_depth == 0 => {
+                            return Ok(Some(TypeInfo::Pointer {
+                                pointee_type: None,
+                                cut_off: true,
+                            }));
+                        }
+                        true => pointer_depth - 1,
+                        false => pointer_depth,
+                    };
+                    let pointee_type = self
+                        .get_type_info(unit, entry, pointer_depth)
+                        .ok()
+                        .flatten()
+                        .map(Box::new);
+                    return Ok(Some(TypeInfo::Pointer {
+                        pointee_type,
+                        cut_off: false,
+                    }));
+                }
+                gimli::constants::DW_TAG_structure_type
+                | gimli::constants::DW_TAG_class_type
+                | gimli::constants::DW_TAG_union_type => {
+                    let size = match entry.attr(gimli::DW_AT_byte_size)? {
+                        Some(size) => match size.udata_value() {
+                            Some(value) => value * 8,
+                            None => bail!("Byte size stored in unexpected way"),
+                        },
+                        // Only declared, the definition is somewhere else
+                        None => bail!("Incomplete struct and union types are not supported"),
+                    };
+                    let is_union = entry.tag() == gimli::constants::DW_TAG_union_type;
+                    let name = self.get_entry_name(unit, entry);
+                    let mut members = Vec::new();
+                    let mut children = root.children();
+                    while let Some(child) = children.next()? {
+                        let child = child.entry();
+                        match child.tag() {
+                            gimli::constants::DW_TAG_member => {}
+                            gimli::constants::DW_TAG_variant_part => {
+                                bail!("Enums with data are not supported")
                             }
+                            _ => continue,
                         }
-                        _ => unreachable!("Unrecognized variable location info"),
+                        // Static members are stored outside of the struct
+                        if child.attr(gimli::DW_AT_declaration)?.is_some() {
+                            continue;
+                        }
+                        let type_info = self
+                            .get_type_info(unit, child, pointer_depth)?
+                            .ok_or_else(|| anyhow!("Couldn't find the type of a member"))?;
+                        members.push(Member {
+                            name: self.get_entry_name(unit, child).unwrap_or_default(),
+                            type_name: self.get_type_name(unit, child)?,
+                            offset: get_member_offset(unit, child, type_info.size())?,
+                            bit_size: child
+                                .attr(gimli::DW_AT_bit_size)?
+                                .and_then(|size| size.udata_value()),
+                            type_info,
+                        });
+                    }
+                    if is_union {
+                        return Ok(Some(TypeInfo::Union { size, members }));
+                    }
+                    if let Some(str_type) = get_str_type(name, size, &members) {
+                        return Ok(Some(str_type));
                     }
+                    return Ok(Some(TypeInfo::Struct { size, members }));
+                }
+                // Qualifiers and aliases don't change how the value is read
+                gimli::constants::DW_TAG_const_type
+                | gimli::constants::DW_TAG_volatile_type
+                | gimli::constants::DW_TAG_typedef => {
+                    return self.get_type_info(unit, entry, pointer_depth);
+                }
+                _ => {
+                    bail!(
+                        "Only primitive types, arrays, pointers, enums, structs and unions are supported"
+                    )
                 }
             }
         }
-        anyhow::bail!("Couldn't find the variable")
+        Ok(None)
     }
 
-    fn get_variable_name_from_entry(
+    // Whether the type of the entry is a struct or a union, looking through
+    // qualifiers and aliases
+    fn points_to_struct(
         &self,
-        entry: &gimli::DebuggingInformationEntry<
-            '_,
-            '_,
-            gimli::EndianReader<LittleEndian, Rc<[u8]>>,
-            usize,
-        >,
-    ) -> Option<String> {
-        let attribute_value = entry.attr(gimli::DW_AT_name).ok()??.value();
-        if let AttributeValue::DebugStrRef(offset) = attribute_value {
-            self.inner
-                .debug_str
-                .get_str(offset)
-                .ok()?
-                .to_string()
-                .ok()
-                .map(|s| s.into_owned())
-        } else {
-            None
+        unit: &gimli::Unit<DwarfReader, usize>,
+        entry: &gimli::DebuggingInformationEntry<'_, '_, DwarfReader, usize>,
+    ) -> anyhow::Result<bool> {
+        let Some(AttributeValue::UnitRef(type_offset)) = entry.attr_value(gimli::DW_AT_type)?
+        else {
+            return Ok(false);
+        };
+        let type_entry = unit.entry(type_offset)?;
+        match type_entry.tag() {
+            gimli::constants::DW_TAG_structure_type
+            | gimli::constants::DW_TAG_class_type
+            | gimli::constants::DW_TAG_union_type => Ok(true),
+            gimli::constants::DW_TAG_const_type
+            | gimli::constants::DW_TAG_volatile_type
+            | gimli::constants::DW_TAG_typedef => self.points_to_struct(unit, &type_entry),
+            _ => Ok(false),
         }
     }
-}
 
-fn get_type_info(
-    unit: &gimli::Unit<gimli::EndianReader<LittleEndian, Rc<[u8]>>, usize>,
-    entry: &gimli::DebuggingInformationEntry<
-        '_,
-        '_,
-        gimli::EndianReader<LittleEndian, Rc<[u8]>>,
-        usize,
-    >,
-) -> Result<Option<(BaseType, u64)>, anyhow::Error> {
-    if let Some(attr) = entry.attr(gimli::DW_AT_type)? {
-        let type_offset = match attr.value() {
-            AttributeValue::UnitRef(offset) => offset,
-            _ => unreachable!(""),
+    // Types without a name, like pointers in C, are named after the types
+    // they are built from
+    fn get_type_name(
+        &self,
+        unit: &gimli::Unit<DwarfReader, usize>,
+        entry: &gimli::DebuggingInformationEntry<'_, '_, DwarfReader, usize>,
+    ) -> anyhow::Result<String> {
+        let origin = self.get_origin_with(unit, entry, gimli::DW_AT_type)?;
+        let entry = origin.as_ref().unwrap_or(entry);
+        let Some(AttributeValue::UnitRef(type_offset)) = entry.attr_value(gimli::DW_AT_type)?
+        else {
+            return Ok(String::from("void"));
         };
-        if let Some((_, entry)) = unit.entries_at_offset(type_offset)?.next_dfs()? {
-            if entry.tag() != gimli::constants::DW_TAG_base_type {
-                bail!("Only primitive types are supported");
+        let type_entry = unit.entry(type_offset)?;
+        if let Some(name) = self.get_entry_name(unit, &type_entry) {
+            return Ok(name);
+        }
+        let name = match type_entry.tag() {
+            gimli::constants::DW_TAG_pointer_type => {
+                format!("{} *", self.get_type_name(unit, &type_entry)?)
             }
-            let base_type = match entry.attr(gimli::DW_AT_encoding)? {
-                Some(base_type) => match base_type.value() {
-                    AttributeValue::Encoding(value) => parse_base_type(value)?,
-                    _ => unreachable!("Unrecognized base type"),
-                },
-                _ => return Ok(None),
-            };
-            let byte_size = match entry.attr(gimli::DW_AT_byte_size)? {
-                Some(size) => match size.value() {
-                    AttributeValue::Udata(value) => Some(value),
-                    _ => unreachable!("Byte size stored in unexpected way"),
-                },
-                _ => None,
-            };
-            let bit_size = match entry.attr(gimli::DW_AT_bit_size)? {
-                Some(size) => match size.value() {
-                    AttributeValue::Udata(value) => Some(value),
-                    _ => unreachable!("Bit size stored in unexpected way"),
-                },
-                _ => None,
+            gimli::constants::DW_TAG_const_type => {
+                format!("const {}", self.get_type_name(unit, &type_entry)?)
+            }
+            gimli::constants::DW_TAG_volatile_type => {
+                format!("volatile {}", self.get_type_name(unit, &type_entry)?)
+            }
+            gimli::constants::DW_TAG_array_type => {
+                let mut tree = unit.entries_tree(Some(type_offset))?;
+                let mut children = tree.root()?.children();
+                let mut dimensions = String::new();
+                while let Some(child) = children.next()? {
+                    if child.entry().tag() == gimli::constants::DW_TAG_subrange_type {
+                        match get_subrange_count(child.entry())? {
+                            Some(count) => dimensions += &format!("[{}]", count),
+                            None => dimensions += "[]",
+                        }
+                    }
+                }
+                // The dimensions of nested arrays go in order, like in int [2][3]
+                let element_name = self.get_type_name(unit, &type_entry)?;
+                match element_name.split_once(" [") {
+                    Some((base_name, inner_dimensions)) => {
+                        format!("{} {}[{}", base_name, dimensions, inner_dimensions)
+                    }
+                    None => format!("{} {}", element_name, dimensions),
+                }
+            }
+            // Anonymous, like the ones nested in other types
+            gimli::constants::DW_TAG_structure_type => String::from("struct {...}"),
+            gimli::constants::DW_TAG_union_type => String::from("union {...}"),
+            _ => String::from("?"),
+        };
+        Ok(name)
+    }
+}
+
+// Offsets in .debug_info, or in .debug_types for the type units of DWARF 4
+fn describe_offset(offset: gimli::UnitSectionOffset) -> String {
+    match offset {
+        gimli::UnitSectionOffset::DebugInfoOffset(offset) => {
+            format!("{:#x} of .debug_info", offset.0)
+        }
+        gimli::UnitSectionOffset::DebugTypesOffset(offset) => {
+            format!("{:#x} of .debug_types", offset.0)
+        }
+    }
+}
+
+fn get_base_type_info(
+    entry: &gimli::DebuggingInformationEntry<'_, '_, DwarfReader, usize>,
+) -> Result<Option<(BaseType, u64)>, anyhow::Error> {
+    let base_type = match entry.attr(gimli::DW_AT_encoding)? {
+        Some(base_type) => match base_type.value() {
+            AttributeValue::Encoding(value) => parse_base_type(value)?,
+            _ => bail!("Unrecognized base type"),
+        },
+        _ => return Ok(None),
+    };
+    let byte_size = match entry.attr(gimli::DW_AT_byte_size)? {
+        Some(size) => match size.value() {
+            AttributeValue::Udata(value) => Some(value),
+            _ => bail!("Byte size stored in unexpected way"),
+        },
+        _ => None,
+    };
+    let bit_size = match entry.attr(gimli::DW_AT_bit_size)? {
+        Some(size) => match size.value() {
+            AttributeValue::Udata(value) => Some(value),
+            _ => bail!("Bit size stored in unexpected way"),
+        },
+        _ => None,
+    };
+    let size = bit_size.or(byte_size.map(|v| v * 8));
+    Ok(size.map(|size| (base_type, size)))
+}
+
+// The string slices of Rust are structs with the pointer and the length
+fn get_str_type(name: Option<String>, size: u64, members: &[Member]) -> Option<TypeInfo> {
+    if !matches!(name.as_deref(), Some("&str" | "&mut str")) {
+        return None;
+    }
+    let member = |name| members.iter().find(|member| member.name == name);
+    let data = member("data_ptr")?;
+    let length = member("length")?;
+    match (&data.type_info, &length.type_info) {
+        (
+            TypeInfo::Pointer { .. },
+            TypeInfo::Base {
+                base_type: BaseType::Unsigned,
+                size: 64,
+            },
+        ) => Some(TypeInfo::Str {
+            size,
+            data_offset: data.offset,
+            length_offset: length.offset,
+        }),
+        _ => None,
+    }
+}
+
+// In bits, from the start of the struct. storage_size is the size of the
+// type of the member, which holds the bitfields in the older format
+fn get_member_offset(
+    unit: &gimli::Unit<DwarfReader, usize>,
+    entry: &gimli::DebuggingInformationEntry<'_, '_, DwarfReader, usize>,
+    storage_size: u64,
+) -> anyhow::Result<u64> {
+    if let Some(bit_offset) = entry.attr(gimli::DW_AT_data_bit_offset)? {
+        return bit_offset
+            .udata_value()
+            .ok_or(anyhow!("Bit offset stored in unexpected way"));
+    }
+    let byte_offset = match entry.attr_value(gimli::DW_AT_data_member_location)? {
+        // Older compilers use an expression that adds the offset to the
+        // address of the struct
+        Some(AttributeValue::Exprloc(expr)) => {
+            let mut evaluator = expr.evaluation(unit.encoding());
+            evaluator.set_initial_value(0);
+            let gimli::EvaluationResult::Complete = evaluator.evaluate()? else {
+                bail!("Unsupported location expression for the member");
             };
-            let size = bit_size.or(byte_size.map(|v| v * 8));
-            if let Some(size) = size {
-                return Ok(Some((base_type, size)));
+            match evaluator
+                .result()
+                .first()
+                .map(|piece| piece.location.clone())
+            {
+                Some(Location::Address { address }) => address,
+                _ => bail!("Unsupported location expression for the member"),
             }
         }
+        Some(location) => location
+            .udata_value()
+            .ok_or(anyhow!("Member location stored in unexpected way"))?,
+        // The members of unions don't need it
+        None => 0,
+    };
+    // DWARF 2 style bitfields count the bits from the most significant one
+    // of the storage unit
+    let Some(bit_offset) = entry
+        .attr(gimli::DW_AT_bit_offset)?
+        .and_then(|offset| offset.udata_value())
+    else {
+        return Ok(byte_offset * 8);
+    };
+    let bit_size = entry
+        .attr(gimli::DW_AT_bit_size)?
+        .and_then(|size| size.udata_value())
+        .ok_or(anyhow!("Bitfield without a size"))?;
+    let storage_size = match entry.attr(gimli::DW_AT_byte_size)? {
+        Some(size) => size
+            .udata_value()
+            .map(|size| size * 8)
+            .unwrap_or(storage_size),
+        None => storage_size,
+    };
+    Ok(byte_offset * 8 + storage_size - bit_offset - bit_size)
+}
+
+fn get_subrange_count(
+    entry: &gimli::DebuggingInformationEntry<'_, '_, DwarfReader, usize>,
+) -> Result<Option<u64>, anyhow::Error> {
+    if let Some(count) = entry.attr(gimli::DW_AT_count)? {
+        return Ok(count.udata_value());
     }
-    Ok(None)
+    let lower_bound = match entry.attr(gimli::DW_AT_lower_bound)? {
+        Some(lower_bound) => lower_bound.udata_value().unwrap_or(0),
+        None => 0,
+    };
+    let Some(upper_bound) = entry
+        .attr(gimli::DW_AT_upper_bound)?
+        .and_then(|upper_bound| upper_bound.udata_value())
+    else {
+        return Ok(None);
+    };
+    // The upper bound is inclusive
+    upper_bound
+        .checked_add(1)
+        .and_then(|end| end.checked_sub(lower_bound))
+        .map(Some)
+        .ok_or(anyhow!(
+            "Invalid bounds of the array, from {} to {}",
+            lower_bound,
+            upper_bound
+        ))
 }
 
 fn parse_base_type(value: DwAte) -> anyhow::Result<BaseType> {
     match value {
         gimli::DW_ATE_boolean => Ok(BaseType::Boolean),
+        gimli::DW_ATE_signed_char | gimli::DW_ATE_unsigned_char | gimli::DW_ATE_UTF => {
+            Ok(BaseType::Char)
+        }
         gimli::DW_ATE_float => Ok(BaseType::Float),
         gimli::DW_ATE_signed => Ok(BaseType::Signed),
         gimli::DW_ATE_unsigned => Ok(BaseType::Unsigned),
@@ -295,29 +1525,9 @@ fn parse_base_type(value: DwAte) -> anyhow::Result<BaseType> {
     }
 }
 
-fn get_frame_base_location(
-    debugging_information_entry: &gimli::DebuggingInformationEntry<
-        '_,
-        '_,
-        gimli::EndianReader<LittleEndian, Rc<[u8]>>,
-        usize,
-    >,
-    encoding: gimli::Encoding,
-) -> Result<Location<gimli::EndianReader<LittleEndian, Rc<[u8]>>>, anyhow::Error> {
-    let mut evaluator = match debugging_information_entry
-        .attr(gimli::DW_AT_frame_base)?
-        .unwrap()
-        .value()
-    {
-        AttributeValue::Exprloc(expression) => expression.evaluation(encoding),
-        _ => unimplemented!("Frame based store in something other than a Exprloc"),
-    };
-    evaluator.evaluate()?;
-    // TODO: try to handle locations with offsets/different sizes
-    Ok(evaluator.result()[0].location.clone())
-}
-
 fn process_sequence<R>(
+    dwarf: &gimli::Dwarf<R>,
+    unit: &gimli::Unit<R>,
     program: &gimli::CompleteLineProgram<R>,
     sequence: &gimli::LineSequence<R>,
 ) -> Result<HashMap<Breakpoint, u64>, anyhow::Error>
@@ -327,12 +1537,12 @@ where
     let mut rows = program.resume_from(sequence);
     let mut breakpoints = HashMap::new();
 
-    while let Ok(Some((_, row))) = rows.next_row() {
+    while let Some((_, row)) = rows.next_row()? {
         if row.end_sequence() {
             continue;
         }
 
-        let path = match extract_path(program, row.file_index()) {
+        let path = match extract_path(dwarf, unit, program.header(), row.file_index()) {
             Some(p) => p,
             None => continue,
         };
@@ -344,45 +1554,141 @@ where
                 line_number: line.get(),
             };
             // We only add the first address for each line
-            if !breakpoints.contains_key(&breakpoint) {
-                breakpoints.insert(breakpoint, address);
-            }
+            breakpoints.entry(breakpoint).or_insert(address);
         }
     }
 
     Ok(breakpoints)
 }
 
-fn extract_path<R>(program: &gimli::CompleteLineProgram<R>, file_index: u64) -> Option<PathBuf>
+// The file index is interpreted by gimli, it starts at 1 before DWARF 5
+// and at 0 since then
+fn extract_path<R>(
+    dwarf: &gimli::Dwarf<R>,
+    unit: &gimli::Unit<R>,
+    header: &gimli::LineProgramHeader<R>,
+    file_index: u64,
+) -> Option<PathBuf>
 where
     R: gimli::Reader,
 {
-    let header = program.header();
     let file = header.file(file_index)?;
-
-    let dir = match file.directory(header)? {
-        gimli::AttributeValue::String(s) => PathBuf::from(s.to_string().ok()?.into_owned()),
-        _ => return None,
+    // Since DWARF 5 the names are usually in .debug_line_str or .debug_str
+    let attr_string = |value| -> Option<String> {
+        Some(
+            dwarf
+                .attr_string(unit, value)
+                .ok()?
+                .to_string()
+                .ok()?
+                .into_owned(),
+        )
     };
 
-    let file_name = match file.path_name() {
-        gimli::AttributeValue::String(s) => s.to_string().ok()?.into_owned(),
-        _ => return None,
-    };
+    let mut dir = PathBuf::from(attr_string(file.directory(header)?)?);
+    // Relative directories are relative to the compilation directory
+    if dir.is_relative()
+        && let Some(comp_dir) = header.directory(0).and_then(attr_string)
+    {
+        dir = PathBuf::from(comp_dir).join(dir);
+    }
+
+    let file_name = attr_string(file.path_name())?;
 
-    dir.join(file_name).canonicalize().ok()
+    // The sources may not exist in this filesystem (e.g. the binary was built
+    // somewhere else), so keep the path recorded in the DWARF in that case
+    let path = dir.join(file_name);
+    Some(path.canonicalize().unwrap_or(path))
 }
 
-fn get_line_program_offset<R>(
-    entry: &gimli::DebuggingInformationEntry<'_, '_, R, <R as Reader>::Offset>,
-) -> Option<gimli::DebugLineOffset<R::Offset>>
-where
-    R: Reader,
-{
-    if let AttributeValue::DebugLineRef(offset) =
-        entry.attr(gimli::constants::DW_AT_stmt_list).ok()??.value()
+// Stripped binaries can have their DWARF in another file, found through the
+// build id under /usr/lib/debug/.build-id or the name in .gnu_debuglink
+// Compilers can compress the debug sections, like with -gz. A missing
+// section is left empty
+fn load_section(file: &object::File, name: &str, endian: RunTimeEndian) -> DwarfReader {
+    let section = file
+        .section_by_name(name)
+        .and_then(|section| section.uncompressed_data().ok())
+        .map(|data| data.into_owned())
+        .unwrap_or_default();
+    gimli::EndianReader::new(Rc::from(section), endian)
+}
+
+fn find_debug_file(obj_file: &object::File, path: &Path) -> Option<Vec<u8>> {
+    const DEBUG_DIRECTORY: &str = "/usr/lib/debug";
+    if let Ok(Some(build_id)) = obj_file.build_id()
+        && let [first, rest @ ..] = build_id
     {
-        return Some(offset);
+        let hex: String = rest.iter().map(|byte| format!("{:02x}", byte)).collect();
+        let debug_path = format!("{}/.build-id/{:02x}/{}.debug", DEBUG_DIRECTORY, first, hex);
+        if let Ok(buffer) = fs::read(debug_path) {
+            return Some(buffer);
+        }
+    }
+
+    let (name, crc) = obj_file.gnu_debuglink().ok()??;
+    let name = Path::new(std::str::from_utf8(name).ok()?);
+    let directory = path.parent()?;
+    // The same places gdb looks in
+    let candidates = [
+        directory.join(name),
+        directory.join(".debug").join(name),
+        Path::new(DEBUG_DIRECTORY)
+            .join(directory.strip_prefix("/").unwrap_or(directory))
+            .join(name),
+    ];
+    candidates
+        .iter()
+        // The binary itself can be the first candidate
+        .filter(|candidate| candidate.as_path() != path)
+        .filter_map(|candidate| fs::read(candidate).ok())
+        // A different crc means the file is from another build
+        .find(|buffer| crc32(buffer) == crc)
+}
+
+// The CRC-32 used by .gnu_debuglink, the same as zlib
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb88320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_buffers_that_are_not_elf_files() {
+        let mut pseudo_random = 0x2545f4914f6cdd1du64;
+        let noise = (0..4096)
+            .map(|_| {
+                pseudo_random ^= pseudo_random << 13;
+                pseudo_random ^= pseudo_random >> 7;
+                pseudo_random ^= pseudo_random << 17;
+                pseudo_random as u8
+            })
+            .collect();
+        let buffers: [Vec<u8>; 4] = [
+            Vec::new(),
+            b"#!/bin/sh\necho hello\n".to_vec(),
+            // The magic number, with the rest of the header missing
+            b"\x7fELF\x02\x01\x01".to_vec(),
+            noise,
+        ];
+        for buffer in buffers {
+            let error = DwarfInfo::new(buffer, Path::new("/tmp/not-an-elf"))
+                .err()
+                .expect("the buffer isn't an ELF file");
+            assert_eq!(error.to_string(), "Not a valid ELF file");
+        }
     }
-    None
 }
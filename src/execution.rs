@@ -0,0 +1,1387 @@
+use anyhow::{Context, anyhow};
+use nix::{
+    errno::Errno,
+    sys::{
+        ptrace::{self, traceme},
+        signal::{
+            SaFlags, SigAction, SigHandler, SigSet,
+            Signal::{self, SIGALRM, SIGCHLD, SIGINT, SIGPROF, SIGSTOP, SIGTRAP, SIGURG, SIGWINCH},
+            kill, sigaction,
+        },
+        wait::{WaitPidFlag, WaitStatus, waitpid},
+    },
+    unistd::{ForkResult, Pid, dup2, execve, fork},
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    ffi::CString,
+    fs, io,
+    os::{fd::AsRawFd, unix::ffi::OsStrExt},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::{
+    Address, Breakpoint, BreakpointLocation, LoadedBinary, ProgramContext, RunningProgram,
+    SetBreakpoint, UserBreakpoint,
+    binary::{
+        find_debug_info, get_range_for_program_source_code, is_mapped_code, map_contains,
+        relative_address_to_virtual, virtual_address_to_relative,
+    },
+    breakpoints::{
+        arm_breakpoint, arm_library_breakpoints, disarm_breakpoint, find_breakpoint, parse_address,
+        read_original_word, remove_temporary_breakpoint, restore_original_instruction,
+        stopped_at_breakpoint, write_trap_instruction,
+    },
+    dwarf,
+    frames::{print_address, print_location, print_source_code_line},
+    output::{self, Json, Output},
+    print::{format_variable, format_wide_base_value},
+    registers,
+    tracee::Tracee,
+    watchpoints::report_watchpoint,
+};
+
+// The shared libraries aren't mapped yet when the program starts, but the
+// dynamic loader has mapped them before the entry point of the binary. If
+// there are breakpoints in them, or stop_at_entry is set, the program is
+// stopped there, and last_status is updated. Returns the status if the
+// program stopped before reaching it
+fn run_to_entry_point(
+    running_program: &mut RunningProgram,
+    queued_events: &mut Vec<WaitStatus>,
+    binary: &LoadedBinary,
+    breakpoints: &[UserBreakpoint],
+    stop_at_entry: bool,
+) -> anyhow::Result<Option<WaitStatus>> {
+    let has_library_breakpoints = breakpoints.iter().any(|breakpoint| {
+        breakpoint.enabled && matches!(breakpoint.location, BreakpointLocation::LibraryLine { .. })
+    });
+    let entry_point =
+        relative_address_to_virtual(binary.dwarf.entry_point(), &running_program.proc_map)?;
+    // A user breakpoint at the entry point is reported as the first stop
+    if !(has_library_breakpoints || stop_at_entry)
+        || running_program.set_breakpoints.contains_key(&entry_point)
+    {
+        return Ok(None);
+    }
+    arm_breakpoint(running_program, entry_point)?;
+    running_program.temporary_breakpoint = Some(entry_point);
+    running_program.pid.cont(None)?;
+    let status = wait_for_stop(running_program, queued_events)?;
+    if program_finished(status)
+        || get_last_instruction_address(running_program.thread)? != entry_point
+    {
+        return Ok(Some(status));
+    }
+    running_program.last_status = status;
+    let mut registers = running_program.thread.get_regs()?;
+    registers.rip = entry_point;
+    running_program.thread.set_regs(registers)?;
+    remove_temporary_breakpoint(running_program)?;
+    arm_library_breakpoints(running_program, binary, breakpoints)?;
+    Ok(None)
+}
+
+// Runs the program from the beginning with the arguments of the context,
+// terminating the current one if there is one. With stop_at_entry, it stops
+// at the entry point of the binary, before the code of the user runs
+pub fn start_program(context: &mut ProgramContext, stop_at_entry: bool) -> anyhow::Result<String> {
+    let binary = context.binary.as_ref().unwrap();
+    if !stop_at_entry && !context.breakpoints.iter().any(|b| b.enabled) {
+        anyhow::bail!("Please set at least one enabled breakpoint first");
+    }
+    // Nothing from a previous run can be reused, the new process will be
+    // loaded at a different address
+    if let Some(running_program) = context.running_program.take() {
+        terminate(&running_program, &mut context.queued_events)?;
+    }
+    let (program_args, redirections) = parse_redirections(context.program_args.iter().collect())?;
+    let pid = launch_fork(
+        &binary.binary_path,
+        program_args,
+        &redirections,
+        &context.environment,
+    )?;
+    // The exec can fail. Other children, like the ones of a process
+    // substitution in the shell, must not be mistaken for it
+    match waitpid(pid, None)? {
+        // The trap sent to traced processes after the exec
+        WaitStatus::Stopped(_, SIGTRAP) => {}
+        // The program didn't run yet, so the code comes from exit_child
+        WaitStatus::Exited(_, EXEC_FAILED) => anyhow::bail!("Failed to exec the target"),
+        status if program_finished(status) => return Ok(context.finish_program(status)),
+        status => {
+            // It's still traced, so it can be stopped in any other way
+            let _ = ptrace::kill(pid);
+            let _ = waitpid(pid, None);
+            anyhow::bail!(
+                "The program stopped unexpectedly while starting: {:?}",
+                status
+            );
+        }
+    }
+    // Follow the threads created by the program, and don't leave it
+    // running if the debugger dies
+    ptrace::setoptions(
+        pid,
+        ptrace::Options::PTRACE_O_TRACECLONE | ptrace::Options::PTRACE_O_EXITKILL,
+    )?;
+    let setup =
+        get_range_for_program_source_code(pid.as_raw() as u64, binary).and_then(|proc_map| {
+            let breakpoint_addresses = context
+                .breakpoints
+                .iter()
+                .filter(|breakpoint| breakpoint.enabled)
+                .filter_map(|breakpoint| breakpoint.relative_address(binary).transpose())
+                .map(|relative_address| relative_address_to_virtual(relative_address?, &proc_map))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            Ok((proc_map, breakpoint_addresses))
+        });
+    let (proc_map, breakpoint_addresses) = match setup {
+        Ok(setup) => setup,
+        Err(err) => {
+            ptrace::kill(pid)?;
+            waitpid(pid, None)?;
+            return Err(err);
+        }
+    };
+    let mut running_program = RunningProgram {
+        proc_map,
+        libraries: Vec::new(),
+        set_breakpoints: HashMap::new(),
+        temporary_breakpoint: None,
+        watchpoints: Default::default(),
+        software_watchpoints: Vec::new(),
+        pid,
+        threads: vec![pid],
+        thread: pid,
+        selected_frame: 0,
+        last_status: WaitStatus::StillAlive,
+        pending_signal: None,
+        stop_requested: false,
+        interrupted: false,
+    };
+    for virtual_address in breakpoint_addresses {
+        if let Err(err) = arm_breakpoint(&mut running_program, virtual_address) {
+            terminate(&running_program, &mut context.queued_events)?;
+            return Err(err);
+        }
+    }
+    let status = match run_to_entry_point(
+        &mut running_program,
+        &mut context.queued_events,
+        binary,
+        &context.breakpoints,
+        stop_at_entry,
+    )? {
+        Some(status) => status,
+        // The last status is only set if it stopped at the entry point
+        None if stop_at_entry && running_program.last_status != WaitStatus::StillAlive => {
+            let address = pid.get_regs()?.rip;
+            let prefix = "Stopped at the entry point";
+            if print_location(prefix, &running_program, binary, address).is_err() {
+                print_address(format!("{} {:#x}", prefix, address), address);
+            }
+            output::json_event("stop", vec![("reason", Json::from("entry"))]);
+            context.running_program = Some(running_program);
+            return Ok(String::new());
+        }
+        None => {
+            pid.cont(None)?;
+            wait_for_stop(&mut running_program, &mut context.queued_events)?
+        }
+    };
+    if program_finished(status) {
+        return Ok(context.finish_program(status));
+    }
+    running_program.last_status = status;
+    arm_library_breakpoints(&mut running_program, binary, &context.breakpoints)?;
+    print_stopped_thread(&running_program, binary)?;
+    let message = stop_message(&mut running_program, binary, &mut context.breakpoints)?;
+    context.running_program = Some(running_program);
+    Ok(message)
+}
+
+pub fn run_until(context: &mut ProgramContext, location_str: &str) -> anyhow::Result<String> {
+    if context.running_program.is_none() {
+        anyhow::bail!("You need to run a program first");
+    }
+    let binary = context.binary.as_ref().unwrap(); // If there's a pid, there's a binary
+    let location: Breakpoint = location_str.parse()?;
+    let Some(location) = find_breakpoint(&binary.possible_breakpoints, location)? else {
+        return Ok("Not a valid position".to_owned());
+    };
+    let relative_address = binary.possible_breakpoints[&location];
+    continue_execution(context, Some(relative_address))
+}
+
+// Optionally stops at until_address in addition to the breakpoints
+pub fn continue_execution(
+    context: &mut ProgramContext,
+    until_address: Option<Address>,
+) -> anyhow::Result<String> {
+    if let Some(message) = resume(context, until_address)? {
+        return Ok(message);
+    }
+    let running_program = context.running_program.as_mut().unwrap();
+    let binary = context.binary.as_ref().unwrap(); // If there's a pid, there's a binary
+    print_stopped_thread(running_program, binary)?;
+    stop_message(running_program, binary, &mut context.breakpoints)
+}
+
+// Returns None, without reporting anything, if the program stopped at
+// until_address and there is no breakpoint of the user there. Any other
+// stop is reported, returning the message of the command
+fn resume(
+    context: &mut ProgramContext,
+    until_address: Option<Address>,
+) -> anyhow::Result<Option<String>> {
+    let running_program = context.running_program.as_mut().unwrap();
+    let binary = context.binary.as_ref().unwrap(); // If there's a pid, there's a binary
+    // There's nothing left to resume
+    if program_finished(running_program.last_status) {
+        context.running_program = None;
+        return Ok(Some(String::from(
+            "The program has already finished, use run to start it again",
+        )));
+    }
+    let pid = running_program.thread;
+    if let Some(address) = breakpoint_to_step_over(running_program)? {
+        let status =
+            run_original_breakpoint_instruction(pid, &running_program.set_breakpoints, address)?;
+        if program_finished(status) {
+            return Ok(Some(context.finish_program(status)));
+        }
+        // The original instruction could have modified a watched variable
+        if report_watchpoint(running_program, binary)? {
+            return Ok(Some(String::from("Reached watchpoint")));
+        }
+    }
+    remove_temporary_breakpoint(running_program)?;
+    let until_address = until_address
+        .map(|relative_address| {
+            relative_address_to_virtual(relative_address, &running_program.proc_map)
+        })
+        .transpose()?;
+    if let Some(virtual_address) = until_address {
+        arm_breakpoint(running_program, virtual_address)?;
+        running_program.temporary_breakpoint = Some(virtual_address);
+    }
+    let signal_thread = running_program.thread;
+    let mut signal = running_program.pending_signal.take();
+    running_program.interrupted = false;
+    // Every instruction of every thread is checked with software
+    // watchpoints, the threads run one instruction at a time
+    let single_step = !running_program.software_watchpoints.is_empty();
+    if single_step {
+        for &thread in &running_program.threads {
+            registers::clear_watchpoint_status(thread)?;
+            if thread == signal_thread {
+                thread.step(signal.take())?;
+            } else {
+                thread.step(None)?;
+            }
+        }
+    }
+    loop {
+        let status = if single_step {
+            wait_for_step(running_program, &mut context.queued_events)?
+        } else {
+            for &thread in &running_program.threads {
+                registers::clear_watchpoint_status(thread)?;
+                if thread == signal_thread {
+                    thread.cont(signal.take())?;
+                } else {
+                    thread.cont(None)?;
+                }
+            }
+            wait_for_stop(running_program, &mut context.queued_events)?
+        };
+        if program_finished(status) {
+            return Ok(Some(context.finish_program(status)));
+        }
+        running_program.last_status = status;
+        running_program.selected_frame = 0;
+        let changed = report_watchpoint(running_program, binary)?;
+        // The variable was written without changing its value, or the trap
+        // is the one of a single step, not of a breakpoint
+        let keep_going = !changed
+            && (registers::get_triggered_watchpoint(running_program.thread)?.is_some()
+                || single_step
+                    && matches!(status, WaitStatus::Stopped(_, SIGTRAP))
+                    && !stopped_at_breakpoint(running_program));
+        if keep_going {
+            if single_step {
+                registers::clear_watchpoint_status(running_program.thread)?;
+                running_program.thread.step(None)?;
+            }
+            continue;
+        }
+        // The other threads are still stepping
+        if single_step {
+            stop_other_threads(running_program)?;
+        }
+        if changed {
+            return Ok(Some(String::from("Reached watchpoint")));
+        }
+        // Libraries can also be loaded while running, like with dlopen
+        arm_library_breakpoints(running_program, binary, &context.breakpoints)?;
+        let address = get_last_instruction_address(running_program.thread)?;
+        if stopped_at_breakpoint(running_program)
+            && until_address == Some(address)
+            && !context.breakpoints.iter().any(|breakpoint| {
+                breakpoint.enabled
+                    && breakpoint
+                        .virtual_address(binary, running_program)
+                        .is_ok_and(|virtual_address| virtual_address == Some(address))
+            })
+        {
+            return Ok(None);
+        }
+        print_stopped_thread(running_program, binary)?;
+        return stop_message(running_program, binary, &mut context.breakpoints).map(Some);
+    }
+}
+
+// Temporary breakpoints are deleted once they are hit, and signals are kept
+// to deliver them when continuing
+fn stop_message(
+    running_program: &mut RunningProgram,
+    binary: &LoadedBinary,
+    breakpoints: &mut Vec<UserBreakpoint>,
+) -> anyhow::Result<String> {
+    if running_program.interrupted {
+        output::json_event("stop", vec![("reason", Json::from("interrupt"))]);
+        return Ok(String::from("Use continue to resume it"));
+    }
+    if let WaitStatus::Stopped(_, signal) = running_program.last_status
+        && signal != SIGTRAP
+    {
+        running_program.pending_signal = Some(signal);
+        output::json_event("stop", vec![("reason", Json::from("signal"))]);
+        return Ok(String::from(
+            "The signal will be delivered when continuing, use continue --discard-signal to suppress it",
+        ));
+    }
+    if !stopped_at_breakpoint(running_program) {
+        output::json_event("stop", vec![("reason", Json::from("breakpoint"))]);
+        return Ok(String::from("Reached breakpoint"));
+    }
+    let virtual_address = get_last_instruction_address(running_program.thread)?;
+    // Several breakpoints can share the address, all of them are reported
+    let hit = (0..breakpoints.len())
+        .filter(|&index| {
+            breakpoints[index].enabled
+                && breakpoints[index]
+                    .virtual_address(binary, running_program)
+                    .is_ok_and(|address| address == Some(virtual_address))
+        })
+        .collect::<Vec<_>>();
+    let temporary = hit
+        .iter()
+        .copied()
+        .filter(|&index| breakpoints[index].temporary)
+        .collect::<Vec<_>>();
+    let (dwarf, relative_address) = find_debug_info(running_program, binary, virtual_address);
+    let line_note = match dwarf.get_line_from_address(relative_address) {
+        Ok(stop_line) => hit
+            .iter()
+            .filter_map(|&index| breakpoints[index].requested_line)
+            .find(|&line| line != stop_line.line_number as u64)
+            .map(|line| {
+                format!(
+                    ", it was set at line {} but the program stopped at line {}",
+                    line, stop_line.line_number
+                )
+            })
+            .unwrap_or_default(),
+        Err(_) => String::new(),
+    };
+    let get_numbers = |indexes: &[usize]| {
+        indexes
+            .iter()
+            .map(|&index| breakpoints[index].number)
+            .collect::<Vec<_>>()
+    };
+    let hit_numbers = get_numbers(&hit);
+    let deleted_numbers = get_numbers(&temporary);
+    // In reverse order, so the indexes of the rest don't change
+    for &index in temporary.iter().rev() {
+        breakpoints.remove(index);
+        disarm_breakpoint(running_program, virtual_address)?;
+    }
+    output::json_event(
+        "stop",
+        vec![
+            ("reason", Json::from("breakpoint")),
+            ("breakpoints", hit_numbers.clone().into()),
+            ("deleted", deleted_numbers.clone().into()),
+        ],
+    );
+    let join = |numbers: &[usize]| {
+        numbers
+            .iter()
+            .map(usize::to_string)
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let message = match (hit_numbers.as_slice(), deleted_numbers.as_slice()) {
+        // The breakpoint set by until
+        ([], _) => String::from("Reached breakpoint"),
+        ([number], []) => format!("Reached breakpoint {}", number),
+        ([number], _) => format!(
+            "Reached temporary breakpoint {}, it has been deleted",
+            number
+        ),
+        (_, []) => format!("Reached breakpoints {}", join(&hit_numbers)),
+        (_, [number]) => format!(
+            "Reached breakpoints {}, temporary breakpoint {} has been deleted",
+            join(&hit_numbers),
+            number
+        ),
+        (_, _) => format!(
+            "Reached breakpoints {}, temporary breakpoints {} have been deleted",
+            join(&hit_numbers),
+            join(&deleted_numbers)
+        ),
+    };
+    Ok(message + &line_note)
+}
+
+fn print_stopped_thread(
+    running_program: &RunningProgram,
+    binary: &LoadedBinary,
+) -> anyhow::Result<()> {
+    if running_program.threads.len() > 1 {
+        output::event(
+            "thread",
+            Output::new(format!("[Thread {}]", running_program.thread))
+                .with("thread", running_program.thread.as_raw() as i64),
+        );
+    }
+    if let WaitStatus::Stopped(_, signal) = running_program.last_status
+        && signal != SIGTRAP
+    {
+        // Unlike with traps, the instruction that caused the signal is
+        // the one at rip
+        let address = running_program.thread.get_regs()?.rip;
+        if running_program.interrupted {
+            output::event(
+                "interrupt",
+                Output::new(String::from("Program interrupted")),
+            );
+        } else {
+            output::event(
+                "signal",
+                Output::new(format!("Program received signal {:?}", signal))
+                    .with("signal", format!("{:?}", signal)),
+            );
+        }
+        if print_location(
+            &format!("{:#x} in", address),
+            running_program,
+            binary,
+            address,
+        )
+        .is_err()
+        {
+            print_address(format!("{:#x}", address), address);
+        }
+        return Ok(());
+    }
+    print_source_code_line(running_program, binary)
+}
+
+// Like waitpid for every child, but only for the threads of the program.
+// The events of the other programs are queued
+fn wait_for_program(
+    running_program: &RunningProgram,
+    queued_events: &mut Vec<WaitStatus>,
+    flags: WaitPidFlag,
+) -> nix::Result<WaitStatus> {
+    if let Some(position) = queued_events.iter().position(|status| {
+        status
+            .pid()
+            .is_some_and(|thread| is_program_thread(running_program, thread))
+    }) {
+        return Ok(queued_events.remove(position));
+    }
+    loop {
+        let status = waitpid(None, Some(flags | WaitPidFlag::__WALL))?;
+        match status.pid() {
+            Some(thread) if !is_program_thread(running_program, thread) => {
+                queued_events.push(status)
+            }
+            _ => return Ok(status),
+        }
+    }
+}
+
+// New threads can report events before being added to the program
+fn is_program_thread(running_program: &RunningProgram, thread: Pid) -> bool {
+    thread == running_program.pid
+        || running_program.threads.contains(&thread)
+        || Path::new(&format!("/proc/{}/task/{}", running_program.pid, thread)).exists()
+}
+
+// Set by the signal handler, which can only reach static data. It's only
+// read through the InterruptHandler that installed it
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn handle_interrupt(_: nix::libc::c_int) {
+    INTERRUPTED.store(true, Ordering::Relaxed);
+}
+
+// Ctrl-C stops the program instead of the debugger while it runs. The
+// previous handler is restored when dropped, so Ctrl-C still exits scripts
+struct InterruptHandler {
+    previous: SigAction,
+}
+
+impl InterruptHandler {
+    // Whether Ctrl-C was pressed since the last call
+    fn take_interrupt(&self) -> bool {
+        INTERRUPTED.swap(false, Ordering::Relaxed)
+    }
+
+    fn install() -> anyhow::Result<Self> {
+        INTERRUPTED.store(false, Ordering::Relaxed);
+        // Without SA_RESTART, so waitpid returns when it's pressed
+        let action = SigAction::new(
+            SigHandler::Handler(handle_interrupt),
+            SaFlags::empty(),
+            SigSet::empty(),
+        );
+        let previous = unsafe { sigaction(SIGINT, &action) }?;
+        Ok(Self { previous })
+    }
+}
+
+impl Drop for InterruptHandler {
+    fn drop(&mut self) {
+        let _ = unsafe { sigaction(SIGINT, &self.previous) };
+    }
+}
+
+// The SIGINT of the terminal also reaches the program, unless the debugger
+// was interrupted in some other way. When the program hasn't stopped
+// because of it yet, another one is sent to the whole program, which is
+// merged with the one of the terminal if it's still pending
+fn interrupt(
+    running_program: &mut RunningProgram,
+    queued_events: &mut Vec<WaitStatus>,
+    handler: &InterruptHandler,
+) -> anyhow::Result<Option<WaitStatus>> {
+    if running_program.stop_requested || !handler.take_interrupt() {
+        return Ok(None);
+    }
+    running_program.stop_requested = true;
+    match wait_for_program(running_program, queued_events, WaitPidFlag::WNOHANG)? {
+        WaitStatus::StillAlive => {
+            kill(running_program.pid, SIGINT)?;
+            Ok(None)
+        }
+        status => Ok(Some(status)),
+    }
+}
+
+// A SIGINT is the one of the interrupt if it was sent by the debugger or
+// the debugger was interrupted too. It isn't delivered to the program
+fn take_interrupt(
+    running_program: &mut RunningProgram,
+    handler: &InterruptHandler,
+    signal: Signal,
+) {
+    if signal == SIGINT && (running_program.stop_requested || handler.take_interrupt()) {
+        running_program.stop_requested = false;
+        running_program.interrupted = true;
+    }
+}
+
+// Waits until any thread stops and then stops all the other ones. Thread
+// creations and exits are handled here, so they aren't returned
+fn wait_for_stop(
+    running_program: &mut RunningProgram,
+    queued_events: &mut Vec<WaitStatus>,
+) -> anyhow::Result<WaitStatus> {
+    let handler = InterruptHandler::install()?;
+    loop {
+        let status = match wait_for_program(running_program, queued_events, WaitPidFlag::empty()) {
+            Err(Errno::EINTR) => match interrupt(running_program, queued_events, &handler)? {
+                Some(status) => status,
+                None => continue,
+            },
+            status => status?,
+        };
+        match status {
+            WaitStatus::PtraceEvent(thread, _, event)
+                if event == ptrace::Event::PTRACE_EVENT_CLONE as i32 =>
+            {
+                let new_thread = add_thread(running_program, thread)?;
+                new_thread.cont(None)?;
+                thread.cont(None)?;
+            }
+            WaitStatus::Exited(thread, _) | WaitStatus::Signaled(thread, _, _)
+                if thread == running_program.pid =>
+            {
+                return Ok(status);
+            }
+            WaitStatus::Exited(thread, _) | WaitStatus::Signaled(thread, _, _) => {
+                running_program.threads.retain(|&t| t != thread);
+            }
+            // A new thread can stop before its creation is reported, it
+            // will be resumed when handling the clone event
+            WaitStatus::Stopped(thread, SIGSTOP) if !running_program.threads.contains(&thread) => {
+                running_program.threads.push(thread);
+            }
+            // Like in gdb, signals that programs use in their normal
+            // operation are delivered without stopping
+            WaitStatus::Stopped(
+                thread,
+                signal @ (SIGALRM | SIGCHLD | SIGPROF | SIGURG | SIGWINCH),
+            ) => thread.cont(Some(signal))?,
+            WaitStatus::Stopped(thread, signal) => {
+                take_interrupt(running_program, &handler, signal);
+                running_program.thread = thread;
+                stop_other_threads(running_program)?;
+                return Ok(status);
+            }
+            WaitStatus::PtraceEvent(thread, _, _) => thread.cont(None)?,
+            _ => {}
+        }
+    }
+}
+
+// Like wait_for_stop, but while every thread is single-stepped. The steps
+// of the threads that don't stop go on
+fn wait_for_step(
+    running_program: &mut RunningProgram,
+    queued_events: &mut Vec<WaitStatus>,
+) -> anyhow::Result<WaitStatus> {
+    let handler = InterruptHandler::install()?;
+    loop {
+        let status = match wait_for_program(running_program, queued_events, WaitPidFlag::empty()) {
+            Err(Errno::EINTR) => match interrupt(running_program, queued_events, &handler)? {
+                Some(status) => status,
+                None => continue,
+            },
+            status => status?,
+        };
+        match status {
+            WaitStatus::PtraceEvent(thread, _, event)
+                if event == ptrace::Event::PTRACE_EVENT_CLONE as i32 =>
+            {
+                let new_thread = add_thread(running_program, thread)?;
+                new_thread.step(None)?;
+                thread.step(None)?;
+            }
+            WaitStatus::Exited(thread, _) | WaitStatus::Signaled(thread, _, _)
+                if thread == running_program.pid =>
+            {
+                return Ok(status);
+            }
+            WaitStatus::Exited(thread, _) | WaitStatus::Signaled(thread, _, _) => {
+                running_program.threads.retain(|&t| t != thread);
+            }
+            WaitStatus::Stopped(thread, SIGSTOP) if !running_program.threads.contains(&thread) => {
+                running_program.threads.push(thread);
+            }
+            WaitStatus::Stopped(
+                thread,
+                signal @ (SIGALRM | SIGCHLD | SIGPROF | SIGURG | SIGWINCH),
+            ) => thread.step(Some(signal))?,
+            WaitStatus::Stopped(thread, signal) => {
+                take_interrupt(running_program, &handler, signal);
+                running_program.thread = thread;
+                return Ok(status);
+            }
+            WaitStatus::PtraceEvent(thread, _, _) => thread.step(None)?,
+            _ => {}
+        }
+    }
+}
+
+// Registers the thread created by parent, which is left stopped
+fn add_thread(running_program: &mut RunningProgram, parent: Pid) -> anyhow::Result<Pid> {
+    let new_thread = Pid::from_raw(ptrace::getevent(parent)? as i32);
+    if !running_program.threads.contains(&new_thread) {
+        // New threads start with a SIGSTOP
+        new_thread.wait()?;
+        running_program.threads.push(new_thread);
+    }
+    // Debug registers aren't inherited by new threads
+    for (index, watchpoint) in running_program.watchpoints.iter().enumerate() {
+        if let Some(watchpoint) = watchpoint {
+            let length = watchpoint.type_info.size() / 8;
+            registers::set_watchpoint(new_thread, index, watchpoint.address, length)?;
+        }
+    }
+    Ok(new_thread)
+}
+
+fn stop_other_threads(running_program: &mut RunningProgram) -> anyhow::Result<()> {
+    let threads = running_program.threads.clone();
+    for thread in threads {
+        if thread == running_program.thread {
+            continue;
+        }
+        // Only this thread has to receive the signal, not the whole program
+        let result = unsafe {
+            nix::libc::syscall(
+                nix::libc::SYS_tgkill,
+                running_program.pid.as_raw(),
+                thread.as_raw(),
+                SIGSTOP as i32,
+            )
+        };
+        if result != 0 {
+            anyhow::bail!("Failed to stop thread {}", thread);
+        }
+        loop {
+            match thread.wait()? {
+                WaitStatus::Stopped(_, SIGSTOP) => break,
+                WaitStatus::Exited(_, _) | WaitStatus::Signaled(_, _, _) => {
+                    running_program.threads.retain(|&t| t != thread);
+                    break;
+                }
+                WaitStatus::PtraceEvent(_, _, event)
+                    if event == ptrace::Event::PTRACE_EVENT_CLONE as i32 =>
+                {
+                    add_thread(running_program, thread)?;
+                    thread.cont(None)?;
+                }
+                // The thread hit a breakpoint too, it will hit it again
+                // after being resumed
+                WaitStatus::Stopped(_, SIGTRAP) => {
+                    let mut registers = thread.get_regs()?;
+                    if running_program
+                        .set_breakpoints
+                        .contains_key(&(registers.rip - 1))
+                    {
+                        registers.rip -= 1;
+                        thread.set_regs(registers)?;
+                    }
+                    thread.cont(None)?;
+                }
+                WaitStatus::Stopped(_, signal) => thread.cont(Some(signal))?,
+                _ => thread.cont(None)?,
+            }
+        }
+    }
+    Ok(())
+}
+
+fn program_finished(status: WaitStatus) -> bool {
+    matches!(
+        status,
+        WaitStatus::Exited(_, _) | WaitStatus::Signaled(_, _, _)
+    )
+}
+
+pub fn step_instruction(context: &mut ProgramContext) -> anyhow::Result<String> {
+    if let Some(message) = execute_instruction(context)? {
+        return Ok(message);
+    }
+    let running_program = context.running_program.as_ref().unwrap();
+    let binary = context.binary.as_ref().unwrap(); // If there's a pid, there's a binary
+    let address = running_program.thread.get_regs()?.rip;
+    if print_location(
+        &format!("{:#x} in", address),
+        running_program,
+        binary,
+        address,
+    )
+    .is_err()
+    {
+        print_address(format!("{:#x}", address), address);
+    }
+    Ok("".to_string())
+}
+
+// Executes a single instruction of the current thread. Returns the exit
+// message if that finished the program
+fn execute_instruction(context: &mut ProgramContext) -> anyhow::Result<Option<String>> {
+    let running_program = context
+        .running_program
+        .as_mut()
+        .ok_or(anyhow!("You need to run a program first"))?;
+    let pid = running_program.thread;
+    let status = match breakpoint_to_step_over(running_program)? {
+        Some(address) => {
+            run_original_breakpoint_instruction(pid, &running_program.set_breakpoints, address)?
+        }
+        None => do_step(pid)?,
+    };
+    if program_finished(status) {
+        return Ok(Some(context.finish_program(status)));
+    }
+    remove_temporary_breakpoint(running_program)?;
+    running_program.last_status = status;
+    running_program.interrupted = false;
+    running_program.selected_frame = 0;
+    Ok(None)
+}
+
+// Executes instructions until the line changes, stopping at the start of a
+// statement. With step_over, calls run until they return, with a temporary
+// breakpoint after the call, instead of being stepped into
+pub fn step_line(context: &mut ProgramContext, step_over: bool) -> anyhow::Result<String> {
+    let running_program = context
+        .running_program
+        .as_ref()
+        .ok_or(anyhow!("You need to run a program first"))?;
+    let binary = context.binary.as_ref().unwrap(); // If there's a pid, there's a binary
+    let start_line = current_line(running_program, binary)?;
+    let start_function = current_function(running_program, binary)?;
+    let start_inline_depth = current_inline_depth(running_program, binary)?;
+    loop {
+        let running_program = context.running_program.as_ref().unwrap();
+        let binary = context.binary.as_ref().unwrap();
+        let stack_pointer = running_program.thread.get_regs()?.rsp;
+        let function = current_function(running_program, binary)?;
+        if let Some(message) = execute_instruction(context)? {
+            return Ok(message);
+        }
+        let running_program = context.running_program.as_mut().unwrap();
+        let binary = context.binary.as_ref().unwrap();
+        if let WaitStatus::Stopped(_, signal) = running_program.last_status
+            && signal != SIGTRAP
+        {
+            print_stopped_thread(running_program, binary)?;
+            return stop_message(running_program, binary, &mut context.breakpoints);
+        }
+        // A call pushes the return address and jumps to another function.
+        // Like in gdb, step only goes into functions with line info, so it
+        // doesn't go through the PLT and the libraries. Calls from the shared
+        // libraries are stepped into
+        if let Some(return_address) = pushed_word(running_program.thread, stack_pointer)?
+            && current_function(running_program, binary)? != function
+            && (step_over || current_line(running_program, binary)?.is_none())
+            && map_contains(&running_program.proc_map, return_address)
+        {
+            let relative_address =
+                virtual_address_to_relative(return_address, &running_program.proc_map);
+            // Stopped somewhere else inside the call
+            if let Some(message) = resume(context, Some(relative_address))? {
+                return Ok(message);
+            }
+        }
+        let running_program = context.running_program.as_ref().unwrap();
+        let binary = context.binary.as_ref().unwrap();
+        let line = current_line(running_program, binary)?;
+        // Inlined calls don't have a call instruction, their lines are
+        // skipped until returning to the code that calls them
+        let inside_inlined_call = step_over
+            && current_function(running_program, binary)? == start_function
+            && current_inline_depth(running_program, binary)? > start_inline_depth;
+        if line.is_some()
+            && line != start_line
+            && !inside_inlined_call
+            && at_statement_start(running_program, binary)?
+        {
+            let address = current_address(running_program)?;
+            print_location("Stopped at", running_program, binary, address)?;
+            return Ok("".to_string());
+        }
+    }
+}
+
+// Where the program is stopped, before the trap instruction if it was hit
+fn current_address(running_program: &RunningProgram) -> anyhow::Result<u64> {
+    Ok(if stopped_at_breakpoint(running_program) {
+        get_last_instruction_address(running_program.thread)?
+    } else {
+        running_program.thread.get_regs()?.rip
+    })
+}
+
+fn current_line(
+    running_program: &RunningProgram,
+    binary: &LoadedBinary,
+) -> anyhow::Result<Option<(PathBuf, usize)>> {
+    let (dwarf, address) =
+        find_debug_info(running_program, binary, current_address(running_program)?);
+    Ok(dwarf
+        .get_line_from_address(address)
+        .ok()
+        .map(|line| (line.path, line.line_number)))
+}
+
+// Whether the line table marks the current address as the start of a
+// statement, the ones in the middle of a statement can have another line
+fn at_statement_start(
+    running_program: &RunningProgram,
+    binary: &LoadedBinary,
+) -> anyhow::Result<bool> {
+    let (dwarf, address) =
+        find_debug_info(running_program, binary, current_address(running_program)?);
+    Ok(dwarf.is_statement_start(address))
+}
+
+fn current_function(
+    running_program: &RunningProgram,
+    binary: &LoadedBinary,
+) -> anyhow::Result<Option<u64>> {
+    let (dwarf, address) =
+        find_debug_info(running_program, binary, current_address(running_program)?);
+    Ok(dwarf.get_function_start(address))
+}
+
+// How many inlined calls contain the current address
+fn current_inline_depth(
+    running_program: &RunningProgram,
+    binary: &LoadedBinary,
+) -> anyhow::Result<usize> {
+    let (dwarf, address) =
+        find_debug_info(running_program, binary, current_address(running_program)?);
+    Ok(dwarf.get_inlined_calls(address).len())
+}
+
+// The function being executed, where its return address is stored in the
+// stack and where the frame pointer of the caller is, if it was already
+// saved. The frame is found with the frame pointer, so the function needs
+// to start with the usual push rbp; mov rbp, rsp
+fn find_return_address(
+    running_program: &RunningProgram,
+    binary: &LoadedBinary,
+) -> anyhow::Result<(String, u64, Option<u64>)> {
+    // push rbp; mov rbp, rsp
+    const PROLOGUE: [u8; 4] = [0x55, 0x48, 0x89, 0xe5];
+    let pid = running_program.thread;
+    let registers = pid.get_regs()?;
+    let rip = current_address(running_program)?;
+    let (dwarf, relative_address) = find_debug_info(running_program, binary, rip);
+    let function = dwarf
+        .get_function_from_address(relative_address)
+        .ok_or(anyhow!("The program isn't stopped in a known function"))?
+        .to_owned();
+    // The function starts on the same mapping as the address
+    let start = dwarf.get_function_start(relative_address).unwrap() + rip - relative_address;
+    let code = read_original_word(running_program, start)?.to_ne_bytes();
+    if code[..PROLOGUE.len()] != PROLOGUE {
+        anyhow::bail!(
+            "{} doesn't start with the usual prologue, its frame can't be found",
+            function
+        );
+    }
+    // Before and after pushing rbp, the frame pointer is still the one of
+    // the caller
+    let (return_address_location, frame_pointer_location) = match rip - start {
+        0 => (registers.rsp, None),
+        offset if offset < PROLOGUE.len() as u64 => (registers.rsp + 8, Some(registers.rsp)),
+        _ => (registers.rbp + 8, Some(registers.rbp)),
+    };
+    let return_address = pid.read_word(return_address_location)? as u64;
+    if !is_mapped_code(running_program, return_address) {
+        anyhow::bail!("Couldn't find where {} returns to", function);
+    }
+    Ok((function, return_address_location, frame_pointer_location))
+}
+
+pub fn return_from_function(
+    context: &mut ProgramContext,
+    value: Option<&str>,
+) -> anyhow::Result<String> {
+    let running_program = context
+        .running_program
+        .as_mut()
+        .ok_or(anyhow!("You need to run a program first"))?;
+    let binary = context.binary.as_ref().unwrap(); // If there's a pid, there's a binary
+    let value = value
+        .map(|value| match value.strip_prefix('-') {
+            Some(value) => parse_address(value).map(|value| value.wrapping_neg()),
+            None => parse_address(value),
+        })
+        .transpose()?;
+    let (function, return_address_location, frame_pointer_location) =
+        find_return_address(running_program, binary)?;
+    let pid = running_program.thread;
+    let return_address = pid.read_word(return_address_location)? as u64;
+    let mut registers = pid.get_regs()?;
+    registers.rip = return_address;
+    if let Some(location) = frame_pointer_location {
+        registers.rbp = pid.read_word(location)? as u64;
+    }
+    registers.rsp = return_address_location + 8;
+    if let Some(value) = value {
+        registers.rax = value;
+    }
+    pid.set_regs(registers)?;
+    running_program.selected_frame = 0;
+    if print_location(
+        &format!("{:#x} in", return_address),
+        running_program,
+        binary,
+        return_address,
+    )
+    .is_err()
+    {
+        print_address(format!("{:#x}", return_address), return_address);
+    }
+    Ok(format!("Returned from {}", function))
+}
+
+// Runs until the current function returns and shows the value it returned,
+// read from rax, or from xmm0 for floating point numbers
+pub fn finish_function(context: &mut ProgramContext) -> anyhow::Result<String> {
+    let running_program = context
+        .running_program
+        .as_ref()
+        .ok_or(anyhow!("You need to run a program first"))?;
+    let binary = context.binary.as_ref().unwrap(); // If there's a pid, there's a binary
+    let (function, return_address_location, _) = find_return_address(running_program, binary)?;
+    let return_address = running_program.thread.read_word(return_address_location)? as u64;
+    if !map_contains(&running_program.proc_map, return_address) {
+        anyhow::bail!(
+            "{} returns into a shared library, which isn't supported",
+            function
+        );
+    }
+    let (dwarf, relative_address) = find_debug_info(
+        running_program,
+        binary,
+        running_program.thread.get_regs()?.rip,
+    );
+    let return_type = dwarf.get_return_type(relative_address, context.print_options.max_depth)?;
+    let relative_return_address =
+        virtual_address_to_relative(return_address, &running_program.proc_map);
+    loop {
+        if let Some(message) = resume(context, Some(relative_return_address))? {
+            return Ok(message);
+        }
+        // Recursive calls of the function return to the same address from
+        // deeper frames
+        let stack_pointer = context
+            .running_program
+            .as_ref()
+            .unwrap()
+            .thread
+            .get_regs()?
+            .rsp;
+        if stack_pointer > return_address_location {
+            break;
+        }
+    }
+    let running_program = context.running_program.as_ref().unwrap();
+    let binary = context.binary.as_ref().unwrap();
+    let address = current_address(running_program)?;
+    if print_location("Returned to", running_program, binary, address).is_err() {
+        print_address(format!("Returned to {:#x}", address), address);
+    }
+    let Some((type_info, type_name)) = return_type else {
+        return Ok(format!("{} returned (no return value)", function));
+    };
+    let pid = running_program.thread;
+    let cannot_show = || {
+        format!(
+            "{} returned a {}, values of that type can't be shown",
+            function, type_name
+        )
+    };
+    let value = match &type_info {
+        // Wider values don't fit in a register of the VariableInfo. long
+        // double is returned in st0 and 128 bit integers in rdx:rax
+        dwarf::TypeInfo::Base { base_type, size } if *size > 64 => {
+            let bytes = match base_type {
+                dwarf::BaseType::Float if type_name == "long double" => {
+                    registers::get_st_registers(pid)?[0].to_le_bytes()
+                }
+                dwarf::BaseType::Signed | dwarf::BaseType::Unsigned if *size <= 128 => {
+                    let registers = pid.get_regs()?;
+                    (registers.rax as u128 | (registers.rdx as u128) << 64).to_le_bytes()
+                }
+                _ => return Ok(cannot_show()),
+            };
+            let radix = context.print_options.radix;
+            return Ok(format!(
+                "{} returned {} ({})",
+                function,
+                format_wide_base_value(&bytes, base_type, *size, radix),
+                type_name
+            ));
+        }
+        dwarf::TypeInfo::Base {
+            base_type: dwarf::BaseType::Float,
+            ..
+        } => registers::get_xmm_registers(pid)?[0] as u64,
+        dwarf::TypeInfo::Base { .. }
+        | dwarf::TypeInfo::Enum { .. }
+        | dwarf::TypeInfo::Pointer { .. } => pid.get_regs()?.rax,
+        _ => return Ok(cannot_show()),
+    };
+    let variable = dwarf::VariableInfo {
+        location: dwarf::VariableLocation::Value(value),
+        type_info,
+        type_name,
+    };
+    Ok(format!(
+        "{} returned {} ({})",
+        function,
+        format_variable(pid, &variable, context.print_options)?,
+        variable.type_name
+    ))
+}
+
+pub fn kill_program(context: &mut ProgramContext) -> anyhow::Result<String> {
+    let running_program = context
+        .running_program
+        .take()
+        .ok_or(anyhow!("There is no program running"))?;
+    terminate(&running_program, &mut context.queued_events)?;
+    Ok(String::from("Program killed"))
+}
+
+pub fn terminate(
+    running_program: &RunningProgram,
+    queued_events: &mut Vec<WaitStatus>,
+) -> anyhow::Result<()> {
+    ptrace::kill(running_program.pid)?;
+    // The exit of the main thread is reported after the other ones
+    loop {
+        let status = wait_for_program(running_program, queued_events, WaitPidFlag::empty())?;
+        if program_finished(status) && status.pid() == Some(running_program.pid) {
+            return Ok(());
+        }
+    }
+}
+
+pub fn get_last_instruction_address(tracee: impl Tracee) -> anyhow::Result<u64> {
+    let registers = tracee.get_regs()?;
+    // We subtract an extra 1 because the rip was already increased by the trap instruction
+    Ok(registers.rip - 1)
+}
+
+// The breakpoint whose original instruction the thread runs next: the one
+// it stopped at, or the one at its address when it didn't execute the trap
+// yet, like after a step, a signal or disabling and enabling the breakpoint
+fn breakpoint_to_step_over(
+    running_program: &RunningProgram,
+) -> anyhow::Result<Option<Address>> {
+    if stopped_at_breakpoint(running_program) {
+        return Ok(Some(get_last_instruction_address(running_program.thread)?));
+    }
+    let address = running_program.thread.get_regs()?.rip;
+    Ok(running_program
+        .set_breakpoints
+        .contains_key(&address)
+        .then_some(address))
+}
+
+fn run_original_breakpoint_instruction(
+    tracee: impl Tracee,
+    set_breakpoints: &HashMap<Address, SetBreakpoint>,
+    address: Address,
+) -> anyhow::Result<WaitStatus> {
+    let Some(original_word) = set_breakpoints
+        .get(&address)
+        .map(|breakpoint| breakpoint.original_word)
+    else {
+        return do_step(tracee);
+    };
+    let mut registers = tracee.get_regs()?;
+    registers.rip = address;
+    tracee.set_regs(registers)?;
+    restore_original_instruction(tracee, address, original_word)?;
+    let status = do_step(tracee)?;
+    if program_finished(status) {
+        return Ok(status);
+    }
+    // Even if the step jumped back to the same address, the trap belongs
+    // at the breakpoint and not at the new rip
+    write_trap_instruction(tracee, address)?;
+    Ok(status)
+}
+
+// The word that the instruction just executed pushed to the stack, like the
+// return address of a call. The stack pointer is the one before executing it
+fn pushed_word(tracee: impl Tracee, stack_pointer: u64) -> anyhow::Result<Option<u64>> {
+    let stack_pointer_after = tracee.get_regs()?.rsp;
+    if stack_pointer_after != stack_pointer.wrapping_sub(8) {
+        return Ok(None);
+    }
+    Ok(Some(tracee.read_word(stack_pointer_after)? as u64))
+}
+
+// Files used instead of the standard streams of the debugged program
+#[derive(Default)]
+struct Redirections {
+    stdin: Option<PathBuf>,
+    stdout: Option<PathBuf>,
+    stderr: Option<PathBuf>,
+}
+
+// Separates the redirections, like "< in.txt" or ">out.txt", from the
+// arguments of the program
+fn parse_redirections(args: Vec<&String>) -> anyhow::Result<(Vec<&String>, Redirections)> {
+    let mut program_args = Vec::new();
+    let mut redirections = Redirections::default();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let (target, file) = if let Some(file) = arg.strip_prefix("2>") {
+            (&mut redirections.stderr, file)
+        } else if let Some(file) = arg.strip_prefix('>') {
+            (&mut redirections.stdout, file)
+        } else if let Some(file) = arg.strip_prefix('<') {
+            (&mut redirections.stdin, file)
+        } else {
+            program_args.push(arg);
+            continue;
+        };
+        let file = if file.is_empty() {
+            args.next()
+                .ok_or(anyhow!("Missing the file of the redirection \"{}\"", arg))?
+        } else {
+            file
+        };
+        *target = Some(PathBuf::from(file));
+    }
+    Ok((program_args, redirections))
+}
+
+fn launch_fork(
+    executable: &Path,
+    args: Vec<&String>,
+    redirections: &Redirections,
+    environment: &BTreeMap<String, String>,
+) -> anyhow::Result<Pid> {
+    let args = args
+        .iter()
+        .map(|arg| CString::new(arg.as_str()).unwrap())
+        .collect::<Vec<_>>();
+    let mut variables = std::env::vars_os()
+        .map(|(key, value)| (key.to_string_lossy().into_owned(), value))
+        .collect::<BTreeMap<_, _>>();
+    variables.extend(
+        environment
+            .iter()
+            .map(|(key, value)| (key.clone(), value.into())),
+    );
+    let variables = variables
+        .into_iter()
+        .map(|(key, value)| {
+            CString::new(format!("{}={}", key, value.to_string_lossy()))
+                .context("Environment variables can't contain null characters")
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    // The files are opened before forking to report any errors here
+    let mut streams = Vec::new();
+    if let Some(path) = &redirections.stdin {
+        let file = fs::File::open(path).with_context(|| format!("Couldn't open {:?}", path))?;
+        streams.push((file, io::stdin().as_raw_fd()));
+    }
+    for (path, fd) in [
+        (&redirections.stdout, io::stdout().as_raw_fd()),
+        (&redirections.stderr, io::stderr().as_raw_fd()),
+    ] {
+        if let Some(path) = path {
+            let file =
+                fs::File::create(path).with_context(|| format!("Couldn't create {:?}", path))?;
+            streams.push((file, fd));
+        }
+    }
+    let executable = CString::new(executable.as_os_str().as_bytes())
+        .context("The path of the binary can't contain null characters")?;
+    match unsafe { fork() }? {
+        ForkResult::Child => {
+            for (file, fd) in &streams {
+                if dup2(file.as_raw_fd(), *fd).is_err() {
+                    exit_child("Failed to redirect the standard streams");
+                }
+            }
+            if traceme().is_err() {
+                exit_child("Failed to trace the program");
+            }
+            let Err(err) = execve(&executable, &args, &variables);
+            exit_child(err.desc())
+        }
+        ForkResult::Parent { child: pid } => Ok(pid),
+    }
+}
+
+// The exit code of the child when it couldn't become the program, the same
+// one shells use
+const EXEC_FAILED: i32 = 127;
+
+// Unwinding in the child would run the code of the debugger in a copy of it,
+// so it just reports the reason and exits
+fn exit_child(reason: &str) -> ! {
+    let message = format!("Failed to exec the target: {}\n", reason);
+    let _ = nix::unistd::write(io::stderr(), message.as_bytes());
+    unsafe { nix::libc::_exit(EXEC_FAILED) }
+}
+
+fn do_step(tracee: impl Tracee) -> anyhow::Result<WaitStatus> {
+    tracee.step(None)?;
+    // Only this thread is running, the rest stay stopped
+    tracee.wait()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        breakpoints::add_breakpoint_owner,
+        tracee::mock::{CODE, CODE_ADDRESS, MockTracee},
+    };
+
+    const STACK_POINTER: u64 = 0x7ffc_0000_1000;
+
+    // The trap is removed to run the instruction that was there, and written
+    // again after the step
+    #[test]
+    fn steps_over_a_breakpoint_with_the_original_instruction() {
+        // After the trap of the breakpoint
+        let tracee =
+            MockTracee::with_code(CODE_ADDRESS, &CODE).stopped_at(CODE_ADDRESS + 1, STACK_POINTER);
+        let mut set_breakpoints = HashMap::new();
+        add_breakpoint_owner(&mut set_breakpoints, &tracee, CODE_ADDRESS).unwrap();
+        assert_eq!(get_last_instruction_address(&tracee).unwrap(), CODE_ADDRESS);
+        let status =
+            run_original_breakpoint_instruction(&tracee, &set_breakpoints, CODE_ADDRESS).unwrap();
+        assert!(matches!(status, WaitStatus::Stopped(_, SIGTRAP)));
+        assert_eq!(*tracee.executed.borrow(), [0x55]);
+        let registers = tracee.get_regs().unwrap();
+        assert_eq!(registers.rip, CODE_ADDRESS + 1);
+        assert_eq!(registers.rsp, STACK_POINTER - 8);
+        assert_eq!(tracee.byte(CODE_ADDRESS), 0xcc);
+    }
+
+    // Addresses without a breakpoint are stepped as they are
+    #[test]
+    fn steps_addresses_without_breakpoints() {
+        let tracee =
+            MockTracee::with_code(CODE_ADDRESS, &CODE).stopped_at(CODE_ADDRESS + 4, STACK_POINTER);
+        run_original_breakpoint_instruction(&tracee, &HashMap::new(), CODE_ADDRESS + 4).unwrap();
+        assert_eq!(*tracee.executed.borrow(), [0xb8]);
+        assert_eq!(tracee.get_regs().unwrap().rip, CODE_ADDRESS + 9);
+        assert!(tracee.wait().is_err());
+    }
+
+    // next and step find the calls by the return address they push
+    #[test]
+    fn finds_the_return_address_pushed_by_a_call() {
+        // call 0x401010; nop
+        let mut code = vec![0xe8, 0x0b, 0x00, 0x00, 0x00, 0x90];
+        code.resize(16, 0x90);
+        code.extend(CODE);
+        let tracee =
+            MockTracee::with_code(CODE_ADDRESS, &code).stopped_at(CODE_ADDRESS, STACK_POINTER);
+        do_step(&tracee).unwrap();
+        assert_eq!(tracee.get_regs().unwrap().rip, CODE_ADDRESS + 16);
+        assert_eq!(
+            pushed_word(&tracee, STACK_POINTER).unwrap(),
+            Some(CODE_ADDRESS + 5)
+        );
+        // push rbp pushes a word too, the function is checked by the callers
+        do_step(&tracee).unwrap();
+        assert_eq!(
+            pushed_word(&tracee, STACK_POINTER - 8).unwrap(),
+            Some(0x7fff_0000_1234)
+        );
+        // mov rbp, rsp
+        do_step(&tracee).unwrap();
+        assert_eq!(pushed_word(&tracee, STACK_POINTER - 16).unwrap(), None);
+        assert_eq!(*tracee.executed.borrow(), [0xe8, 0x55, 0x48]);
+    }
+}
@@ -0,0 +1,243 @@
+use anyhow::anyhow;
+use nix::libc::user_regs_struct;
+use std::fs;
+
+use crate::{
+    LoadedBinary, ProgramContext, RunningProgram,
+    binary::{find_debug_info, virtual_address_to_relative},
+    execution::get_last_instruction_address,
+    output::{self, Json, Output},
+    registers::{self, Registers},
+    tracee::Tracee,
+};
+
+pub fn show_backtrace(context: &ProgramContext) -> anyhow::Result<Output> {
+    let running_program = context
+        .running_program
+        .as_ref()
+        .ok_or(anyhow!("You need to run a program first"))?;
+    let binary = context.binary.as_ref().unwrap(); // If there's a pid, there's a binary
+    let frames = get_frames(running_program, binary)?
+        .iter()
+        .enumerate()
+        .map(|(index, frame)| format_frame(running_program, binary, index, frame))
+        .collect::<Vec<_>>();
+    let text = frames
+        .iter()
+        .map(|frame| frame.text.as_str())
+        .collect::<Vec<_>>()
+        .join("\n");
+    let frames = frames
+        .into_iter()
+        .map(|frame| Json::object(frame.fields))
+        .collect::<Vec<_>>();
+    Ok(Output::new(text).with("frames", Json::Array(frames)))
+}
+
+pub fn select_frame(context: &mut ProgramContext, index: usize) -> anyhow::Result<Output> {
+    let running_program = context
+        .running_program
+        .as_mut()
+        .ok_or(anyhow!("You need to run a program first"))?;
+    let binary = context.binary.as_ref().unwrap(); // If there's a pid, there's a binary
+    let frames = get_frames(running_program, binary)?;
+    let frame = frames
+        .get(index)
+        .ok_or(anyhow!("There is no frame {}", index))?;
+    running_program.selected_frame = index;
+    Ok(format_frame(running_program, binary, index, frame))
+}
+
+pub fn format_frame(
+    running_program: &RunningProgram,
+    binary: &LoadedBinary,
+    index: usize,
+    frame: &Frame,
+) -> Output {
+    let address = frame.registers.rip;
+    let (dwarf, relative_address) = find_debug_info(running_program, binary, address);
+    let inlined_calls = dwarf.get_inlined_calls(relative_address);
+    let inlined = frame.inline_depth < inlined_calls.len();
+    let function = match inlined_calls.get(frame.inline_depth) {
+        Some(inlined_call) => inlined_call.function.as_str(),
+        None => dwarf
+            .get_function_from_address(relative_address)
+            .unwrap_or("??"),
+    };
+    // The callers of inlined code are stopped where they call it
+    let line_pos = match frame.inline_depth {
+        0 => dwarf.get_line_from_address(relative_address).ok(),
+        depth => inlined_calls[depth - 1].call_position.clone(),
+    };
+    let suffix = if inlined { " [inlined]" } else { "" };
+    let output = match line_pos {
+        Some(line_pos) => Output::new(format!(
+            "#{} {:#x} in {} ({}:{}){}",
+            index,
+            address,
+            function,
+            line_pos.path.to_string_lossy(),
+            line_pos.line_number,
+            suffix
+        ))
+        .with("index", index)
+        .with("address", address)
+        .with("function", function)
+        .with("file", line_pos.path.to_string_lossy().into_owned())
+        .with("line", line_pos.line_number),
+        None => Output::new(format!(
+            "#{} {:#x} in {}{}",
+            index, address, function, suffix
+        ))
+        .with("index", index)
+        .with("address", address)
+        .with("function", function),
+    };
+    output.with("inlined", inlined)
+}
+
+// A frame of the backtrace. Inlined calls don't have a frame of their own,
+// they share the registers of the function they were inlined into
+#[derive(Clone, Copy)]
+pub struct Frame {
+    registers: user_regs_struct,
+    // How many inlined calls at the address are inside of this one, the
+    // innermost code is at depth 0
+    inline_depth: usize,
+}
+
+// Registers of the innermost frame and its callers, found by following the
+// frame pointers. Only the registers needed to find the variables of each
+// frame (rip, rsp and rbp) are recovered
+pub fn get_frames(
+    running_program: &RunningProgram,
+    binary: &LoadedBinary,
+) -> anyhow::Result<Vec<Frame>> {
+    // Avoid looping forever if the chain is corrupted
+    const MAX_FRAMES: usize = 256;
+    let pid = running_program.thread;
+    let mut registers = pid.get_regs()?;
+    let mut frames = Vec::new();
+    let code = &running_program.proc_map.address_range;
+    loop {
+        let (dwarf, relative_address) = find_debug_info(running_program, binary, registers.rip);
+        let inlined_calls = dwarf.get_inlined_calls(relative_address).len();
+        frames.extend((0..=inlined_calls).map(|inline_depth| Frame {
+            registers,
+            inline_depth,
+        }));
+        if frames.len() >= MAX_FRAMES || registers.rbp == 0 {
+            break;
+        }
+        let Ok(return_address) = pid.read_word(registers.rbp + 8) else {
+            break;
+        };
+        let return_address = return_address as u64;
+        // The end of the chain is usually outside of the binary, in libc
+        if return_address <= code.begin || return_address > code.end {
+            break;
+        }
+        // The caller is still executing the call instruction
+        let caller_address = return_address - 1;
+        let relative_address =
+            virtual_address_to_relative(caller_address, &running_program.proc_map);
+        if binary
+            .dwarf
+            .get_function_from_address(relative_address)
+            .is_none()
+        {
+            break;
+        }
+        let caller_rbp = pid.read_word(registers.rbp)? as u64;
+        registers.rip = caller_address;
+        registers.rsp = registers.rbp + 16;
+        registers.rbp = caller_rbp;
+    }
+    Ok(frames)
+}
+
+// The XMM registers of the callers can't be recovered, the current ones are
+// used for every frame
+pub fn get_selected_frame(
+    running_program: &RunningProgram,
+    binary: &LoadedBinary,
+) -> anyhow::Result<Registers> {
+    let general = if running_program.selected_frame == 0 {
+        running_program.thread.get_regs()?
+    } else {
+        get_frames(running_program, binary)?
+            .get(running_program.selected_frame)
+            .map(|frame| frame.registers)
+            .ok_or(anyhow!("The selected frame doesn't exist anymore"))?
+    };
+    Ok(Registers {
+        general,
+        xmm: registers::get_xmm_registers(running_program.thread)?,
+    })
+}
+
+pub fn print_source_code_line(
+    running_program: &RunningProgram,
+    binary: &LoadedBinary,
+) -> Result<(), anyhow::Error> {
+    let address = get_last_instruction_address(running_program.thread)?;
+    print_location("Breakpoint at", running_program, binary, address)
+}
+
+pub fn print_location(
+    prefix: &str,
+    running_program: &RunningProgram,
+    binary: &LoadedBinary,
+    virtual_address: u64,
+) -> anyhow::Result<()> {
+    let (dwarf, address) = find_debug_info(running_program, binary, virtual_address);
+    let line_pos = dwarf.get_line_from_address(address)?;
+    let location = format!(
+        "{}:{}",
+        line_pos.path.to_string_lossy(),
+        line_pos.line_number
+    );
+    // Inlined code is reported as the function it comes from, along with
+    // the one it's inlined into
+    let inlined_calls = dwarf.get_inlined_calls(address);
+    let inlined_call = inlined_calls.first().map(|call| call.function.as_str());
+    let caller = match inlined_calls.get(1) {
+        Some(call) => Some(call.function.as_str()),
+        None => dwarf.get_function_from_address(address),
+    };
+    let mut text = match (inlined_call, caller) {
+        (Some(inlined), Some(caller)) => {
+            format!(
+                "{} {}, inlined into {} ({})",
+                prefix, inlined, caller, location
+            )
+        }
+        (Some(function), None) | (None, Some(function)) => {
+            format!("{} {} ({})", prefix, function, location)
+        }
+        (None, None) => format!("{} {}", prefix, location),
+    };
+    let function = inlined_call.or(caller);
+    // The sources aren't always available
+    if let Ok(source) = fs::read_to_string(&line_pos.path)
+        && let Some(line) = source.lines().nth(line_pos.line_number - 1)
+    {
+        text += &format!("\n{}\t{}", line_pos.line_number, line);
+    }
+    let mut output = Output::new(text).with("address", virtual_address);
+    if let Some(function) = function {
+        output = output.with("function", function);
+    }
+    output::event(
+        "location",
+        output
+            .with("file", line_pos.path.to_string_lossy().into_owned())
+            .with("line", line_pos.line_number),
+    );
+    Ok(())
+}
+
+// For the locations without debug info
+pub fn print_address(text: String, address: u64) {
+    output::event("location", Output::new(text).with("address", address));
+}
@@ -0,0 +1,341 @@
+use anyhow::{Context, anyhow};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    BreakpointLocation, LoadedBinary, ProgramContext, RunningProgram,
+    binary::{
+        find_debug_info, find_frame_debug_info, is_mapped_code, load_libraries,
+        relative_address_to_virtual,
+    },
+    breakpoints::{find_breakpoint, find_breakpoint_file, find_function_breakpoint, parse_address},
+    exit_message,
+    frames::get_selected_frame,
+    print::format_variable,
+    registers,
+    tracee::Tracee,
+};
+
+pub fn show_inferiors(context: &ProgramContext) -> String {
+    let describe = |binary: &Option<LoadedBinary>, running_program: &Option<RunningProgram>| {
+        let binary = match binary {
+            Some(binary) => binary.binary_path.to_string_lossy().into_owned(),
+            None => String::from("no binary"),
+        };
+        match running_program {
+            Some(running_program) => format!("{}, process {}", binary, running_program.pid),
+            None => binary,
+        }
+    };
+    let selected = describe(&context.binary, &context.running_program);
+    if context.inferiors.is_empty() {
+        return format!("* 1: {}", selected);
+    }
+    context
+        .inferiors
+        .iter()
+        .enumerate()
+        .map(|(index, inferior)| match inferior {
+            Some(inferior) => format!(
+                "  {}: {}",
+                index + 1,
+                describe(&inferior.binary, &inferior.running_program)
+            ),
+            None => format!("* {}: {}", index + 1, selected),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// The pending breakpoints first, they don't have a number yet
+pub fn show_breakpoints(context: &ProgramContext) -> String {
+    context
+        .pending_breakpoints
+        .iter()
+        .map(|pending| {
+            format!(
+                "pending: {}{}",
+                pending.location,
+                if pending.temporary {
+                    " (temporary)"
+                } else {
+                    ""
+                }
+            )
+        })
+        .chain(context.breakpoints.iter().map(|breakpoint| {
+            format!(
+                "{}: {}{}{}",
+                breakpoint.number,
+                match &breakpoint.location {
+                    BreakpointLocation::Line(location) => format!(
+                        "{}:{}",
+                        location.file.to_string_lossy(),
+                        location.line_number
+                    ),
+                    BreakpointLocation::Address(address) => format!("*{:#x}", address),
+                    BreakpointLocation::LibraryLine { library, line } => format!(
+                        "{}:{} (in {})",
+                        line.file.to_string_lossy(),
+                        line.line_number,
+                        library.to_string_lossy()
+                    ),
+                },
+                if breakpoint.temporary {
+                    " (temporary)"
+                } else {
+                    ""
+                },
+                if breakpoint.enabled {
+                    ""
+                } else {
+                    " (disabled)"
+                }
+            )
+        }))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+pub fn show_registers(context: &ProgramContext) -> anyhow::Result<String> {
+    let running_program = context
+        .running_program
+        .as_ref()
+        .ok_or(anyhow!("You need to run a program first"))?;
+    let regs = running_program.thread.get_regs()?;
+    Ok(registers::REGISTER_NAMES
+        .iter()
+        .map(|name| {
+            let value = registers::get_register_value_by_name(&regs, name).unwrap();
+            format!("{:<8}{:#018x} {}", name, value, value)
+        })
+        .collect::<Vec<_>>()
+        .join("\n"))
+}
+
+// The arguments of the function with args, the rest of its variables
+// otherwise
+pub fn show_variables(context: &ProgramContext, args: bool) -> anyhow::Result<String> {
+    let running_program = context
+        .running_program
+        .as_ref()
+        .ok_or(anyhow!("You need to run a program first"))?;
+    let binary = context.binary.as_ref().unwrap(); // If there's a pid, there's a binary
+    let pid = running_program.thread;
+    let frame = get_selected_frame(running_program, binary)?;
+    let (dwarf, load_bias) = find_frame_debug_info(running_program, binary, &frame);
+    let locals =
+        dwarf.get_local_variables(&frame, load_bias, args, context.print_options.max_depth)?;
+    if locals.is_empty() {
+        return Ok(String::from(if args { "No args" } else { "No locals" }));
+    }
+    Ok(locals
+        .into_iter()
+        .map(|(name, variable)| {
+            let value = match variable {
+                Ok(variable) => format_variable(pid, &variable, context.print_options)
+                    .unwrap_or_else(|err| format!("<{}>", err)),
+                Err(err) => format!("<{}>", err),
+            };
+            format!("{} = {}", name, value)
+        })
+        .collect::<Vec<_>>()
+        .join("\n"))
+}
+
+pub fn show_program(context: &ProgramContext) -> String {
+    match (&context.running_program, context.last_exit) {
+        (Some(running_program), _) => format!(
+            "Process {} is stopped in thread {}",
+            running_program.pid, running_program.thread
+        ),
+        (None, Some(status)) => exit_message(status),
+        (None, None) => String::from("The program hasn't been run yet"),
+    }
+}
+
+// Each row is shown as it is in the DWARF, a line can have several of them
+// and they don't have to be in order
+pub fn show_line_table(context: &ProgramContext, file: &str) -> anyhow::Result<String> {
+    let binary = context
+        .binary
+        .as_ref()
+        .ok_or(anyhow!("Please load a binary first"))?;
+    let Some(path) = find_breakpoint_file(&binary.possible_breakpoints, Path::new(file))? else {
+        anyhow::bail!("There is no code from {} in the binary", file);
+    };
+    let rows = binary
+        .dwarf
+        .get_line_table(&path)?
+        .into_iter()
+        .map(|row| {
+            let flags = [(row.is_stmt, "is_stmt"), (row.prologue_end, "prologue_end")]
+                .into_iter()
+                .filter(|(set, _)| *set)
+                .map(|(_, flag)| flag)
+                .collect::<Vec<_>>();
+            let mut text = format!("{:#x} line {}", row.address, row.line_number);
+            if !flags.is_empty() {
+                text += &format!(" ({})", flags.join(", "));
+            }
+            text
+        })
+        .collect::<Vec<_>>();
+    Ok(format!("{}:\n{}", path.to_string_lossy(), rows.join("\n")))
+}
+
+// Sorted by the address the libraries are loaded at, the lowest address of
+// their mappings
+pub fn show_shared_libraries(context: &mut ProgramContext) -> anyhow::Result<String> {
+    let running_program = context
+        .running_program
+        .as_mut()
+        .ok_or(anyhow!("You need to run a program first"))?;
+    let binary = context.binary.as_ref().unwrap(); // If there's a pid, there's a binary
+    load_libraries(running_program, binary, &context.breakpoints)?;
+    let maps = rsprocmaps::from_pid(running_program.pid.as_raw())
+        .context("Couldn't read the memory maps of the program")?;
+    let mut libraries: BTreeMap<PathBuf, (u64, bool)> = BTreeMap::new();
+    for map in maps.filter_map(Result::ok) {
+        let rsprocmaps::Pathname::Path(path) = &map.pathname else {
+            continue;
+        };
+        let path = PathBuf::from(path);
+        if path == binary.binary_path {
+            continue;
+        }
+        let base = libraries
+            .entry(path)
+            .or_insert((map.address_range.begin, false));
+        base.0 = base.0.min(map.address_range.begin);
+        // Other mapped files, like fonts or locales, don't have code
+        base.1 |= map.permissions.executable;
+    }
+    let mut libraries = libraries
+        .into_iter()
+        .filter(|(_, (_, executable))| *executable)
+        .map(|(path, (base, _))| {
+            let has_debug_info = running_program
+                .libraries
+                .iter()
+                .any(|library| library.path == path && !library.possible_breakpoints.is_empty());
+            (base, path, has_debug_info)
+        })
+        .collect::<Vec<_>>();
+    if libraries.is_empty() {
+        return Ok(String::from("No shared libraries loaded"));
+    }
+    libraries.sort();
+    Ok(libraries
+        .into_iter()
+        .map(|(base, path, has_debug_info)| {
+            format!(
+                "{:#x} {}{}",
+                base,
+                path.to_string_lossy(),
+                if has_debug_info {
+                    ""
+                } else {
+                    " (no debug info)"
+                }
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n"))
+}
+
+// Sorted by name, the functions without line info only show their address
+pub fn show_functions(context: &ProgramContext, filter: Option<&str>) -> anyhow::Result<String> {
+    let binary = context
+        .binary
+        .as_ref()
+        .ok_or(anyhow!("Please load a binary first"))?;
+    let mut functions = binary
+        .dwarf
+        .get_functions()
+        .into_iter()
+        .filter(|(name, _)| filter.is_none_or(|filter| name.contains(filter)))
+        .map(
+            |(name, address)| match binary.dwarf.get_line_from_address(address) {
+                Ok(line_pos) => format!(
+                    "{} ({}:{})",
+                    name,
+                    line_pos.path.to_string_lossy(),
+                    line_pos.line_number
+                ),
+                Err(_) => format!("{} ({:#x})", name, address),
+            },
+        )
+        .collect::<Vec<_>>();
+    functions.sort();
+    functions.dedup();
+    if functions.is_empty() {
+        return Ok(String::from("No functions found"));
+    }
+    Ok(functions.join("\n"))
+}
+
+// Addresses are the ones in the binary, like with breakpoint *address,
+// unless they are inside the memory of the running program
+pub fn show_line_info(context: &ProgramContext, location: &str) -> anyhow::Result<String> {
+    let binary = context
+        .binary
+        .as_ref()
+        .ok_or(anyhow!("Please load a binary first"))?;
+    if let Some(address_str) = location.strip_prefix('*') {
+        let address = parse_address(address_str)?;
+        let (dwarf, relative_address) = match &context.running_program {
+            Some(running_program)
+                if !binary.dwarf.is_code_address(address)
+                    && is_mapped_code(running_program, address) =>
+            {
+                find_debug_info(running_program, binary, address)
+            }
+            _ => (&binary.dwarf, address),
+        };
+        let line_pos = dwarf.get_line_from_address(relative_address)?;
+        let mut message = format!(
+            "Address {:#x} is at {}:{}",
+            address,
+            line_pos.path.to_string_lossy(),
+            line_pos.line_number
+        );
+        if let Some(function) = dwarf.get_function_from_address(relative_address) {
+            message += &format!(" in {}", function);
+        }
+        return Ok(message);
+    }
+    let requested = if location.contains(':') {
+        location.parse()?
+    } else {
+        find_function_breakpoint(&binary.dwarf, location)?
+    };
+    let requested_line = requested.line_number;
+    let Some(line) = find_breakpoint(&binary.possible_breakpoints, requested)? else {
+        anyhow::bail!("There is no code at or after {}", location);
+    };
+    let address = binary.possible_breakpoints[&line];
+    let mut message = if line.line_number == requested_line {
+        String::new()
+    } else {
+        format!("Line {} has no code. ", requested_line)
+    };
+    message += &format!(
+        "Line {} of {} is at address {:#x}",
+        line.line_number,
+        line.file.to_string_lossy(),
+        address
+    );
+    if let Some(function) = binary.dwarf.get_function_from_address(address) {
+        message += &format!(" in {}", function);
+    }
+    if let Some(running_program) = &context.running_program {
+        message += &format!(
+            ", {:#x} in the running program",
+            relative_address_to_virtual(address, &running_program.proc_map)?
+        );
+    }
+    Ok(message)
+}
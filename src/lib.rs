@@ -0,0 +1,626 @@
+use anyhow::{Context, anyhow};
+use nix::{
+    sys::{signal::Signal, wait::WaitStatus},
+    unistd::Pid,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+    str::FromStr,
+};
+
+mod binary;
+mod breakpoints;
+pub mod dap;
+mod dwarf;
+mod execution;
+mod frames;
+mod info;
+pub mod output;
+mod print;
+mod registers;
+mod tracee;
+mod watchpoints;
+
+use binary::{load_binary, relative_address_to_virtual, reload_binary};
+use breakpoints::{
+    add_numbered_breakpoint, add_user_breakpoint, change_breakpoint_enabled, load_breakpoints,
+    save_breakpoints,
+};
+use dwarf::DwarfInfo;
+use execution::{
+    continue_execution, finish_function, kill_program, return_from_function, run_until,
+    start_program, step_instruction, step_line,
+};
+use frames::{select_frame, show_backtrace};
+use info::{
+    show_breakpoints, show_functions, show_inferiors, show_line_info, show_line_table,
+    show_program, show_registers, show_shared_libraries, show_variables,
+};
+use output::{Json, Output};
+use print::print_variable;
+use watchpoints::add_watchpoint;
+
+pub use print::{PrintOptions, Radix};
+
+type Address = u64;
+
+// The state of a debugging session: the binary, its breakpoints and the
+// program being run. Besides the commands of the REPL, it can be driven
+// through its methods. They print the events, like the program stopping,
+// as the REPL does, with output::Mode::Dap they are kept to be read with
+// output::take_events instead
+#[derive(Default)]
+pub struct ProgramContext {
+    binary: Option<LoadedBinary>,
+    running_program: Option<RunningProgram>,
+    breakpoints: Vec<UserBreakpoint>,
+    // Breakpoints and watchpoints share the numbers, the last one given to
+    // one of them
+    last_breakpoint_number: usize,
+    // Set before loading a binary, like in scripts. They are checked and
+    // added when one is loaded
+    pending_breakpoints: Vec<PendingBreakpoint>,
+    // Variables set or overridden for the debugged program, the rest
+    // are inherited from the debugger
+    environment: BTreeMap<String, String>,
+    // Arguments of the last run, including the redirections, reused by rerun
+    program_args: Vec<String>,
+    // How the last run ended, shown by info program
+    last_exit: Option<WaitStatus>,
+    // Every program being debugged, numbered from 1. The fields above hold
+    // the state of the selected one, which is None here, and they are
+    // swapped when selecting another. Empty until adding a second one
+    inferiors: Vec<Option<Inferior>>,
+    selected_inferior: usize,
+    // Used when showing values, print overrides them with its flags. Some
+    // are changed with set print
+    print_options: PrintOptions,
+    // Events of the programs that aren't selected, waitpid returns the ones
+    // of every child. They are handled when selecting them again
+    queued_events: Vec<WaitStatus>,
+}
+
+// The state of a program that isn't selected, see ProgramContext
+#[derive(Default)]
+struct Inferior {
+    binary: Option<LoadedBinary>,
+    running_program: Option<RunningProgram>,
+    breakpoints: Vec<UserBreakpoint>,
+    last_breakpoint_number: usize,
+    pending_breakpoints: Vec<PendingBreakpoint>,
+    environment: BTreeMap<String, String>,
+    program_args: Vec<String>,
+    last_exit: Option<WaitStatus>,
+}
+
+impl ProgramContext {
+    fn swap_inferior(&mut self, inferior: &mut Inferior) {
+        std::mem::swap(&mut self.binary, &mut inferior.binary);
+        std::mem::swap(&mut self.running_program, &mut inferior.running_program);
+        std::mem::swap(&mut self.breakpoints, &mut inferior.breakpoints);
+        std::mem::swap(
+            &mut self.last_breakpoint_number,
+            &mut inferior.last_breakpoint_number,
+        );
+        std::mem::swap(
+            &mut self.pending_breakpoints,
+            &mut inferior.pending_breakpoints,
+        );
+        std::mem::swap(&mut self.environment, &mut inferior.environment);
+        std::mem::swap(&mut self.program_args, &mut inferior.program_args);
+        std::mem::swap(&mut self.last_exit, &mut inferior.last_exit);
+    }
+
+    // The selected program or some of its threads could have finished while
+    // another one was selected. Returns the message of the program exit
+    fn take_queued_exits(&mut self) -> Option<String> {
+        let running_program = self.running_program.as_mut()?;
+        let mut exit = None;
+        self.queued_events.retain(|&status| match status {
+            WaitStatus::Exited(thread, _) | WaitStatus::Signaled(thread, _, _)
+                if thread == running_program.pid =>
+            {
+                exit = Some(status);
+                false
+            }
+            WaitStatus::Exited(thread, _) | WaitStatus::Signaled(thread, _, _)
+                if running_program.threads.contains(&thread) =>
+            {
+                running_program.threads.retain(|&t| t != thread);
+                false
+            }
+            _ => true,
+        });
+        exit.map(|status| self.finish_program(status))
+    }
+
+    // Forgets the running program, recording why it finished
+    fn finish_program(&mut self, status: WaitStatus) -> String {
+        self.running_program = None;
+        self.last_exit = Some(status);
+        match status {
+            WaitStatus::Exited(_, code) => {
+                output::json_event("exited", vec![("code", Json::from(code as i64))]);
+            }
+            WaitStatus::Signaled(_, signal, _) => {
+                output::json_event(
+                    "exited",
+                    vec![("signal", Json::from(format!("{:?}", signal)))],
+                );
+            }
+            _ => {}
+        }
+        exit_message(status)
+    }
+}
+
+// The operations of the commands, for programs that embed the debugger.
+// Like the commands, they return the message for the user. Locations are
+// given as in the breakpoint command
+impl ProgramContext {
+    // Numbered from 1, the new one isn't selected. The rest of the methods
+    // act on the selected one
+    pub fn add_inferior(&mut self) -> usize {
+        if self.inferiors.is_empty() {
+            self.inferiors.push(None);
+        }
+        self.inferiors.push(Some(Inferior::default()));
+        self.inferiors.len()
+    }
+
+    // Returns the exit message if the program finished while another one
+    // was selected
+    pub fn select_inferior(&mut self, number: usize) -> anyhow::Result<Option<String>> {
+        if self.inferiors.is_empty() {
+            self.inferiors.push(None);
+        }
+        let index = number
+            .checked_sub(1)
+            .filter(|&index| index < self.inferiors.len())
+            .ok_or(anyhow!("There is no inferior {}", number))?;
+        if index == self.selected_inferior {
+            return Ok(None);
+        }
+        let mut inferior = self.inferiors[index].take().unwrap();
+        self.swap_inferior(&mut inferior);
+        self.inferiors[self.selected_inferior] = Some(inferior);
+        self.selected_inferior = index;
+        Ok(self.take_queued_exits())
+    }
+
+    pub fn has_binary(&self) -> bool {
+        self.binary.is_some()
+    }
+
+    pub fn load_binary(&mut self, path: &str) -> anyhow::Result<String> {
+        load_binary(self, path)
+    }
+
+    // Loads the binary again from its path, like after recompiling it
+    pub fn reload_binary(&mut self) -> anyhow::Result<String> {
+        reload_binary(self)
+    }
+
+    pub fn add_breakpoint(&mut self, location: &str, temporary: bool) -> anyhow::Result<String> {
+        add_user_breakpoint(self, location, temporary)
+    }
+
+    // Like add_breakpoint, with the number of the new breakpoint for tools
+    // that refer to it later
+    pub fn add_numbered_breakpoint(
+        &mut self,
+        location: &str,
+        temporary: bool,
+    ) -> anyhow::Result<Output> {
+        add_numbered_breakpoint(self, location, temporary)
+    }
+
+    // Breakpoints are numbered from 1, in the order they are added. The
+    // numbers don't change when others are deleted
+    pub fn set_breakpoint_enabled(
+        &mut self,
+        number: usize,
+        enabled: bool,
+    ) -> anyhow::Result<String> {
+        change_breakpoint_enabled(self, number, enabled)
+    }
+
+    pub fn save_breakpoints(&self, path: &str) -> anyhow::Result<String> {
+        save_breakpoints(self, path)
+    }
+
+    pub fn load_breakpoints(&mut self, path: &str) -> anyhow::Result<String> {
+        load_breakpoints(self, path)
+    }
+
+    // Stops when the value of the variable changes
+    pub fn watch(&mut self, variable: &str) -> anyhow::Result<String> {
+        add_watchpoint(self, variable)
+    }
+
+    // The program is started again if it's already running. The arguments
+    // can include redirections, like in the run command
+    pub fn run(&mut self, args: Vec<String>) -> anyhow::Result<String> {
+        self.start(args, false)
+    }
+
+    // Like run, but it stops at the entry point of the binary, before any
+    // breakpoint
+    pub fn run_to_entry_point(&mut self, args: Vec<String>) -> anyhow::Result<String> {
+        self.start(args, true)
+    }
+
+    fn start(&mut self, args: Vec<String>, stop_at_entry: bool) -> anyhow::Result<String> {
+        if self.binary.is_none() {
+            anyhow::bail!("You need to load a binary first");
+        }
+        self.program_args = args;
+        start_program(self, stop_at_entry)
+    }
+
+    // With the arguments of the last run
+    pub fn rerun(&mut self) -> anyhow::Result<String> {
+        if self.binary.is_none() {
+            anyhow::bail!("You need to load a binary first");
+        }
+        start_program(self, false)
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running_program.is_some()
+    }
+
+    pub fn continue_execution(&mut self) -> anyhow::Result<String> {
+        if self.running_program.is_none() {
+            anyhow::bail!("You need to run a program first");
+        }
+        continue_execution(self, None)
+    }
+
+    // The signal that stopped the program isn't delivered to it
+    pub fn continue_discarding_signal(&mut self) -> anyhow::Result<String> {
+        let Some(running_program) = self.running_program.as_mut() else {
+            anyhow::bail!("You need to run a program first");
+        };
+        running_program.pending_signal = None;
+        continue_execution(self, None)
+    }
+
+    // Stops at the line, given as source_file:line_number, or at a
+    // breakpoint
+    pub fn run_until(&mut self, location: &str) -> anyhow::Result<String> {
+        run_until(self, location)
+    }
+
+    // Like the next command
+    pub fn step_over(&mut self) -> anyhow::Result<String> {
+        step_line(self, true)
+    }
+
+    // Like the step command
+    pub fn step_into(&mut self) -> anyhow::Result<String> {
+        step_line(self, false)
+    }
+
+    // Like the finish command, the message has the returned value
+    pub fn finish(&mut self) -> anyhow::Result<String> {
+        finish_function(self)
+    }
+
+    // The value is the integer returned by the function, as in the return
+    // command
+    pub fn return_from_function(&mut self, value: Option<&str>) -> anyhow::Result<String> {
+        return_from_function(self, value)
+    }
+
+    pub fn step_instruction(&mut self) -> anyhow::Result<String> {
+        step_instruction(self)
+    }
+
+    // The expressions are the ones of the print command, like *pointer or
+    // point.x
+    pub fn print(&mut self, expression: &str) -> anyhow::Result<Output> {
+        print_variable(self, expression, self.print_options)
+    }
+
+    pub fn print_with(
+        &mut self,
+        expression: &str,
+        options: PrintOptions,
+    ) -> anyhow::Result<Output> {
+        print_variable(self, expression, options)
+    }
+
+    // The ones used by print, changed with set_print_elements
+    pub fn print_options(&self) -> PrintOptions {
+        self.print_options
+    }
+
+    pub fn set_print_elements(&mut self, limit: Option<&str>) -> anyhow::Result<String> {
+        set_print_elements(self, limit)
+    }
+
+    pub fn backtrace(&self) -> anyhow::Result<Output> {
+        show_backtrace(self)
+    }
+
+    // Variables are looked up in the selected frame, 0 is the innermost one
+    pub fn select_frame(&mut self, index: usize) -> anyhow::Result<Output> {
+        select_frame(self, index)
+    }
+
+    pub fn kill(&mut self) -> anyhow::Result<String> {
+        kill_program(self)
+    }
+
+    // The variables set for the next runs of the program
+    pub fn show_environment(&self) -> String {
+        show_environment(self)
+    }
+
+    pub fn set_environment_variable(&mut self, variable: &str) -> anyhow::Result<String> {
+        set_environment_variable(self, variable)
+    }
+
+    pub fn show_breakpoints(&self) -> String {
+        show_breakpoints(self)
+    }
+
+    pub fn show_registers(&self) -> anyhow::Result<String> {
+        show_registers(self)
+    }
+
+    pub fn show_locals(&self) -> anyhow::Result<String> {
+        show_variables(self, false)
+    }
+
+    pub fn show_args(&self) -> anyhow::Result<String> {
+        show_variables(self, true)
+    }
+
+    // Whether the program is running or how it finished
+    pub fn show_program(&self) -> String {
+        show_program(self)
+    }
+
+    pub fn show_inferiors(&self) -> String {
+        show_inferiors(self)
+    }
+
+    pub fn show_shared_libraries(&mut self) -> anyhow::Result<String> {
+        show_shared_libraries(self)
+    }
+
+    pub fn show_functions(&self, filter: Option<&str>) -> anyhow::Result<String> {
+        show_functions(self, filter)
+    }
+
+    // The address of a line, or the line of an address
+    pub fn show_line(&self, location: &str) -> anyhow::Result<String> {
+        show_line_info(self, location)
+    }
+
+    pub fn show_line_table(&self, file: &str) -> anyhow::Result<String> {
+        show_line_table(self, file)
+    }
+}
+
+fn exit_message(status: WaitStatus) -> String {
+    match status {
+        WaitStatus::Exited(_, 0) => String::from("[Inferior exited normally with code 0]"),
+        WaitStatus::Exited(_, code) => format!("[Inferior exited with code {}]", code),
+        WaitStatus::Signaled(_, signal, _) => {
+            format!("[Inferior terminated by signal {:?}]", signal)
+        }
+        _ => String::from("[Inferior exited]"),
+    }
+}
+
+struct UserBreakpoint {
+    // Used to refer to it, it doesn't change when other breakpoints are
+    // deleted
+    number: usize,
+    location: BreakpointLocation,
+    // Disabled breakpoints aren't set in the running program
+    enabled: bool,
+    // Set by tbreak, deleted after being hit once
+    temporary: bool,
+    // The line given by the user, None for functions and addresses. The
+    // program can stop at another one, if it has no code or the compiler
+    // moved it
+    requested_line: Option<u64>,
+}
+
+struct PendingBreakpoint {
+    // As written in the command
+    location: String,
+    temporary: bool,
+}
+
+#[derive(PartialEq)]
+enum BreakpointLocation {
+    Line(Breakpoint),
+    // Address in the binary, set with *address. It doesn't need debug info
+    Address(Address),
+    // Line of a shared library, identified by its path
+    LibraryLine { library: PathBuf, line: Breakpoint },
+}
+
+impl UserBreakpoint {
+    // The address in the binary, before taking into account where it's
+    // loaded. Breakpoints in shared libraries don't have one
+    fn relative_address(&self, binary: &LoadedBinary) -> anyhow::Result<Option<Address>> {
+        match &self.location {
+            BreakpointLocation::Line(breakpoint) => binary
+                .possible_breakpoints
+                .get(breakpoint)
+                .copied()
+                .map(Some)
+                .ok_or(anyhow!(
+                    "Breakpoint {} isn't in the line table of the binary",
+                    self.number
+                )),
+            BreakpointLocation::Address(address) => Ok(Some(*address)),
+            BreakpointLocation::LibraryLine { .. } => Ok(None),
+        }
+    }
+
+    // None if the breakpoint is in a library that isn't loaded
+    fn virtual_address(
+        &self,
+        binary: &LoadedBinary,
+        running_program: &RunningProgram,
+    ) -> anyhow::Result<Option<Address>> {
+        if let BreakpointLocation::LibraryLine { library, line } = &self.location {
+            let Some(library) = running_program
+                .libraries
+                .iter()
+                .find(|loaded_library| &loaded_library.path == library)
+            else {
+                return Ok(None);
+            };
+            let address = library.possible_breakpoints.get(line).ok_or(anyhow!(
+                "Breakpoint {} isn't in the line table of {}",
+                self.number,
+                library.path.to_string_lossy()
+            ))?;
+            return relative_address_to_virtual(*address, &library.proc_map).map(Some);
+        }
+        self.relative_address(binary)?
+            .map(|address| relative_address_to_virtual(address, &running_program.proc_map))
+            .transpose()
+    }
+}
+
+struct LoadedBinary {
+    binary_path: PathBuf,
+    // Matches a breakpoint location to the address from the DWARF
+    // These addresses aren't final, they need to take into account
+    // where the file is loaded into memory
+    possible_breakpoints: HashMap<Breakpoint, Address>,
+    dwarf: DwarfInfo,
+}
+
+// A shared library mapped by the running program. They are only loaded
+// when looking for breakpoints that aren't in the binary
+struct LoadedLibrary {
+    path: PathBuf,
+    proc_map: rsprocmaps::Map,
+    possible_breakpoints: HashMap<Breakpoint, Address>,
+    dwarf: DwarfInfo,
+}
+
+struct RunningProgram {
+    proc_map: rsprocmaps::Map,
+    // Only the ones with debug info are useful, but all of them are kept
+    // to avoid reading them again
+    libraries: Vec<LoadedLibrary>,
+    // Matches the address in memory where there is a breakpoint to
+    // its original instruction (after substituting it for a trap instruction)
+    set_breakpoints: HashMap<Address, SetBreakpoint>,
+    // One-shot breakpoint used by until, it's kept in set_breakpoints until
+    // the program is resumed
+    temporary_breakpoint: Option<Address>,
+    // One for each hardware debug register
+    watchpoints: [Option<Watchpoint>; 4],
+    // Used when the hardware ones can't be, they are checked after every
+    // instruction, so the program is single-stepped while there are any
+    software_watchpoints: Vec<Watchpoint>,
+    pid: Pid,
+    // Every thread of the program, they are all stopped while
+    // the user inspects it
+    threads: Vec<Pid>,
+    // The thread that caused the last stop, registers are read from it
+    thread: Pid,
+    // Index in the backtrace of the frame used to look up variables, it goes
+    // back to the innermost one (0) whenever the program is resumed
+    selected_frame: usize,
+    last_status: WaitStatus,
+    // Signal that stopped the program, it's delivered to the thread that
+    // received it when the program is resumed
+    pending_signal: Option<Signal>,
+    // A SIGINT was sent to stop it after a Ctrl-C, and it hasn't stopped
+    // because of it yet
+    stop_requested: bool,
+    // The last stop was the one of a Ctrl-C
+    interrupted: bool,
+}
+
+struct SetBreakpoint {
+    original_word: i64,
+    // Several breakpoints can be at the same address, like a temporary one
+    // and the one used by until. The original instruction is only restored
+    // when all of them are removed
+    owners: usize,
+}
+
+struct Watchpoint {
+    number: usize,
+    variable_name: String,
+    address: Address,
+    type_info: dwarf::TypeInfo,
+    // Formatted, to report how it changes
+    last_value: String,
+}
+
+fn show_environment(context: &ProgramContext) -> String {
+    context
+        .environment
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// Given as KEY=VALUE
+fn set_environment_variable(
+    context: &mut ProgramContext,
+    variable: &str,
+) -> anyhow::Result<String> {
+    let (key, value) = variable.split_once('=').ok_or(anyhow!("Missing ="))?;
+    if key.is_empty() {
+        anyhow::bail!("Missing the name of the variable");
+    }
+    context.environment.insert(key.to_owned(), value.to_owned());
+    Ok(format!(
+        "{} will be set to \"{}\" in the next run",
+        key, value
+    ))
+}
+
+// A number, 0 or "unlimited" to show all of them. Without a limit it only
+// shows the current one
+fn set_print_elements(context: &mut ProgramContext, limit: Option<&str>) -> anyhow::Result<String> {
+    let options = &mut context.print_options;
+    if let Some(limit) = limit {
+        options.max_elements = match limit {
+            "unlimited" => 0,
+            limit => limit
+                .parse()
+                .with_context(|| format!("Invalid limit {}", limit))?,
+        };
+    }
+    Ok(match options.max_elements {
+        0 => String::from("All the elements are shown"),
+        max_elements => format!("Up to {} elements are shown", max_elements),
+    })
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct Breakpoint {
+    file: PathBuf,
+    line_number: u64,
+}
+
+impl FromStr for Breakpoint {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let (file, number) = s.split_once(":").ok_or(anyhow::anyhow!("Missing :"))?;
+        Ok(Self {
+            file: PathBuf::from(file),
+            line_number: number.parse().context("Couldn't parse line number")?,
+        })
+    }
+}
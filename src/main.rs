@@ -1,394 +1,59 @@
-use anyhow::{Context, anyhow};
+// The command line front-end, the debugger itself is in lib.rs
+use std::path::Path;
+
 use clap::Arg;
-use nix::{
-    sys::{
-        ptrace::{self, cont, getregs, setregs, step, traceme},
-        signal::Signal::SIGTRAP,
-        wait::{WaitStatus, wait},
-    },
-    unistd::{ForkResult, Pid, execv, fork},
-};
-use std::{
-    collections::HashMap,
-    ffi::CString,
-    fs, io,
-    path::{Path, PathBuf},
-    str::FromStr,
+use debugito::{
+    ProgramContext, dap,
+    output::{self, Output},
 };
 
-mod dwarf;
-mod registers;
+mod commands;
 mod repl;
 
-use dwarf::DwarfInfo;
-use repl::Repl;
-
-type Address = u64;
-
-#[derive(Default)]
-struct ProgramContext {
-    binary: Option<LoadedBinary>,
-    running_program: Option<RunningProgram>,
-    breakpoints: Vec<Breakpoint>,
-}
-
-struct LoadedBinary {
-    binary_path: PathBuf,
-    // Matches a breakpoint location to the address from the DWARF
-    // These addresses aren't final, they need to take into account
-    // where the file is loaded into memory
-    possible_breakpoints: HashMap<Breakpoint, Address>,
-    dwarf: DwarfInfo,
-}
-
-struct RunningProgram {
-    proc_map: rsprocmaps::Map,
-    // Matches the address in memory where there is a breakpoint to
-    // its original instruction (after substituting it for a trap instruction)
-    set_breakpoints: HashMap<Address, i64>,
-    pid: Pid,
-    last_status: WaitStatus,
-}
+use commands::run_repl;
 
 fn main() -> anyhow::Result<()> {
     let arg_matches = clap::Command::new("Debugito")
         .about("Simple debugger")
         .arg(Arg::new("binary_path"))
-        .get_matches();
-    let mut context = ProgramContext::default();
-    if arg_matches.contains_id("binary_path") {
-        load_program(&arg_matches, &mut context)?;
-    }
-    let mut repl = Repl::new(context)
-        .add_command(
-            clap::Command::new("load")
-                .visible_alias("l")
-                .arg(
-                    clap::Arg::new("binary_path")
-                        .required(true)
-                        .help("the path to the executable binary"),
-                )
-                .about("load a binary to prepare for debugging"),
-            load_program,
+        .arg(
+            Arg::new("source")
+                .long("source")
+                .short('x')
+                .value_hint(clap::ValueHint::FilePath)
+                .help("run the commands in the file, one per line, and exit"),
         )
-        .add_command(
-            clap::Command::new("breakpoint")
-                .visible_alias("b")
-                .arg(
-                    clap::Arg::new("where")
-                        .required(true)
-                        .help("in the form \"source_file:line_number\""),
-                )
-                .about("set a breakpoint"),
-            add_breakpoint,
+        .arg(
+            Arg::new("json")
+                .long("json")
+                .action(clap::ArgAction::SetTrue)
+                .help("print the results and events as JSON objects, one per line"),
         )
-        .add_command(
-            clap::Command::new("run")
-                .visible_alias("r")
-                .arg(
-                    Arg::new("program_args")
-                        .trailing_var_arg(true)
-                        .num_args(0..),
-                )
-                .about("run the specified binary until finding a breakpoint"),
-            run_program,
+        .arg(
+            Arg::new("dap")
+                .long("dap")
+                .action(clap::ArgAction::SetTrue)
+                .conflicts_with_all(["source", "json", "binary_path"])
+                .help("speak the Debug Adapter Protocol over the standard streams, for editors"),
         )
-        .add_command(
-            clap::Command::new("continue")
-                .visible_alias("c")
-                .about("Keep running the program until a breakpoint"),
-            continue_program,
-        )
-        .add_command(
-            clap::Command::new("print")
-                .visible_alias("p")
-                .arg(
-                    clap::Arg::new("var")
-                        .required(true)
-                        .help("name of the variable"),
-                )
-                .about("Print the value of a variable"),
-            print_var,
-        );
-    repl.run()
-}
-
-fn load_program(args: &clap::ArgMatches, context: &mut ProgramContext) -> anyhow::Result<String> {
-    if context.binary.is_some() {
-        if !ask_for_confirmation(
-            "Another binary was already loaded, do you want to load a new one?",
-        ) {
-            return Ok(String::from("Kept original binary"));
-        }
+        .get_matches();
+    if arg_matches.get_flag("dap") {
+        output::set_mode(output::Mode::Dap);
+        return dap::run(&mut ProgramContext::default());
     }
-    let binary_path =
-        PathBuf::from(args.get_one::<String>("binary_path").unwrap()).canonicalize()?;
-    let file_buffer = fs::read(&binary_path).expect("Failed to read file");
-    let dwarf = DwarfInfo::new(file_buffer);
-    let possible_breakpoints = dwarf.get_breakpoints_from_dwarf()?;
-
-    context.binary = Some(LoadedBinary {
-        binary_path,
-        dwarf,
-        possible_breakpoints,
-    });
-    Ok(String::from("Binary loaded"))
-}
-
-fn add_breakpoint(args: &clap::ArgMatches, context: &mut ProgramContext) -> anyhow::Result<String> {
-    let loaded_binary = context
-        .binary
-        .as_ref()
-        .ok_or(anyhow!("Please load a binary first"))?;
-    let breakpoint_str = args.get_one::<String>("where").unwrap();
-    let mut breakpoint: Breakpoint = breakpoint_str.parse()?;
-    breakpoint.file = breakpoint.file.canonicalize()?;
-    if !loaded_binary.possible_breakpoints.contains_key(&breakpoint) {
-        return Ok("Not a valid breakpoint position".to_owned());
+    if arg_matches.get_flag("json") {
+        output::set_mode(output::Mode::Json);
     }
-    if context
-        .breakpoints
-        .iter()
-        .find(|&b| b == &breakpoint)
-        .is_some()
+    let mut context = ProgramContext::default();
+    let script = arg_matches.get_one::<String>("source");
+    // A binary that can't be loaded shouldn't prevent using the REPL
+    if let Some(binary_path) = arg_matches.get_one::<String>("binary_path")
+        && let Err(err) = context.load_binary(binary_path)
     {
-        return Ok("Breakpoint already exists".to_owned());
-    }
-    if let Some(running_program) = &context.running_program {
-        setup_breakpoint(
-            running_program.pid,
-            loaded_binary.possible_breakpoints[&breakpoint],
-            &running_program.proc_map,
-        );
-    }
-    context.breakpoints.push(breakpoint);
-    Ok(String::from("Breakpoint added to ") + breakpoint_str)
-}
-
-fn run_program(args: &clap::ArgMatches, context: &mut ProgramContext) -> anyhow::Result<String> {
-    let binary = context
-        .binary
-        .as_ref()
-        .ok_or(anyhow!("You need to load a binary first"))?;
-    if context.running_program.is_some() {
-        if !ask_for_confirmation("A program is already being run, do you want to rerun it?") {
-            return Ok("The original program is still running".to_owned());
-        }
-    }
-    if context.breakpoints.is_empty() {
-        anyhow::bail!("Please set at least one breakpoint first");
-    }
-    let pid = launch_fork(
-        &binary.binary_path,
-        args.get_many("program_args")
-            .map(|args| args.collect::<Vec<_>>())
-            .unwrap_or(vec![]),
-    );
-    if let nix::sys::wait::WaitStatus::Exited(_, _) = wait().unwrap() {
-        context.running_program = None;
-        return Ok("Program exited".to_owned());
-    }
-    let proc_map = get_range_for_program_source_code(pid.as_raw() as u64, &binary.binary_path);
-    let set_breakpoints = context
-        .breakpoints
-        .iter()
-        .map(|breakpoint| {
-            let relative_address = binary.possible_breakpoints[breakpoint];
-            setup_breakpoint(pid, relative_address, &proc_map)
-        })
-        .collect();
-    cont(pid, None).unwrap();
-    let status = wait().unwrap();
-    if let nix::sys::wait::WaitStatus::Exited(_, _) = status {
-        context.running_program = None;
-        return Ok("Program exited".to_owned());
-    }
-    print_source_code_line(&proc_map, binary, pid)?;
-    context.running_program = Some(RunningProgram {
-        proc_map,
-        set_breakpoints,
-        pid,
-        last_status: status,
-    });
-    Ok(String::from("Reached breakpoint"))
-}
-
-fn ask_for_confirmation(message: &str) -> bool {
-    println!("{} (y/n)", message);
-    let stdin = io::stdin();
-    stdin.lines().next().unwrap().unwrap() == "y"
-}
-
-fn continue_program(_: &clap::ArgMatches, context: &mut ProgramContext) -> anyhow::Result<String> {
-    let running_program = context
-        .running_program
-        .as_mut()
-        .ok_or(anyhow!("You need to run a program first"))?;
-    let binary = context.binary.as_ref().unwrap(); // If there's a pid, there's a binary
-    let pid = running_program.pid;
-    if let WaitStatus::Stopped(pid, SIGTRAP) = running_program.last_status {
-        if run_original_breakpoint_instruction(pid, &running_program.set_breakpoints).is_err() {
-            context.running_program = None;
-            return Ok("Program exited".to_owned());
-        };
-    }
-    cont(pid, None).unwrap();
-    let status = wait().unwrap();
-    if let nix::sys::wait::WaitStatus::Exited(_, _) = status {
-        context.running_program = None;
-        return Ok("Program exited".to_owned());
-    }
-    running_program.last_status = status;
-    print_source_code_line(&running_program.proc_map, binary, pid)?;
-    Ok(String::from("Reached breakpoint"))
-}
-
-fn print_source_code_line(
-    proc_map: &rsprocmaps::Map,
-    binary: &LoadedBinary,
-    pid: Pid,
-) -> Result<(), anyhow::Error> {
-    let address = virtual_address_to_relative(get_last_instruction_address(pid), proc_map);
-    let line_pos = binary.dwarf.get_line_from_address(address)?;
-    let line = fs::read_to_string(&line_pos.path)?
-        .lines()
-        .nth(line_pos.line_number - 1)
-        .unwrap()
-        .to_owned();
-    println!(
-        "{}:{}\n{}",
-        line_pos.path.to_str().unwrap(),
-        line_pos.line_number,
-        line
-    );
-    Ok(())
-}
-
-fn print_var(args: &clap::ArgMatches, context: &mut ProgramContext) -> anyhow::Result<String> {
-    let variable_name = args.get_one::<String>("var").unwrap();
-    let program = context
-        .running_program
-        .as_mut()
-        .ok_or(anyhow!("You need to run a program first"))?;
-    let binary = context.binary.as_mut().unwrap();
-    let variable = binary.dwarf.get_variable_info(variable_name, program.pid)?;
-
-    print_var_with_info(program, variable)?;
-    Ok("".to_string())
-}
-
-fn print_var_with_info(
-    program: &mut RunningProgram,
-    variable_info: dwarf::VariableInfo,
-) -> Result<(), anyhow::Error> {
-    let word = ptrace::read(program.pid, variable_info.address as ptrace::AddressType)?;
-    let word = u64::from_be_bytes(word.to_be_bytes());
-    let value = word & (u64::MAX >> (64 - variable_info.size));
-    match variable_info.base_type {
-        dwarf::BaseType::Boolean => println!("{}", value == 1),
-        dwarf::BaseType::Float => {
-            if variable_info.size == 32 {
-                println!("{}", f32::from_be_bytes((value as u32).to_be_bytes()));
-            } else {
-                println!("{}", f64::from_be_bytes(value.to_be_bytes()));
-            }
+        if script.is_some() {
+            return Err(err);
         }
-        dwarf::BaseType::Signed | dwarf::BaseType::Unsigned => println!("{}", value),
+        output::event("error", Output::new(format!("{}\n", err)));
     }
-    Ok(())
-}
-
-fn get_last_instruction_address(pid: Pid) -> u64 {
-    let registers = getregs(pid).unwrap();
-    // We subtract an extra 1 because the rip was already increased by the trap instruction
-    registers.rip - 1
-}
-
-fn virtual_address_to_relative(address: u64, proc_map: &rsprocmaps::Map) -> u64 {
-    address - proc_map.address_range.begin + proc_map.offset
-}
-
-fn relative_address_to_virtual(address: u64, proc_map: &rsprocmaps::Map) -> u64 {
-    address + proc_map.address_range.begin - proc_map.offset
-}
-
-fn run_original_breakpoint_instruction(
-    pid: Pid,
-    set_breakpoints: &HashMap<u64, i64>,
-) -> anyhow::Result<()> {
-    let mut registers = getregs(pid).unwrap();
-    // We subtract an extra 1 because the rip was already increased by the trap instruction
-    registers.rip -= 1;
-    setregs(pid, registers).unwrap();
-    let original_word = set_breakpoints[&registers.rip];
-    ptrace::write(pid, registers.rip as ptrace::AddressType, original_word).unwrap();
-    do_step(pid)?;
-    let word = add_trap_instruction(original_word);
-    ptrace::write(pid, registers.rip as ptrace::AddressType, word).unwrap();
-    Ok(())
-}
-
-fn setup_breakpoint(pid: Pid, relative_address: u64, proc_map: &rsprocmaps::Map) -> (u64, i64) {
-    let virtual_address = relative_address_to_virtual(relative_address, proc_map);
-    let original_word = ptrace::read(pid, virtual_address as ptrace::AddressType).unwrap();
-    let word = add_trap_instruction(original_word);
-    ptrace::write(pid, virtual_address as ptrace::AddressType, word).unwrap();
-    (virtual_address as u64, original_word)
-}
-
-fn add_trap_instruction(word: i64) -> i64 {
-    const TRAP_INSTRUCTION: i64 = 0xCC;
-    // Only valid for x86
-    (word & (!0xFF)) | TRAP_INSTRUCTION
-}
-
-fn launch_fork(executable: &Path, args: Vec<&String>) -> Pid {
-    let args = args
-        .iter()
-        .map(|arg| CString::new(arg.as_str()).unwrap())
-        .collect::<Vec<_>>();
-    match unsafe { fork() }.unwrap() {
-        ForkResult::Child => {
-            traceme().expect("I don't want to be traced");
-            execv(&CString::new(executable.to_str().unwrap()).unwrap(), &args).unwrap();
-            unreachable!()
-        }
-        ForkResult::Parent { child: pid } => return pid,
-    }
-}
-
-fn do_step(pid: Pid) -> anyhow::Result<()> {
-    step(pid, None).unwrap();
-    let status = wait().unwrap();
-    if let nix::sys::wait::WaitStatus::Exited(_, _) = status {
-        anyhow::bail!("Child exited")
-    }
-    Ok(())
-}
-
-#[derive(Clone, Debug, PartialEq, Eq, Hash)]
-struct Breakpoint {
-    file: PathBuf,
-    line_number: u64,
-}
-
-impl FromStr for Breakpoint {
-    type Err = anyhow::Error;
-
-    fn from_str(s: &str) -> anyhow::Result<Self> {
-        let (file, number) = s.split_once(":").ok_or(anyhow::anyhow!("Missing :"))?;
-        Ok(Self {
-            file: PathBuf::from(file),
-            line_number: number.parse().context("Couldn't parse line number")?,
-        })
-    }
-}
-
-fn get_range_for_program_source_code(pid: u64, executable: &Path) -> rsprocmaps::Map {
-    let maps = rsprocmaps::from_pid(pid as i32).unwrap();
-    let executable_pathname = rsprocmaps::Pathname::Path(executable.to_str().unwrap().to_string());
-    maps.into_iter()
-        .map(Result::unwrap)
-        .find(|map| &map.pathname == &executable_pathname && map.permissions.executable)
-        .unwrap()
+    run_repl(context, script.map(Path::new))
 }
@@ -0,0 +1,363 @@
+use std::{
+    fmt,
+    sync::{
+        Mutex,
+        atomic::{AtomicU8, Ordering},
+    },
+};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Mode {
+    Text,
+    // Set with --json, for tools that drive the debugger instead of a
+    // person. Every result and event is printed as a JSON object in its
+    // own line
+    Json,
+    // Set with --dap. The standard output carries the protocol, so the
+    // events are kept until the adapter sends them
+    Dap,
+}
+
+static MODE: AtomicU8 = AtomicU8::new(Mode::Text as u8);
+static EVENTS: Mutex<Vec<(&'static str, Output)>> = Mutex::new(Vec::new());
+
+pub fn set_mode(mode: Mode) {
+    MODE.store(mode as u8, Ordering::Relaxed);
+}
+
+pub fn mode() -> Mode {
+    match MODE.load(Ordering::Relaxed) {
+        mode if mode == Mode::Json as u8 => Mode::Json,
+        mode if mode == Mode::Dap as u8 => Mode::Dap,
+        _ => Mode::Text,
+    }
+}
+
+pub fn json_output() -> bool {
+    mode() == Mode::Json
+}
+
+// The events since the last call, only kept in the DAP mode
+pub fn take_events() -> Vec<(&'static str, Output)> {
+    std::mem::take(&mut *EVENTS.lock().unwrap())
+}
+
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(i64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn object(fields: Vec<(&str, Json)>) -> Self {
+        Json::Object(
+            fields
+                .into_iter()
+                .map(|(key, value)| (key.to_owned(), value))
+                .collect(),
+        )
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(fields) => fields
+                .iter()
+                .find(|(field, _)| field == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Json::Number(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    // Fractions and exponents are truncated, the protocols used here only
+    // send integers
+    pub fn parse(text: &str) -> anyhow::Result<Json> {
+        let mut parser = Parser {
+            chars: text.chars().peekable(),
+        };
+        let value = parser.parse_value()?;
+        parser.skip_whitespace();
+        if parser.chars.next().is_some() {
+            anyhow::bail!("Unexpected characters after the JSON value");
+        }
+        Ok(value)
+    }
+}
+
+struct Parser<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        while self.chars.next_if(|c| c.is_whitespace()).is_some() {}
+    }
+
+    fn expect(&mut self, expected: char) -> anyhow::Result<()> {
+        match self.chars.next() {
+            Some(c) if c == expected => Ok(()),
+            _ => anyhow::bail!("Expected {} in the JSON value", expected),
+        }
+    }
+
+    fn parse_value(&mut self) -> anyhow::Result<Json> {
+        self.skip_whitespace();
+        match self.chars.peek() {
+            Some('{') => {
+                self.chars.next();
+                let mut fields = Vec::new();
+                self.skip_whitespace();
+                if self.chars.next_if_eq(&'}').is_some() {
+                    return Ok(Json::Object(fields));
+                }
+                loop {
+                    self.skip_whitespace();
+                    let key = self.parse_string()?;
+                    self.skip_whitespace();
+                    self.expect(':')?;
+                    fields.push((key, self.parse_value()?));
+                    self.skip_whitespace();
+                    if self.chars.next_if_eq(&',').is_none() {
+                        self.expect('}')?;
+                        return Ok(Json::Object(fields));
+                    }
+                }
+            }
+            Some('[') => {
+                self.chars.next();
+                let mut values = Vec::new();
+                self.skip_whitespace();
+                if self.chars.next_if_eq(&']').is_some() {
+                    return Ok(Json::Array(values));
+                }
+                loop {
+                    values.push(self.parse_value()?);
+                    self.skip_whitespace();
+                    if self.chars.next_if_eq(&',').is_none() {
+                        self.expect(']')?;
+                        return Ok(Json::Array(values));
+                    }
+                }
+            }
+            Some('"') => Ok(Json::String(self.parse_string()?)),
+            Some('-' | '0'..='9') => {
+                let mut number = String::new();
+                while let Some(c) = self
+                    .chars
+                    .next_if(|c| matches!(c, '-' | '+' | '.' | 'e' | 'E' | '0'..='9'))
+                {
+                    number.push(c);
+                }
+                let integer = number.split(['.', 'e', 'E']).next().unwrap();
+                Ok(Json::Number(integer.parse()?))
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(c) = self.chars.next_if(char::is_ascii_alphabetic) {
+                    word.push(c);
+                }
+                match word.as_str() {
+                    "true" => Ok(Json::Bool(true)),
+                    "false" => Ok(Json::Bool(false)),
+                    "null" => Ok(Json::Null),
+                    _ => anyhow::bail!("Invalid JSON value"),
+                }
+            }
+        }
+    }
+
+    fn parse_string(&mut self) -> anyhow::Result<String> {
+        self.expect('"')?;
+        let mut string = String::new();
+        loop {
+            match self.chars.next() {
+                Some('"') => return Ok(string),
+                Some('\\') => match self.chars.next() {
+                    Some('n') => string.push('\n'),
+                    Some('t') => string.push('\t'),
+                    Some('r') => string.push('\r'),
+                    Some('b') => string.push('\u{8}'),
+                    Some('f') => string.push('\u{c}'),
+                    Some('u') => {
+                        let code = (0..4).filter_map(|_| self.chars.next()).collect::<String>();
+                        let code = u32::from_str_radix(&code, 16)?;
+                        // Surrogate pairs aren't decoded
+                        string.push(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER));
+                    }
+                    Some(c) => string.push(c),
+                    None => anyhow::bail!("Unterminated JSON string"),
+                },
+                Some(c) => string.push(c),
+                None => anyhow::bail!("Unterminated JSON string"),
+            }
+        }
+    }
+}
+
+impl From<bool> for Json {
+    fn from(value: bool) -> Self {
+        Json::Bool(value)
+    }
+}
+
+impl From<i64> for Json {
+    fn from(value: i64) -> Self {
+        Json::Number(value)
+    }
+}
+
+// Addresses don't always fit in an i64, and tools parse them anyway
+impl From<u64> for Json {
+    fn from(value: u64) -> Self {
+        Json::String(format!("{:#x}", value))
+    }
+}
+
+impl From<usize> for Json {
+    fn from(value: usize) -> Self {
+        Json::Number(value as i64)
+    }
+}
+
+impl From<&str> for Json {
+    fn from(value: &str) -> Self {
+        Json::String(value.to_owned())
+    }
+}
+
+impl From<String> for Json {
+    fn from(value: String) -> Self {
+        Json::String(value)
+    }
+}
+
+impl<T: Into<Json>> From<Vec<T>> for Json {
+    fn from(values: Vec<T>) -> Self {
+        Json::Array(values.into_iter().map(Into::into).collect())
+    }
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(value) => write!(f, "{}", value),
+            Json::Number(value) => write!(f, "{}", value),
+            Json::String(value) => {
+                write!(f, "\"")?;
+                for c in value.chars() {
+                    match c {
+                        '"' => write!(f, "\\\"")?,
+                        '\\' => write!(f, "\\\\")?,
+                        '\n' => write!(f, "\\n")?,
+                        '\t' => write!(f, "\\t")?,
+                        c if c.is_control() => write!(f, "\\u{:04x}", c as u32)?,
+                        c => write!(f, "{}", c)?,
+                    }
+                }
+                write!(f, "\"")
+            }
+            Json::Array(values) => {
+                write!(f, "[")?;
+                for (index, value) in values.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(fields) => {
+                write!(f, "{{")?;
+                for (index, (key, value)) in fields.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}:{}", Json::from(key.as_str()), value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+// The result of a command: the text shown to the user and, for the
+// commands that tools care about, the same information as JSON fields
+pub struct Output {
+    pub text: String,
+    pub fields: Vec<(&'static str, Json)>,
+}
+
+impl Output {
+    pub fn new(text: String) -> Self {
+        Self {
+            text,
+            fields: Vec::new(),
+        }
+    }
+
+    pub fn with(mut self, key: &'static str, value: impl Into<Json>) -> Self {
+        self.fields.push((key, value.into()));
+        self
+    }
+}
+
+impl From<String> for Output {
+    fn from(text: String) -> Self {
+        Output::new(text)
+    }
+}
+
+// Something that happened while running a command, like the program
+// stopping. Only the text is printed without --json
+pub fn event(name: &'static str, output: Output) {
+    match mode() {
+        Mode::Json => {
+            let mut fields = vec![("event", Json::from(name))];
+            fields.extend(output.fields);
+            if !output.text.is_empty() {
+                fields.push(("message", Json::String(output.text)));
+            }
+            println!("{}", Json::object(fields));
+        }
+        Mode::Dap => EVENTS.lock().unwrap().push((name, output)),
+        Mode::Text if !output.text.is_empty() => println!("{}", output.text),
+        Mode::Text => {}
+    }
+}
+
+// Like event, but only for tools, nothing is printed in the text mode
+pub fn json_event(name: &'static str, fields: Vec<(&'static str, Json)>) {
+    if mode() != Mode::Text {
+        event(
+            name,
+            Output {
+                text: String::new(),
+                fields,
+            },
+        );
+    }
+}
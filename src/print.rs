@@ -0,0 +1,1050 @@
+use anyhow::anyhow;
+use nix::{
+    sys::uio::{RemoteIoVec, process_vm_readv},
+    unistd::Pid,
+};
+use std::io::IoSliceMut;
+
+use crate::{
+    ProgramContext, binary::find_frame_debug_info, breakpoints::parse_address, dwarf,
+    frames::get_selected_frame, output::Output, registers, tracee::Tracee,
+};
+
+pub fn print_variable(
+    context: &mut ProgramContext,
+    variable_name: &str,
+    options: PrintOptions,
+) -> anyhow::Result<Output> {
+    let program = context
+        .running_program
+        .as_mut()
+        .ok_or(anyhow!("You need to run a program first"))?;
+    if let Some(register_name) = variable_name.strip_prefix('$') {
+        // Floating point values are returned in xmm0
+        if let Some(index) = register_name
+            .strip_prefix("xmm")
+            .and_then(|index| index.parse::<usize>().ok())
+            .filter(|index| *index < 16)
+        {
+            let value = registers::get_xmm_registers(program.thread)?[index];
+            return Ok(Output::new(format!(
+                "{:#x} (float: {}, double: {})",
+                value,
+                f32::from_bits(value as u32),
+                f64::from_bits(value as u64)
+            ))
+            .with("name", variable_name)
+            .with("value", format!("{:#x}", value)));
+        }
+        let value =
+            registers::get_register_value_by_name(&program.thread.get_regs()?, register_name)
+                .ok_or(anyhow!("Unknown register {}", register_name))?;
+        return Ok(Output::new(format!("{:#x} ({})", value, value))
+            .with("name", variable_name)
+            .with("value", value));
+    }
+    let binary = context.binary.as_mut().unwrap();
+    let frame = get_selected_frame(program, binary)?;
+    let (dwarf, load_bias) = find_frame_debug_info(program, binary, &frame);
+    // Like in C, &var is the address of the variable, *p is what p points
+    // to and members of structs are accessed as in point.x
+    let (address_of, expression) = match variable_name.strip_prefix('&') {
+        Some(expression) => (true, expression),
+        None => (false, variable_name),
+    };
+    let field_expression = expression.trim_start_matches('*');
+    let dereferences = expression.len() - field_expression.len();
+    let expression = field_expression;
+    // Elements of arrays are accessed as in arr[3], and what pointers point
+    // to as in p[3]
+    let (name, accessors) =
+        expression.split_at(expression.find(['.', '[']).unwrap_or(expression.len()));
+    let indexed = accessors.contains('[');
+    // Each dereference needs the types of one more level of pointers, and
+    // so does each index
+    let variable = dwarf.get_variable_info(
+        name,
+        &frame,
+        load_bias,
+        options.max_depth + dereferences + accessors.matches('[').count(),
+    )?;
+    let path = accessors.split('.').skip(1).collect::<Vec<_>>();
+    let element = if indexed {
+        Some(find_element(
+            program.thread,
+            &variable,
+            &parse_accessors(accessors)?,
+        )?)
+    } else {
+        None
+    };
+
+    if address_of {
+        if dereferences > 0 {
+            anyhow::bail!("Taking the address of a dereference is not supported");
+        }
+        let (address, type_name) = match &element {
+            Some(element) => (element.address, element.type_name.as_str()),
+            None => get_address_of(&variable, &path)?,
+        };
+        // Pointers to pointers are named like "int **"
+        let separator = if type_name.ends_with('*') { "" } else { " " };
+        let type_name = format!("{}{}*", type_name, separator);
+        return Ok(Output::new(format!(
+            "{} = {:#x} ({})",
+            variable_name, address, type_name
+        ))
+        .with("name", variable_name)
+        .with("value", address)
+        .with("type", type_name));
+    }
+    let (value, type_name) = match (element.as_ref(), dereferences, path.is_empty()) {
+        (Some(element), 0, _) => (
+            format_value(program.thread, element.address, element.type_info, options)?,
+            element.type_name.clone(),
+        ),
+        (Some(element), _, _) => follow_pointers(
+            program.thread,
+            read_value(program.thread, element.address, 64)?,
+            element.type_info,
+            element.type_name.clone(),
+            dereferences,
+            options,
+        )?,
+        (None, 0, true) => (
+            format_variable(program.thread, &variable, options)?,
+            variable.type_name.clone(),
+        ),
+        (None, 0, false) => {
+            let (struct_address, member) = find_struct_member(&variable, &path)?;
+            (
+                format_member(program.thread, struct_address, member, options)?,
+                member.type_name.clone(),
+            )
+        }
+        (None, _, _) => {
+            format_dereference(program.thread, &variable, &path, dereferences, options)?
+        }
+    };
+    let mut text = format!("{} = {} ({})", variable_name, value, type_name);
+    // The element is still shown, C doesn't check the bounds either
+    let warning = element.and_then(|element| element.warning);
+    if let Some(warning) = &warning {
+        text += &format!("\n{}", warning);
+    }
+    let output = Output::new(text)
+        .with("name", variable_name)
+        .with("value", value)
+        .with("type", type_name);
+    Ok(match warning {
+        Some(warning) => output.with("warning", warning),
+        None => output,
+    })
+}
+
+enum Accessor<'a> {
+    Member(&'a str),
+    Index(i64),
+}
+
+// Splits what comes after the name of the variable, like ".points[2].x"
+fn parse_accessors(mut accessors: &str) -> anyhow::Result<Vec<Accessor<'_>>> {
+    let mut parsed = Vec::new();
+    while !accessors.is_empty() {
+        if let Some(rest) = accessors.strip_prefix('[') {
+            let (index, rest) = rest
+                .split_once(']')
+                .ok_or(anyhow!("Missing ] in the index"))?;
+            parsed.push(Accessor::Index(parse_index(index.trim())?));
+            accessors = rest;
+        } else if let Some(rest) = accessors.strip_prefix('.') {
+            let end = rest.find(['.', '[']).unwrap_or(rest.len());
+            parsed.push(Accessor::Member(&rest[..end]));
+            accessors = &rest[end..];
+        } else {
+            anyhow::bail!("Unexpected {}, expected . or [", accessors);
+        }
+    }
+    Ok(parsed)
+}
+
+// Indexes can be negative, like p[-1] for the element before a pointer
+fn parse_index(index: &str) -> anyhow::Result<i64> {
+    let invalid = || anyhow!("Invalid index {}, it must be a number", index);
+    let (negative, magnitude) = match index.strip_prefix('-') {
+        Some(magnitude) => (true, magnitude),
+        None => (false, index),
+    };
+    let magnitude = parse_address(magnitude).map_err(|_| invalid())?;
+    if negative {
+        0i64.checked_sub_unsigned(magnitude).ok_or_else(invalid)
+    } else {
+        i64::try_from(magnitude).map_err(|_| invalid())
+    }
+}
+
+// The address of the element at the index, which can be anywhere when
+// indexing a pointer
+fn get_element_address(base: u64, index: i64, element_size: u64) -> anyhow::Result<u64> {
+    i64::try_from(element_size)
+        .ok()
+        .and_then(|element_size| index.checked_mul(element_size))
+        .and_then(|offset| base.checked_add_signed(offset))
+        .ok_or(anyhow!(
+            "The element at index {} is out of the address space",
+            index
+        ))
+}
+
+struct Element<'a> {
+    address: u64,
+    type_info: &'a dwarf::TypeInfo,
+    type_name: String,
+    // Set when an index is past the end of its array
+    warning: Option<String>,
+}
+
+// Follows the members and indexes from the variable to the element they
+// refer to. Indexing a pointer reads it to find where the elements are
+fn find_element<'a>(
+    pid: Pid,
+    variable: &'a dwarf::VariableInfo,
+    accessors: &[Accessor],
+) -> anyhow::Result<Element<'a>> {
+    // The address is None while the value is the one of a variable stored in
+    // a register, only pointers can be indexed then
+    let (mut address, register_value) = match variable.location {
+        dwarf::VariableLocation::Address(address) => (Some(address), 0),
+        dwarf::VariableLocation::Value(value) => (None, value),
+        dwarf::VariableLocation::OptimizedOut => anyhow::bail!("The variable is optimized out"),
+    };
+    let mut type_info = &variable.type_info;
+    let mut type_name = variable.type_name.clone();
+    let mut warning = None;
+    for accessor in accessors {
+        match (accessor, type_info) {
+            (
+                Accessor::Member(name),
+                dwarf::TypeInfo::Struct { members, .. } | dwarf::TypeInfo::Union { members, .. },
+            ) => {
+                let struct_address = address.ok_or(anyhow!(
+                    "Members of variables stored in registers are not supported"
+                ))?;
+                let member = members
+                    .iter()
+                    .find(|member| member.name == *name)
+                    .ok_or(anyhow!("There is no member called {}", name))?;
+                if member.bit_size.is_some() {
+                    anyhow::bail!("Bitfields can't be accessed after an index");
+                }
+                address = Some(get_member_address(struct_address, member)?);
+                type_info = &member.type_info;
+                type_name = member.type_name.clone();
+            }
+            (Accessor::Member(name), _) => anyhow::bail!(
+                "Can't access {}, the value is not a struct or a union",
+                name
+            ),
+            (
+                Accessor::Index(index),
+                dwarf::TypeInfo::Array {
+                    element_type,
+                    count,
+                },
+            ) => {
+                let array_address = address.ok_or(anyhow!(
+                    "Elements of arrays stored in registers are not supported"
+                ))?;
+                match count {
+                    Some(count) if *index < 0 || index.unsigned_abs() >= *count => {
+                        warning = Some(format!(
+                            "Index {} is out of bounds, the array has {} elements",
+                            index, count
+                        ));
+                    }
+                    None if *index < 0 => {
+                        warning = Some(format!("Index {} is out of bounds", index));
+                    }
+                    _ => {}
+                }
+                address = Some(get_element_address(
+                    array_address,
+                    *index,
+                    element_type.size() / 8,
+                )?);
+                type_info = element_type;
+                type_name = get_element_type_name(&type_name);
+            }
+            (
+                Accessor::Index(index),
+                dwarf::TypeInfo::Pointer {
+                    pointee_type: Some(pointee_type),
+                    ..
+                },
+            ) => {
+                let pointer = match address {
+                    Some(address) => read_value(pid, address, 64)?,
+                    None => register_value,
+                };
+                if pointer == 0 {
+                    anyhow::bail!("Can't index a null pointer");
+                }
+                address = Some(get_element_address(
+                    pointer,
+                    *index,
+                    pointee_type.size() / 8,
+                )?);
+                type_info = pointee_type;
+                type_name = get_pointee_type_name(&type_name);
+            }
+            (Accessor::Index(_), _) => {
+                anyhow::bail!("Only arrays and pointers to known types can be indexed")
+            }
+        }
+    }
+    Ok(Element {
+        // There is at least one index
+        address: address.unwrap(),
+        type_info,
+        type_name,
+        warning,
+    })
+}
+
+// Array types are named like "int [3]" in C and like "[i32; 3]" in Rust
+fn get_element_type_name(type_name: &str) -> String {
+    if let Some(name) = type_name
+        .strip_prefix('[')
+        .and_then(|name| name.strip_suffix(']'))
+        .and_then(|name| name.rsplit_once(';'))
+    {
+        return name.0.trim().to_owned();
+    }
+    match type_name.rfind('[') {
+        Some(start) => type_name[..start].trim_end().to_owned(),
+        None => type_name.to_owned(),
+    }
+}
+
+// Returns the address of the variable or member and the name of its type
+fn get_address_of<'a>(
+    variable: &'a dwarf::VariableInfo,
+    path: &[&str],
+) -> anyhow::Result<(u64, &'a str)> {
+    if path.is_empty() {
+        return match variable.location {
+            dwarf::VariableLocation::Address(address) => Ok((address, &variable.type_name)),
+            dwarf::VariableLocation::Value(_) => {
+                anyhow::bail!("Variables stored in registers don't have an address")
+            }
+            dwarf::VariableLocation::OptimizedOut => {
+                anyhow::bail!("The variable is optimized out")
+            }
+        };
+    }
+    let (struct_address, member) = find_struct_member(variable, path)?;
+    if member.bit_size.is_some() {
+        anyhow::bail!("Bitfields don't have an address");
+    }
+    Ok((
+        get_member_address(struct_address, member)?,
+        &member.type_name,
+    ))
+}
+
+// Returns the value pointed to and the name of its type
+fn format_dereference(
+    pid: Pid,
+    variable: &dwarf::VariableInfo,
+    path: &[&str],
+    dereferences: usize,
+    options: PrintOptions,
+) -> anyhow::Result<(String, String)> {
+    let (pointer, type_info, type_name) = if path.is_empty() {
+        let pointer = match variable.location {
+            dwarf::VariableLocation::Address(address) => read_value(pid, address, 64)?,
+            dwarf::VariableLocation::Value(value) => value,
+            dwarf::VariableLocation::OptimizedOut => anyhow::bail!("The variable is optimized out"),
+        };
+        (pointer, &variable.type_info, variable.type_name.clone())
+    } else {
+        let (struct_address, member) = find_struct_member(variable, path)?;
+        if member.bit_size.is_some() {
+            anyhow::bail!("Bitfields can't be dereferenced");
+        }
+        let pointer = read_value(pid, get_member_address(struct_address, member)?, 64)?;
+        (pointer, &member.type_info, member.type_name.clone())
+    };
+    follow_pointers(pid, pointer, type_info, type_name, dereferences, options)
+}
+
+// Returns the value at the end of the chain of pointers that starts with
+// the given one, and the name of its type
+fn follow_pointers(
+    pid: Pid,
+    mut pointer: u64,
+    mut type_info: &dwarf::TypeInfo,
+    mut type_name: String,
+    dereferences: usize,
+    options: PrintOptions,
+) -> anyhow::Result<(String, String)> {
+    for dereference in 0..dereferences {
+        let dwarf::TypeInfo::Pointer {
+            pointee_type: Some(pointee_type),
+            ..
+        } = type_info
+        else {
+            anyhow::bail!("The value is not a pointer to a known type");
+        };
+        if pointer == 0 {
+            anyhow::bail!("Can't dereference a null pointer");
+        }
+        type_info = pointee_type;
+        type_name = get_pointee_type_name(&type_name);
+        if dereference + 1 < dereferences {
+            pointer = read_value(pid, pointer, 64)?;
+        }
+    }
+    Ok((format_value(pid, pointer, type_info, options)?, type_name))
+}
+
+// Pointer types are named like "int *" in C and like "&i32" or "*const i32"
+// in Rust
+fn get_pointee_type_name(type_name: &str) -> String {
+    if let Some(name) = type_name.strip_suffix('*') {
+        return name.trim_end().to_owned();
+    }
+    ["&mut ", "&", "*const ", "*mut "]
+        .iter()
+        .find_map(|prefix| type_name.strip_prefix(prefix))
+        .unwrap_or(type_name)
+        .to_owned()
+}
+
+// Garbage pointers can put the member past the end of the address space
+fn get_member_address(struct_address: u64, member: &dwarf::Member) -> anyhow::Result<u64> {
+    struct_address.checked_add(member.offset / 8).ok_or(anyhow!(
+        "The member {} is out of the address space",
+        member.name
+    ))
+}
+
+// Returns the address of the struct that contains the member and the member
+fn find_struct_member<'a>(
+    variable: &'a dwarf::VariableInfo,
+    path: &[&str],
+) -> anyhow::Result<(u64, &'a dwarf::Member)> {
+    let mut struct_address = match variable.location {
+        dwarf::VariableLocation::Address(address) => address,
+        dwarf::VariableLocation::Value(_) => {
+            anyhow::bail!("Members of variables stored in registers are not supported")
+        }
+        dwarf::VariableLocation::OptimizedOut => anyhow::bail!("The variable is optimized out"),
+    };
+    let mut type_info = &variable.type_info;
+    let mut selected_member: Option<&dwarf::Member> = None;
+    for name in path {
+        if let Some(member) = selected_member {
+            struct_address = get_member_address(struct_address, member)?;
+            type_info = &member.type_info;
+        }
+        let (dwarf::TypeInfo::Struct { members, .. } | dwarf::TypeInfo::Union { members, .. }) =
+            type_info
+        else {
+            anyhow::bail!(
+                "Can't access {}, the value is not a struct or a union",
+                name
+            );
+        };
+        selected_member = Some(
+            members
+                .iter()
+                .find(|member| member.name == *name)
+                .ok_or(anyhow!("There is no member called {}", name))?,
+        );
+    }
+    let member = selected_member.unwrap(); // The path isn't empty
+    Ok((struct_address, member))
+}
+
+fn format_member(
+    pid: Pid,
+    struct_address: u64,
+    member: &dwarf::Member,
+    options: PrintOptions,
+) -> anyhow::Result<String> {
+    // Only the bytes up to the member are needed
+    let length = member.offset / 8
+        + member
+            .bit_size
+            .unwrap_or(member.type_info.size())
+            .div_ceil(8);
+    let struct_bytes = read_memory(pid, struct_address, length)?;
+    format_member_from_bytes(pid, struct_address, &struct_bytes, member, options)
+}
+
+// The members that fit in a word are taken from the bytes of the struct,
+// which are read at once. The rest are read from their own address
+fn format_member_from_bytes(
+    pid: Pid,
+    struct_address: u64,
+    struct_bytes: &[u8],
+    member: &dwarf::Member,
+    options: PrintOptions,
+) -> anyhow::Result<String> {
+    let member_bytes = struct_bytes
+        .get(member.offset as usize / 8..)
+        .unwrap_or_default();
+    let Some(bit_size) = member.bit_size else {
+        if fits_in_word(&member.type_info) {
+            let value = value_from_bytes(member_bytes, member.type_info.size());
+            return format_scalar_value(pid, value, &member.type_info, options);
+        }
+        let address = get_member_address(struct_address, member)?;
+        return format_value(pid, address, &member.type_info, options);
+    };
+    // Bitfields don't need to start at a byte boundary
+    let value =
+        (value_from_bytes(member_bytes, 64) >> (member.offset % 8)) & low_bits_mask(bit_size);
+    match &member.type_info {
+        dwarf::TypeInfo::Base { base_type, .. } => {
+            Ok(format_base_value(value, base_type, bit_size, options.radix))
+        }
+        type_info => format_scalar_value(pid, value, type_info, options),
+    }
+}
+
+fn fits_in_word(type_info: &dwarf::TypeInfo) -> bool {
+    match type_info {
+        dwarf::TypeInfo::Base { size, .. } => *size <= 64,
+        dwarf::TypeInfo::Pointer { .. } | dwarf::TypeInfo::Enum { .. } => true,
+        dwarf::TypeInfo::Array { .. }
+        | dwarf::TypeInfo::Struct { .. }
+        | dwarf::TypeInfo::Union { .. }
+        | dwarf::TypeInfo::Str { .. } => false,
+    }
+}
+
+// The value in the first bytes, in the byte order of the debugged program.
+// Missing bytes are zero
+fn value_from_bytes(bytes: &[u8], size: u64) -> u64 {
+    let mut word = [0; 8];
+    let length = bytes.len().min(8);
+    word[..length].copy_from_slice(&bytes[..length]);
+    u64::from_le_bytes(word) & low_bits_mask(size)
+}
+
+#[derive(Clone, Copy)]
+pub struct PrintOptions {
+    // Print char arrays as strings instead of as the list of characters
+    pub as_string: bool,
+    // Only used for integers
+    pub radix: Radix,
+    // How many levels of pointers to structs are followed to show the
+    // members of the structs they point to
+    pub max_depth: usize,
+    // How many elements of arrays and characters of strings are shown, 0
+    // for all of them
+    pub max_elements: usize,
+}
+
+impl Default for PrintOptions {
+    fn default() -> Self {
+        Self {
+            as_string: false,
+            radix: Radix::default(),
+            max_depth: 1,
+            max_elements: 200,
+        }
+    }
+}
+
+impl PrintOptions {
+    // Strings without a null character or with a wrong length are cut at
+    // MAX_STRING_LENGTH even without a limit, to avoid reading forever
+    fn max_string_length(&self) -> usize {
+        match self.max_elements {
+            0 => MAX_STRING_LENGTH,
+            max_elements => max_elements.min(MAX_STRING_LENGTH),
+        }
+    }
+}
+
+// Watched values are compared as text, so none of their elements is left out
+pub const WATCH_PRINT_OPTIONS: PrintOptions = PrintOptions {
+    as_string: false,
+    radix: Radix::Decimal,
+    max_depth: 1,
+    max_elements: 0,
+};
+
+#[derive(Clone, Copy, Default)]
+pub enum Radix {
+    #[default]
+    Decimal,
+    Hexadecimal,
+    Octal,
+    Binary,
+}
+
+pub fn format_variable(
+    pid: Pid,
+    variable: &dwarf::VariableInfo,
+    options: PrintOptions,
+) -> anyhow::Result<String> {
+    match variable.location {
+        dwarf::VariableLocation::Address(address) => {
+            format_value(pid, address, &variable.type_info, options)
+        }
+        dwarf::VariableLocation::Value(value) => {
+            let size = variable.type_info.size();
+            let value = value & low_bits_mask(size);
+            format_scalar_value(pid, value, &variable.type_info, options)
+        }
+        // Like gdb, to make clear that the compiler removed it
+        dwarf::VariableLocation::OptimizedOut => Ok(String::from("<optimized out>")),
+    }
+}
+
+pub fn format_value(
+    pid: Pid,
+    address: u64,
+    type_info: &dwarf::TypeInfo,
+    options: PrintOptions,
+) -> anyhow::Result<String> {
+    match type_info {
+        // Its elements can still be printed one by one
+        dwarf::TypeInfo::Array { count: None, .. } => Ok(String::from("<array of unknown length>")),
+        dwarf::TypeInfo::Array {
+            element_type,
+            count: Some(count),
+        } => {
+            let stride = element_type.size() / 8;
+            if let dwarf::TypeInfo::Base { base_type, size } = element_type.as_ref()
+                && options.as_string
+            {
+                let dwarf::BaseType::Char = base_type else {
+                    anyhow::bail!("Only char arrays can be printed as strings");
+                };
+                return format_char_array(pid, address, *size, *count, options);
+            }
+            let shown = match options.max_elements as u64 {
+                0 => *count,
+                max_elements => max_elements.min(*count),
+            };
+            // Arrays of scalars are read at once
+            let mut elements = if fits_in_word(element_type) && stride > 0 {
+                read_memory(pid, address, shown * stride)?
+                    .chunks(stride as usize)
+                    .map(|bytes| {
+                        let value = value_from_bytes(bytes, element_type.size());
+                        format_scalar_value(pid, value, element_type, options)
+                    })
+                    .collect::<anyhow::Result<Vec<_>>>()?
+            } else {
+                (0..shown)
+                    .map(|index| format_value(pid, address + index * stride, element_type, options))
+                    .collect::<anyhow::Result<Vec<_>>>()?
+            };
+            if shown < *count {
+                elements.push(String::from("..."));
+            }
+            Ok(format!("[{}]", elements.join(", ")))
+        }
+        dwarf::TypeInfo::Struct { members, .. } => {
+            let struct_bytes = read_memory(pid, address, type_info.size() / 8)?;
+            let members = members
+                .iter()
+                .map(|member| {
+                    Ok(format!(
+                        "{} = {}",
+                        member.name,
+                        format_member_from_bytes(pid, address, &struct_bytes, member, options)?
+                    ))
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            Ok(format!("{{{}}}", members.join(", ")))
+        }
+        // It's unknown which member holds the value, so all of them are
+        // shown, each one labeled with its type
+        dwarf::TypeInfo::Union { members, .. } => {
+            let union_bytes = read_memory(pid, address, type_info.size() / 8)?;
+            let members = members
+                .iter()
+                .map(|member| {
+                    Ok(format!(
+                        "{} = ({}) {}",
+                        member.name,
+                        member.type_name,
+                        format_member_from_bytes(pid, address, &union_bytes, member, options)?
+                    ))
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            Ok(format!("{{{}}}", members.join(", ")))
+        }
+        dwarf::TypeInfo::Str {
+            data_offset,
+            length_offset,
+            ..
+        } => {
+            let bytes = read_memory(pid, address, type_info.size() / 8)?;
+            // The offsets come from the DWARF, they could be outside of the
+            // slice
+            let word_at = |offset: u64| {
+                let start = offset as usize / 8;
+                bytes
+                    .get(start..start + 8)
+                    .map(|word| value_from_bytes(word, 64))
+                    .ok_or(anyhow!(
+                        "The member at offset {} is outside of the string slice",
+                        offset / 8
+                    ))
+            };
+            format_str(
+                pid,
+                word_at(*data_offset)?,
+                word_at(*length_offset)?,
+                options,
+            )
+        }
+        dwarf::TypeInfo::Base { base_type, size } if *size > 64 => {
+            let bytes = read_memory(pid, address, size / 8)?;
+            Ok(format_wide_base_value(
+                &bytes,
+                base_type,
+                *size,
+                options.radix,
+            ))
+        }
+        _ => {
+            let value = read_value(pid, address, type_info.size())?;
+            format_scalar_value(pid, value, type_info, options)
+        }
+    }
+}
+
+// Formats the types that fit in a single word
+fn format_scalar_value(
+    pid: Pid,
+    value: u64,
+    type_info: &dwarf::TypeInfo,
+    options: PrintOptions,
+) -> anyhow::Result<String> {
+    match type_info {
+        dwarf::TypeInfo::Base { base_type, size } => {
+            Ok(format_base_value(value, base_type, *size, options.radix))
+        }
+        dwarf::TypeInfo::Array { .. } => anyhow::bail!("Arrays don't fit in a single word"),
+        dwarf::TypeInfo::Struct { .. } => anyhow::bail!("Structs don't fit in a single word"),
+        dwarf::TypeInfo::Union { .. } => anyhow::bail!("Unions don't fit in a single word"),
+        dwarf::TypeInfo::Str { .. } => anyhow::bail!("Strings don't fit in a single word"),
+        dwarf::TypeInfo::Pointer {
+            pointee_type,
+            cut_off,
+        } => {
+            let pointer = value;
+            if pointer == 0 {
+                return Ok(format!("{:#x}", pointer));
+            }
+            match pointee_type.as_deref() {
+                Some(dwarf::TypeInfo::Base {
+                    base_type: dwarf::BaseType::Char,
+                    size: 8,
+                }) => Ok(format!(
+                    "{:#x} {}",
+                    pointer,
+                    format_c_string(pid, pointer, options)?
+                )),
+                // Pointers are often uninitialized, that shouldn't prevent
+                // printing the rest of the value
+                Some(
+                    pointee_type @ (dwarf::TypeInfo::Struct { .. }
+                    | dwarf::TypeInfo::Union { .. }
+                    | dwarf::TypeInfo::Str { .. }),
+                ) => match format_value(pid, pointer, pointee_type, options) {
+                    Ok(pointee) => Ok(format!("{:#x} -> {}", pointer, pointee)),
+                    Err(_) => Ok(format!("{:#x} -> <unreadable memory>", pointer)),
+                },
+                _ if *cut_off => Ok(format!("{:#x} -> ...", pointer)),
+                _ => Ok(format!("{:#x}", pointer)),
+            }
+        }
+        dwarf::TypeInfo::Enum {
+            base_type,
+            size,
+            enumerators,
+        } => {
+            let raw_value = format_base_value(value, base_type, *size, options.radix);
+            match enumerators.iter().find(|(_, v)| *v == value) {
+                Some((name, _)) => Ok(format!("{} ({})", name, raw_value)),
+                None => Ok(raw_value),
+            }
+        }
+    }
+}
+
+// Avoid reading forever if the pointer doesn't point to an actual string
+const MAX_STRING_LENGTH: usize = 4096;
+
+fn format_c_string(pid: Pid, address: u64, options: PrintOptions) -> anyhow::Result<String> {
+    const PAGE_SIZE: u64 = 4096;
+    let max_length = options.max_string_length();
+    let mut bytes = Vec::new();
+    let mut terminated = false;
+    while bytes.len() < max_length {
+        let chunk_address = address + bytes.len() as u64;
+        // Reading up to the end of the page, the next one could be unmapped
+        // even if the string ends before it
+        let chunk = read_memory(pid, chunk_address, PAGE_SIZE - chunk_address % PAGE_SIZE)?;
+        if let Some(end) = chunk.iter().position(|&byte| byte == 0) {
+            bytes.extend(&chunk[..end]);
+            terminated = true;
+            break;
+        }
+        bytes.extend(chunk);
+    }
+    let truncated = !terminated || bytes.len() > max_length;
+    bytes.truncate(max_length);
+    let string = String::from_utf8_lossy(&bytes);
+    Ok(format!(
+        "\"{}\"{}",
+        string.escape_debug(),
+        if truncated { "..." } else { "" }
+    ))
+}
+
+// The length is in bytes, and it can be garbage if the string isn't
+// initialized yet
+fn format_str(
+    pid: Pid,
+    address: u64,
+    length: u64,
+    options: PrintOptions,
+) -> anyhow::Result<String> {
+    let max_length = options.max_string_length() as u64;
+    let truncated = length > max_length;
+    let length = length.min(max_length);
+    if length == 0 {
+        return Ok(String::from("\"\""));
+    }
+    let bytes = read_memory(pid, address, length)?;
+    Ok(format!(
+        "\"{}\"{}",
+        String::from_utf8_lossy(&bytes).escape_debug(),
+        if truncated { "..." } else { "" }
+    ))
+}
+
+fn format_char_array(
+    pid: Pid,
+    address: u64,
+    size: u64,
+    count: u64,
+    options: PrintOptions,
+) -> anyhow::Result<String> {
+    let mut string = String::new();
+    let mut truncated = false;
+    let bytes = read_memory(pid, address, count * size / 8)?;
+    for (index, character) in bytes.chunks(size as usize / 8).enumerate() {
+        let mut value = [0; 4];
+        value[..character.len()].copy_from_slice(character);
+        let value = u32::from_le_bytes(value);
+        // C strings end at the first null character, even if the array is longer
+        if value == 0 {
+            break;
+        }
+        if options.max_elements != 0 && index == options.max_elements {
+            truncated = true;
+            break;
+        }
+        string.push(char::from_u32(value).unwrap_or(char::REPLACEMENT_CHARACTER));
+    }
+    Ok(format!(
+        "\"{}\"{}",
+        string.escape_debug(),
+        if truncated { "..." } else { "" }
+    ))
+}
+
+// Reads the whole range with a single system call. ptrace, which reads a
+// word at a time, is used if that fails, since process_vm_readv can be
+// forbidden or stop at a page that ptrace can still read
+fn read_memory(pid: Pid, address: u64, length: u64) -> anyhow::Result<Vec<u8>> {
+    let mut bytes = vec![0; length as usize];
+    let remote = [RemoteIoVec {
+        base: address as usize,
+        len: length as usize,
+    }];
+    if let Ok(read) = process_vm_readv(pid, &mut [IoSliceMut::new(&mut bytes)], &remote)
+        && read == bytes.len()
+    {
+        return Ok(bytes);
+    }
+    let mut bytes = Vec::with_capacity(length as usize);
+    while (bytes.len() as u64) < length {
+        let word_address = address + bytes.len() as u64;
+        let word = pid.read_word(word_address)?;
+        bytes.extend(word.to_ne_bytes());
+    }
+    bytes.truncate(length as usize);
+    Ok(bytes)
+}
+
+// Values up to a word, the bigger ones are read with read_memory
+fn read_value(pid: Pid, address: u64, size: u64) -> anyhow::Result<u64> {
+    if size > 64 {
+        anyhow::bail!("Values of {} bits don't fit in a single word", size);
+    }
+    // Like the unit type of Rust
+    if size == 0 {
+        return Ok(0);
+    }
+    // Only x86-64 programs are debugged, so the value is in the lowest
+    // bytes of the word
+    let word = pid.read_word(address)? as u64;
+    Ok(word & low_bits_mask(size))
+}
+
+// The lowest bits of a word, none for 0 bits and all of them from 64
+pub fn low_bits_mask(bits: u64) -> u64 {
+    match bits {
+        0 => 0,
+        1..64 => u64::MAX >> (64 - bits),
+        _ => u64::MAX,
+    }
+}
+
+// Interprets the lowest bits of the word as a signed integer of that size
+fn sign_extend(value: u64, bits: u64) -> i64 {
+    match bits {
+        0 => 0,
+        1..64 => {
+            let unused_bits = 64 - bits;
+            ((value << unused_bits) as i64) >> unused_bits
+        }
+        _ => value as i64,
+    }
+}
+
+fn format_base_value(
+    value: u64,
+    base_type: &dwarf::BaseType,
+    size: u64,
+    radix: Radix,
+) -> String {
+    match base_type {
+        // Only the bytes of the bool count, the rest of the word can have
+        // anything
+        dwarf::BaseType::Boolean => (value & low_bits_mask(size) != 0).to_string(),
+        dwarf::BaseType::Char => format!(
+            "'{}'",
+            char::from_u32(value as u32)
+                .unwrap_or(char::REPLACEMENT_CHARACTER)
+                .escape_debug()
+        ),
+        dwarf::BaseType::Float => match size {
+            32 => f32::from_bits(value as u32).to_string(),
+            64 => f64::from_bits(value).to_string(),
+            _ => format!("<{} bit float>", size),
+        },
+        // Other radixes show the bits as they are, like gdb does
+        dwarf::BaseType::Signed if matches!(radix, Radix::Decimal) => {
+            sign_extend(value, size).to_string()
+        }
+        dwarf::BaseType::Signed | dwarf::BaseType::Unsigned => match radix {
+            Radix::Decimal => value.to_string(),
+            Radix::Hexadecimal => format!("{:#x}", value),
+            Radix::Octal => format!("{:#o}", value),
+            Radix::Binary => format!("{:#b}", value),
+        },
+    }
+}
+
+// Integers of up to 128 bits, like __int128, and the 80 bit floats of x87
+// used for long double, which take 128 bits in memory
+pub fn format_wide_base_value(
+    bytes: &[u8],
+    base_type: &dwarf::BaseType,
+    size: u64,
+    radix: Radix,
+) -> String {
+    let mut value = [0; 16];
+    let length = bytes.len().min(16);
+    value[..length].copy_from_slice(&bytes[..length]);
+    let value = u128::from_le_bytes(value);
+    match base_type {
+        dwarf::BaseType::Float if size == 128 => format_x87_float(value).to_string(),
+        dwarf::BaseType::Signed if matches!(radix, Radix::Decimal) && (1..=128).contains(&size) => {
+            let unused_bits = 128 - size;
+            (((value << unused_bits) as i128) >> unused_bits).to_string()
+        }
+        dwarf::BaseType::Signed | dwarf::BaseType::Unsigned if size <= 128 => match radix {
+            Radix::Decimal => value.to_string(),
+            Radix::Hexadecimal => format!("{:#x}", value),
+            Radix::Octal => format!("{:#o}", value),
+            Radix::Binary => format!("{:#b}", value),
+        },
+        _ => format!("<{} bit value>", size),
+    }
+}
+
+// The x87 format has an explicit integer bit in its 64 bit mantissa, a 15
+// bit exponent and a sign. It's shown with the precision of a double
+fn format_x87_float(value: u128) -> f64 {
+    let mantissa = value as u64;
+    let exponent = ((value >> 64) & 0x7FFF) as i32;
+    let sign = if (value >> 79) & 1 == 1 { -1.0 } else { 1.0 };
+    if exponent == 0x7FFF {
+        // Only the integer bit set means infinity, anything else is a NaN
+        return if mantissa << 1 == 0 {
+            sign * f64::INFINITY
+        } else {
+            f64::NAN
+        };
+    }
+    // Denormals have the same exponent as the smallest normal numbers
+    let exponent = exponent.max(1) - 16383 - 63;
+    // Split the power to avoid overflowing before multiplying
+    sign * mantissa as f64 * 2f64.powi(exponent / 2) * 2f64.powi(exponent - exponent / 2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format_signed(value: u64, size: u64) -> String {
+        format_base_value(value, &dwarf::BaseType::Signed, size, Radix::Decimal)
+    }
+
+    #[test]
+    fn formats_negative_integers_of_every_size() {
+        assert_eq!(format_signed(0xff, 8), "-1");
+        assert_eq!(format_signed(0x80, 8), "-128");
+        assert_eq!(format_signed(0x7f, 8), "127");
+        assert_eq!(format_signed(0x8000, 16), "-32768");
+        assert_eq!(format_signed(0xfffe, 16), "-2");
+        assert_eq!(format_signed(0xffff_fff6, 32), "-10");
+        assert_eq!(format_signed(0x8000_0000, 32), "-2147483648");
+    }
+
+    // There are no unused bits to extend the sign into
+    #[test]
+    fn formats_64_bit_integers() {
+        assert_eq!(format_signed(u64::MAX, 64), "-1");
+        assert_eq!(format_signed(i64::MIN as u64, 64), "-9223372036854775808");
+        assert_eq!(format_signed(i64::MAX as u64, 64), "9223372036854775807");
+        assert_eq!(
+            format_base_value(u64::MAX, &dwarf::BaseType::Unsigned, 64, Radix::Decimal),
+            "18446744073709551615"
+        );
+    }
+
+    // Other radixes show the bits of the value, without extending the sign
+    #[test]
+    fn formats_negative_integers_in_hexadecimal() {
+        assert_eq!(
+            format_base_value(0xff, &dwarf::BaseType::Signed, 8, Radix::Hexadecimal),
+            "0xff"
+        );
+    }
+}
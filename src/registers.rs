@@ -1,7 +1,46 @@
 use gimli::Register;
-use nix::libc::user_regs_struct;
+use nix::{
+    libc::{c_long, user, user_regs_struct},
+    sys::ptrace::{self, AddressType, regset::NT_PRFPREG},
+    unistd::Pid,
+};
+use std::mem::offset_of;
 
-pub fn get_register_value(regs: &user_regs_struct, register: Register) -> anyhow::Result<u64> {
+// The registers needed to evaluate the location of variables. Floating
+// point variables are usually stored in the XMM registers
+#[derive(Clone, Copy)]
+pub struct Registers {
+    pub general: user_regs_struct,
+    pub xmm: [u128; 16],
+}
+
+pub fn get_xmm_registers(pid: Pid) -> anyhow::Result<[u128; 16]> {
+    let fp_registers = ptrace::getregset::<NT_PRFPREG>(pid)?;
+    // Each register is stored as four 32 bit words, the least significant first
+    Ok(std::array::from_fn(|index| {
+        fp_registers.xmm_space[index * 4..index * 4 + 4]
+            .iter()
+            .rev()
+            .fold(0, |register, &word| (register << 32) | word as u128)
+    }))
+}
+
+// The x87 stack, from st0. Each register takes 128 bits, the 80 of the
+// value are the lowest ones
+pub fn get_st_registers(pid: Pid) -> anyhow::Result<[u128; 8]> {
+    let fp_registers = ptrace::getregset::<NT_PRFPREG>(pid)?;
+    Ok(std::array::from_fn(|index| {
+        fp_registers.st_space[index * 4..index * 4 + 4]
+            .iter()
+            .rev()
+            .fold(0, |register, &word| (register << 32) | word as u128)
+    }))
+}
+
+// Only the lower 64 bits of the XMM registers are returned, which is
+// enough for float and double values
+pub fn get_register_value(registers: &Registers, register: Register) -> anyhow::Result<u64> {
+    let regs = &registers.general;
     match register.0 {
         0 => Ok(regs.rax),
         1 => Ok(regs.rdx),
@@ -20,6 +59,110 @@ pub fn get_register_value(regs: &user_regs_struct, register: Register) -> anyhow
         14 => Ok(regs.r14),
         15 => Ok(regs.r15),
         16 => Ok(regs.rip),
+        17..=32 => Ok(registers.xmm[register.0 as usize - 17] as u64),
         _ => anyhow::bail!("Invalid register number"),
     }
 }
+
+// In the order used by info registers
+pub const REGISTER_NAMES: [&str; 24] = [
+    "rax", "rbx", "rcx", "rdx", "rsi", "rdi", "rbp", "rsp", "r8", "r9", "r10", "r11", "r12", "r13",
+    "r14", "r15", "rip", "eflags", "cs", "ss", "ds", "es", "fs", "gs",
+];
+
+// Also accepts the names of the lower 32 bits of the registers, like eax or r8d
+pub fn get_register_value_by_name(regs: &user_regs_struct, name: &str) -> Option<u64> {
+    let value = match name {
+        "rax" => regs.rax,
+        "rbx" => regs.rbx,
+        "rcx" => regs.rcx,
+        "rdx" => regs.rdx,
+        "rsi" => regs.rsi,
+        "rdi" => regs.rdi,
+        "rbp" => regs.rbp,
+        "rsp" => regs.rsp,
+        "r8" => regs.r8,
+        "r9" => regs.r9,
+        "r10" => regs.r10,
+        "r11" => regs.r11,
+        "r12" => regs.r12,
+        "r13" => regs.r13,
+        "r14" => regs.r14,
+        "r15" => regs.r15,
+        "rip" => regs.rip,
+        "eflags" => regs.eflags,
+        "cs" => regs.cs,
+        "ss" => regs.ss,
+        "ds" => regs.ds,
+        "es" => regs.es,
+        "fs" => regs.fs,
+        "gs" => regs.gs,
+        "fs_base" => regs.fs_base,
+        "gs_base" => regs.gs_base,
+        _ => {
+            let full_name = match name {
+                "eax" | "ebx" | "ecx" | "edx" | "esi" | "edi" | "ebp" | "esp" | "eip" => {
+                    name.replacen('e', "r", 1)
+                }
+                "r8d" | "r9d" | "r10d" | "r11d" | "r12d" | "r13d" | "r14d" | "r15d" => {
+                    name.trim_end_matches('d').to_owned()
+                }
+                _ => return None,
+            };
+            return get_register_value_by_name(regs, &full_name).map(|value| value & 0xFFFF_FFFF);
+        }
+    };
+    Some(value)
+}
+
+const DEBUG_STATUS_REGISTER: usize = 6;
+const DEBUG_CONTROL_REGISTER: usize = 7;
+
+fn debug_register_offset(index: usize) -> AddressType {
+    (offset_of!(user, u_debugreg) + index * size_of::<u64>()) as AddressType
+}
+
+// Uses the debug register at index (0 to 3) to stop the program after
+// writing to the given address
+pub fn set_watchpoint(pid: Pid, index: usize, address: u64, length: u64) -> anyhow::Result<()> {
+    let length_bits = match length {
+        1 => 0b00,
+        2 => 0b01,
+        4 => 0b11,
+        8 => 0b10,
+        _ => anyhow::bail!("Invalid watchpoint length"),
+    };
+    // Only break on data writes
+    let condition_bits = 0b01;
+    ptrace::write_user(pid, debug_register_offset(index), address as c_long)?;
+    let offset = debug_register_offset(DEBUG_CONTROL_REGISTER);
+    let mut control = ptrace::read_user(pid, offset)? as u64;
+    control &= !(0b1111 << (16 + index * 4));
+    control |= ((length_bits << 2) | condition_bits) << (16 + index * 4);
+    // Local enable bit
+    control |= 1 << (index * 2);
+    ptrace::write_user(pid, offset, control as c_long)?;
+    Ok(())
+}
+
+// Disables the debug register at index, undoing set_watchpoint
+pub fn clear_watchpoint(pid: Pid, index: usize) -> anyhow::Result<()> {
+    let offset = debug_register_offset(DEBUG_CONTROL_REGISTER);
+    let mut control = ptrace::read_user(pid, offset)? as u64;
+    control &= !(0b1111 << (16 + index * 4));
+    control &= !(1 << (index * 2));
+    ptrace::write_user(pid, offset, control as c_long)?;
+    ptrace::write_user(pid, debug_register_offset(index), 0)?;
+    Ok(())
+}
+
+pub fn get_triggered_watchpoint(pid: Pid) -> anyhow::Result<Option<usize>> {
+    let status = ptrace::read_user(pid, debug_register_offset(DEBUG_STATUS_REGISTER))?;
+    Ok((0..4).find(|index| status & (1 << index) != 0))
+}
+
+// The processor never clears the status register by itself
+pub fn clear_watchpoint_status(pid: Pid) -> anyhow::Result<()> {
+    ptrace::write_user(pid, debug_register_offset(DEBUG_STATUS_REGISTER), 0)?;
+    Ok(())
+}
@@ -1,11 +1,22 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, IsTerminal},
+    path::Path,
+};
+
+use anyhow::Context;
+
+use debugito::output::{self, Json, Output};
 
 use reedline::{
     ColumnarMenu, Completer, Emacs, KeyCode, KeyModifiers, MenuBuilder, Reedline, ReedlineEvent,
     ReedlineMenu, Signal, Suggestion, default_emacs_keybindings,
 };
 
-type Action<T> = fn(&clap::ArgMatches, &mut T) -> anyhow::Result<String>;
+// Most commands only return text, the ones with an Output also describe
+// their result in the JSON mode
+type Action<T> = Box<dyn Fn(&clap::ArgMatches, &mut T) -> anyhow::Result<Output>>;
 
 struct Command<T> {
     clap_representation: clap::Command,
@@ -15,6 +26,9 @@ struct Command<T> {
 pub struct Repl<T> {
     context: T,
     commands: HashMap<String, Command<T>>,
+    // Defined by the user with the alias command, each one is replaced by
+    // the words of its command
+    aliases: HashMap<String, Vec<String>>,
 }
 
 impl<T> Repl<T> {
@@ -22,25 +36,34 @@ impl<T> Repl<T> {
         Self {
             context,
             commands: HashMap::default(),
+            aliases: HashMap::default(),
         }
     }
 
-    pub fn add_command(mut self, command: clap::Command, action: Action<T>) -> Self {
+    pub fn add_command<R: Into<Output> + 'static>(
+        mut self,
+        command: clap::Command,
+        action: fn(&clap::ArgMatches, &mut T) -> anyhow::Result<R>,
+    ) -> Self
+    where
+        T: 'static,
+    {
         self.commands.insert(
             command.get_name().to_string(),
             Command {
                 clap_representation: command.disable_help_flag(true),
-                action,
+                action: Box::new(move |args, context| action(args, context).map(Into::into)),
             },
         );
         Self {
             context: self.context,
             commands: self.commands,
+            aliases: self.aliases,
         }
     }
 
     fn get_help(&self) -> String {
-        let mut command = clap::Command::new("Debugito");
+        let mut command = clap::Command::new("Debugito").subcommand(alias_command());
         for subcommand in self.commands.values() {
             command = command.subcommand(subcommand.clap_representation.clone());
         }
@@ -50,7 +73,7 @@ impl<T> Repl<T> {
     }
 
     pub fn run(&mut self) -> anyhow::Result<()> {
-        let completer = Box::new(CustomCompleter::new(&self.commands));
+        let completer = Box::new(CustomCompleter::new(&self.commands, &self.aliases));
         // Use the interactive menu to select options from the completer
         let completion_menu = Box::new(ColumnarMenu::default().with_name("completion_menu"));
         // Set up the required keybindings
@@ -70,11 +93,21 @@ impl<T> Repl<T> {
             .with_completer(completer)
             .with_menu(ReedlineMenu::EngineCompleter(completion_menu))
             .with_edit_mode(edit_mode);
-        let prompt = CustomPrompt::new();
+        let prompt = CustomPrompt::new(">");
         loop {
             let signal = line_editor.read_line(&prompt)?;
             match signal {
-                Signal::Success(buffer) => self.run_command(buffer),
+                Signal::Success(buffer) => {
+                    let alias_count = self.aliases.len();
+                    self.run_command(buffer);
+                    // New aliases can be completed too
+                    if self.aliases.len() != alias_count {
+                        line_editor = line_editor.with_completer(Box::new(CustomCompleter::new(
+                            &self.commands,
+                            &self.aliases,
+                        )));
+                    }
+                }
                 Signal::CtrlD | Signal::CtrlC => {
                     println!("\nAborted!");
                     return Ok(());
@@ -83,7 +116,110 @@ impl<T> Repl<T> {
         }
     }
 
-    fn run_command(&mut self, buffer: String) {
+    // Runs each line of the file as a command, without the line editor.
+    // Stops at the first command that fails
+    pub fn run_script(&mut self, path: &Path) -> anyhow::Result<()> {
+        let script = fs::read_to_string(path)
+            .with_context(|| format!("Couldn't read {}", path.to_string_lossy()))?;
+        for (index, line) in script.lines().enumerate() {
+            let line = line.trim();
+            // Empty lines and comments are allowed to make scripts readable
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            // The results already say which command they belong to
+            if !output::json_output() {
+                println!(">{}", line);
+            }
+            if !self.run_command(line.to_owned()) {
+                anyhow::bail!("The command at line {} failed", index + 1);
+            }
+        }
+        Ok(())
+    }
+
+    fn is_command(&self, word: &str) -> bool {
+        self.commands.values().any(|command| {
+            command
+                .clap_representation
+                .get_name_and_visible_aliases()
+                .contains(&word)
+        })
+    }
+
+    fn define_alias(&mut self, args: &clap::ArgMatches) -> anyhow::Result<String> {
+        let Some(name) = args.get_one::<String>("name") else {
+            let mut aliases = self
+                .aliases
+                .iter()
+                .map(|(name, words)| format!("{} = {}", name, words.join(" ")))
+                .collect::<Vec<_>>();
+            if aliases.is_empty() {
+                return Ok(String::from("No aliases defined"));
+            }
+            aliases.sort();
+            return Ok(aliases.join("\n"));
+        };
+        let mut words = args
+            .get_many::<String>("command")
+            .into_iter()
+            .flatten()
+            .cloned()
+            .collect::<Vec<_>>();
+        // The equals sign is optional, as in "alias name = command"
+        if words.first().is_some_and(|word| word == "=") {
+            words.remove(0);
+        }
+        if name == "alias" || self.is_command(name) {
+            anyhow::bail!("{} is already a command", name);
+        }
+        let Some(first_word) = words.first() else {
+            anyhow::bail!("Missing the command of the alias");
+        };
+        // Aliases of aliases are expanded now, so they can't loop
+        if let Some(expansion) = self.aliases.get(first_word) {
+            words.splice(0..1, expansion.clone());
+        } else {
+            let command_name = match self.resolve_command_prefix(first_word) {
+                Ok(command_name) => command_name.to_owned(),
+                Err(candidates) => {
+                    anyhow::bail!("Ambiguous command, it could be: {}", candidates.join(", "))
+                }
+            };
+            if !self.is_command(&command_name) {
+                anyhow::bail!("Unknown command {}", command_name);
+            }
+            words[0] = command_name;
+        }
+        let message = format!("{} is now an alias of {}", name, words.join(" "));
+        self.aliases.insert(name.clone(), words);
+        Ok(message)
+    }
+
+    // Like gdb, commands can be abbreviated as long as only one of them
+    // starts with the given prefix. Returns the candidates if there are more
+    fn resolve_command_prefix<'a>(&'a self, word: &'a str) -> Result<&'a str, Vec<&'a str>> {
+        if word == "alias" || self.is_command(word) {
+            return Ok(word);
+        }
+        let mut candidates = self
+            .commands
+            .keys()
+            .map(String::as_str)
+            .chain(["alias"])
+            .filter(|name| name.starts_with(word))
+            .collect::<Vec<_>>();
+        candidates.sort();
+        match candidates.as_slice() {
+            [command_name] => Ok(command_name),
+            // Let clap report unknown commands
+            [] => Ok(word),
+            _ => Err(candidates),
+        }
+    }
+
+    // Returns whether the command succeeded
+    fn run_command(&mut self, buffer: String) -> bool {
         let parser = clap::Command::new("app")
             .subcommands(
                 self.commands
@@ -91,132 +227,311 @@ impl<T> Repl<T> {
                     .map(|v| v.clap_representation.clone())
                     .collect::<Vec<clap::Command>>(),
             )
+            .subcommand(alias_command())
             .no_binary_name(true);
-        let matches = parser.try_get_matches_from(buffer.split_whitespace());
+        let mut words = buffer.split_whitespace().collect::<Vec<_>>();
+        if let Some(expansion) = words.first().and_then(|word| self.aliases.get(*word)) {
+            words.splice(0..1, expansion.iter().map(String::as_str));
+        } else if let Some(first_word) = words.first_mut() {
+            match self.resolve_command_prefix(first_word) {
+                Ok(command_name) => *first_word = command_name,
+                Err(candidates) => {
+                    print_result(
+                        None,
+                        Err(anyhow::anyhow!(
+                            "Ambiguous command, it could be: {}",
+                            candidates.join(", ")
+                        )),
+                        None,
+                    );
+                    return false;
+                }
+            }
+        }
+        // Mistakes in the arguments are reported with the help of the
+        // command that was being used
+        let attempted = words
+            .iter()
+            .map(|word| word.to_string())
+            .collect::<Vec<_>>();
+        let matches = parser.try_get_matches_from(words);
+        if let Err(err) = &matches
+            && let Some(command_name) = attempted.first()
+            && let Some(command) = match command_name.as_str() {
+                "alias" => Some(alias_command()),
+                name => self
+                    .commands
+                    .get(name)
+                    .map(|command| command.clap_representation.clone()),
+            }
+        {
+            let command = find_subcommand(command, &attempted[1..]);
+            if matches!(err.kind(), clap::error::ErrorKind::DisplayHelp) {
+                print!("{}", err);
+                return true;
+            }
+            return print_result(
+                Some(command_name),
+                Err(anyhow::anyhow!(describe_parse_error(err))),
+                Some(&command),
+            );
+        }
         if let Ok(matches) = matches {
+            if let Some(("alias", args)) = matches.subcommand() {
+                let result = self.define_alias(args).map(Output::new);
+                return print_result(Some("alias"), result, Some(&alias_command()));
+            }
             if let Some((command_name, args)) = matches.subcommand() {
                 let command = self.commands.get_mut(command_name).unwrap();
                 let result = (command.action)(args, &mut self.context);
-                match result {
-                    Ok(message) => println!("{}\n", message),
-                    Err(message) => {
-                        println!("{}\n", message);
-                        println!("{}", command.clap_representation.render_help());
-                    }
-                }
+                return print_result(
+                    Some(command_name),
+                    result,
+                    Some(&command.clap_representation),
+                );
             }
+            true
         } else {
-            println!("{}", self.get_help());
+            if output::json_output() {
+                print_result(None, Err(anyhow::anyhow!("Unknown command")), None);
+            } else {
+                println!("{}", self.get_help());
+            }
+            false
         }
     }
 }
 
+// Failed commands show their help, except in the JSON mode. Returns
+// whether the command succeeded
+fn print_result(
+    command_name: Option<&str>,
+    result: anyhow::Result<Output>,
+    command: Option<&clap::Command>,
+) -> bool {
+    let success = result.is_ok();
+    if output::json_output() {
+        let mut fields = vec![("event", Json::from("result"))];
+        if let Some(command_name) = command_name {
+            fields.push(("command", Json::from(command_name)));
+        }
+        fields.push(("success", Json::from(success)));
+        match result {
+            Ok(result) => {
+                fields.extend(result.fields);
+                fields.push(("message", Json::String(result.text)));
+            }
+            Err(err) => fields.push(("message", Json::String(err.to_string()))),
+        }
+        println!("{}", Json::object(fields));
+        return success;
+    }
+    match result {
+        Ok(result) => println!("{}\n", result.text),
+        Err(err) => {
+            println!("{}\n", err);
+            if let Some(command) = command {
+                println!("{}", command.clone().render_help());
+            }
+        }
+    }
+    success
+}
+
+// The innermost subcommand named by the words, like "set print elements"
+fn find_subcommand(command: clap::Command, words: &[String]) -> clap::Command {
+    match words.first().and_then(|word| command.find_subcommand(word)) {
+        Some(subcommand) => find_subcommand(subcommand.clone(), &words[1..]),
+        None => command,
+    }
+}
+
+// Only the first part of the message of clap, print_result adds the help
+// of the command after it
+fn describe_parse_error(err: &clap::Error) -> String {
+    let message = err.to_string();
+    let message = message.split("\n\n").next().unwrap_or_default();
+    message
+        .strip_prefix("error: ")
+        .unwrap_or(message)
+        .trim_end()
+        .to_owned()
+}
+
+// Handled by the REPL itself, since it changes the commands instead of the
+// context
+fn alias_command() -> clap::Command {
+    clap::Command::new("alias")
+        .arg(clap::Arg::new("name").help("new name, lists the aliases if missing"))
+        .arg(
+            clap::Arg::new("command")
+                .trailing_var_arg(true)
+                .allow_hyphen_values(true)
+                .num_args(0..)
+                .help("command and arguments it stands for, as in \"alias pc = print -f x\""),
+        )
+        .about("Define a new name for a command")
+        .disable_help_flag(true)
+}
+
+// Asks a yes or no question with its own line editor, so it behaves like
+// the rest of the REPL. Interrupting it or reaching the end of the input
+// means no
+pub fn ask_for_confirmation(message: &str) -> bool {
+    output::event("question", Output::new(message.to_owned()));
+    // Scripts are read from a file, there is no terminal to edit the line
+    if !io::stdin().is_terminal() {
+        return io::stdin()
+            .lines()
+            .next()
+            .and_then(Result::ok)
+            .is_some_and(|answer| answer.trim() == "y");
+    }
+    let mut line_editor = Reedline::create();
+    let prompt = CustomPrompt::new("(y/n) ");
+    matches!(
+        line_editor.read_line(&prompt),
+        Ok(Signal::Success(answer)) if answer.trim() == "y"
+    )
+}
+
 struct CustomCompleter {
     commands: Vec<String>,
+    // Commands whose argument is a path to a file, like a binary to load.
+    // Completing a file for these finishes the argument
+    file_path_commands: Vec<String>,
 }
 
 impl CustomCompleter {
-    fn new<T>(commands: &HashMap<String, Command<T>>) -> Self {
+    fn new<T>(
+        commands: &HashMap<String, Command<T>>,
+        aliases: &HashMap<String, Vec<String>>,
+    ) -> Self {
         Self {
             commands: commands
                 .values()
                 .flat_map(|command| command.clap_representation.get_name_and_visible_aliases())
                 .map(String::from)
+                .chain(aliases.keys().cloned())
+                .chain([String::from("alias")])
+                .collect(),
+            file_path_commands: commands
+                .values()
+                .filter(|command| {
+                    command
+                        .clap_representation
+                        .get_positionals()
+                        .any(|arg| arg.get_value_hint() == clap::ValueHint::FilePath)
+                })
+                .flat_map(|command| command.clap_representation.get_name_and_visible_aliases())
+                .map(String::from)
                 .collect(),
         }
     }
 }
 
 impl Completer for CustomCompleter {
+    // Only the word under the cursor is completed, the suggestions replace
+    // it from its start to the cursor
     fn complete(&mut self, line: &str, pos: usize) -> Vec<Suggestion> {
-        let command_completions = self
-            .commands
-            .iter()
-            .filter(|command| command.starts_with(line))
-            .map(|command| Suggestion {
-                value: command.to_string(),
-                description: None,
-                style: None,
-                extra: None,
-                span: reedline::Span { start: 0, end: pos },
-                append_whitespace: true,
-            })
-            .collect::<Vec<_>>();
-        if !command_completions.is_empty() {
-            return command_completions;
-        }
-        if let Some(_) = self
-            .commands
-            .iter()
-            .find(|&command| line.starts_with(&format!("{command} ")))
-        {
-            let last_word_start = line.rfind(" ").unwrap() + 1;
-            let options = glob::MatchOptions {
-                case_sensitive: false,
-                require_literal_separator: false,
-                require_literal_leading_dot: false,
-            };
-            let pattern = &format!("{}*", &line[last_word_start..]);
-            let collect = glob::glob_with(pattern, options)
-                .unwrap()
-                .map(|entry| {
-                    let path = entry.unwrap();
-                    let mut path_str = path.to_string_lossy().into_owned();
-                    if path.is_dir() {
-                        path_str += "/";
-                    }
-                    let span = reedline::Span {
-                        start: last_word_start,
-                        end: std::cmp::min(last_word_start + path_str.len(), pos),
-                    };
-                    Suggestion {
-                        value: path_str,
-                        description: None,
-                        style: None,
-                        extra: None,
-                        span,
-                        append_whitespace: false,
-                    }
+        let line = &line[..pos];
+        let word_start = line.rfind(char::is_whitespace).map_or(0, |index| index + 1);
+        let word = &line[word_start..];
+        let span = reedline::Span {
+            start: word_start,
+            end: pos,
+        };
+        let mut words = line.split_whitespace();
+        // The first word is the command
+        if line[..word_start].trim().is_empty() {
+            return self
+                .commands
+                .iter()
+                .filter(|command| command.starts_with(word))
+                .map(|command| Suggestion {
+                    value: command.to_string(),
+                    description: None,
+                    style: None,
+                    extra: None,
+                    span,
+                    append_whitespace: true,
                 })
                 .collect();
-            return collect;
         }
-        vec![]
+        let Some(command) = words.next() else {
+            return vec![];
+        };
+        if !self.commands.iter().any(|known| known == command) {
+            return vec![];
+        }
+        let completes_file_paths = self.file_path_commands.iter().any(|known| known == command);
+        let options = glob::MatchOptions {
+            case_sensitive: false,
+            require_literal_separator: false,
+            require_literal_leading_dot: false,
+        };
+        // Words with characters that aren't valid in a pattern have nothing
+        // to complete
+        let Ok(paths) = glob::glob_with(&format!("{}*", word), options) else {
+            return vec![];
+        };
+        paths
+            .filter_map(Result::ok)
+            .map(|path| {
+                let mut path_str = path.to_string_lossy().into_owned();
+                let is_dir = path.is_dir();
+                if is_dir {
+                    path_str += "/";
+                }
+                Suggestion {
+                    value: path_str,
+                    description: None,
+                    style: None,
+                    extra: None,
+                    span,
+                    append_whitespace: completes_file_paths && !is_dir,
+                }
+            })
+            .collect()
     }
 }
 
-struct CustomPrompt {}
+struct CustomPrompt {
+    indicator: String,
+}
 
 impl CustomPrompt {
-    fn new() -> Self {
-        Self {}
+    fn new(indicator: &str) -> Self {
+        Self {
+            indicator: indicator.to_owned(),
+        }
     }
 }
 
 impl reedline::Prompt for CustomPrompt {
-    fn render_prompt_left(&self) -> std::borrow::Cow<str> {
+    fn render_prompt_left(&self) -> std::borrow::Cow<'_, str> {
         std::borrow::Cow::Borrowed("")
     }
 
-    fn render_prompt_right(&self) -> std::borrow::Cow<str> {
+    fn render_prompt_right(&self) -> std::borrow::Cow<'_, str> {
         std::borrow::Cow::Borrowed("")
     }
 
     fn render_prompt_indicator(
         &self,
         _prompt_mode: reedline::PromptEditMode,
-    ) -> std::borrow::Cow<str> {
-        std::borrow::Cow::Borrowed(">")
+    ) -> std::borrow::Cow<'_, str> {
+        std::borrow::Cow::Borrowed(&self.indicator)
     }
 
-    fn render_prompt_multiline_indicator(&self) -> std::borrow::Cow<str> {
+    fn render_prompt_multiline_indicator(&self) -> std::borrow::Cow<'_, str> {
         std::borrow::Cow::Borrowed(">>")
     }
 
     fn render_prompt_history_search_indicator(
         &self,
         _history_search: reedline::PromptHistorySearch,
-    ) -> std::borrow::Cow<str> {
+    ) -> std::borrow::Cow<'_, str> {
         std::borrow::Cow::Borrowed("Search>")
     }
 }
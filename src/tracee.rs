@@ -0,0 +1,213 @@
+use nix::{
+    libc::user_regs_struct,
+    sys::{
+        ptrace,
+        signal::Signal,
+        wait::{WaitPidFlag, WaitStatus, waitpid},
+    },
+    unistd::Pid,
+};
+
+// The operations the debugger does on a stopped thread of the program. The
+// breakpoints and the stepping go through them instead of calling ptrace, so
+// they can work with something other than a real process
+pub trait Tracee: Copy {
+    fn read_word(self, address: u64) -> anyhow::Result<i64>;
+    fn write_word(self, address: u64, word: i64) -> anyhow::Result<()>;
+    fn get_regs(self) -> anyhow::Result<user_regs_struct>;
+    fn set_regs(self, registers: user_regs_struct) -> anyhow::Result<()>;
+    // Resumes the thread, delivering the signal if there's one
+    fn cont(self, signal: Option<Signal>) -> anyhow::Result<()>;
+    // Like cont, but it stops again after executing one instruction
+    fn step(self, signal: Option<Signal>) -> anyhow::Result<()>;
+    // Waits until the thread stops again or exits, the other threads of the
+    // program aren't waited for
+    fn wait(self) -> anyhow::Result<WaitStatus>;
+}
+
+// The threads of a real process, traced with ptrace
+impl Tracee for Pid {
+    fn read_word(self, address: u64) -> anyhow::Result<i64> {
+        Ok(ptrace::read(self, address as ptrace::AddressType)?)
+    }
+
+    fn write_word(self, address: u64, word: i64) -> anyhow::Result<()> {
+        Ok(ptrace::write(self, address as ptrace::AddressType, word)?)
+    }
+
+    fn get_regs(self) -> anyhow::Result<user_regs_struct> {
+        Ok(ptrace::getregs(self)?)
+    }
+
+    fn set_regs(self, registers: user_regs_struct) -> anyhow::Result<()> {
+        Ok(ptrace::setregs(self, registers)?)
+    }
+
+    fn cont(self, signal: Option<Signal>) -> anyhow::Result<()> {
+        Ok(ptrace::cont(self, signal)?)
+    }
+
+    fn step(self, signal: Option<Signal>) -> anyhow::Result<()> {
+        Ok(ptrace::step(self, signal)?)
+    }
+
+    fn wait(self) -> anyhow::Result<WaitStatus> {
+        Ok(waitpid(self, Some(WaitPidFlag::__WALL))?)
+    }
+}
+
+#[cfg(test)]
+pub mod mock {
+    use super::*;
+    use anyhow::anyhow;
+    use nix::sys::signal::SIGTRAP;
+    use std::{cell::RefCell, collections::HashMap};
+
+    // A thread with the memory given to it, the bytes that weren't added to
+    // it aren't mapped. It runs the few instructions of CODE when stepped
+    pub struct MockTracee {
+        pub memory: RefCell<HashMap<u64, u8>>,
+        pub registers: RefCell<user_regs_struct>,
+        // The first byte of every instruction run, to see that the original
+        // instructions run instead of the traps
+        pub executed: RefCell<Vec<u8>>,
+        // Returned by wait after stepping
+        pub status: RefCell<Option<WaitStatus>>,
+        pub read_only: bool,
+        // The writes succeed without changing anything
+        pub ignore_writes: bool,
+    }
+
+    impl Default for MockTracee {
+        fn default() -> MockTracee {
+            MockTracee {
+                memory: RefCell::default(),
+                // Every field is an integer
+                registers: RefCell::new(unsafe { std::mem::zeroed() }),
+                executed: RefCell::default(),
+                status: RefCell::default(),
+                read_only: false,
+                ignore_writes: false,
+            }
+        }
+    }
+
+    impl MockTracee {
+        pub fn with_code(address: u64, code: &[u8]) -> MockTracee {
+            let tracee = MockTracee::default();
+            tracee.memory.borrow_mut().extend(
+                code.iter()
+                    .enumerate()
+                    .map(|(offset, byte)| (address + offset as u64, *byte)),
+            );
+            tracee
+        }
+
+        pub fn byte(&self, address: u64) -> u8 {
+            self.memory.borrow()[&address]
+        }
+
+        // Stopped at rip, with the stack mapped below stack_pointer
+        pub fn stopped_at(self, rip: u64, stack_pointer: u64) -> MockTracee {
+            self.memory
+                .borrow_mut()
+                .extend((stack_pointer - 64..stack_pointer).map(|address| (address, 0)));
+            let mut registers = self.registers.borrow_mut();
+            registers.rip = rip;
+            registers.rsp = stack_pointer;
+            registers.rbp = 0x7fff_0000_1234;
+            drop(registers);
+            self
+        }
+
+        pub fn push(&self, value: u64) -> anyhow::Result<()> {
+            let stack_pointer = self.registers.borrow().rsp - 8;
+            self.write_word(stack_pointer, value as i64)?;
+            self.registers.borrow_mut().rsp = stack_pointer;
+            Ok(())
+        }
+    }
+
+    impl Tracee for &MockTracee {
+        fn read_word(self, address: u64) -> anyhow::Result<i64> {
+            let memory = self.memory.borrow();
+            let mut bytes = [0; 8];
+            for (offset, byte) in bytes.iter_mut().enumerate() {
+                *byte = *memory
+                    .get(&(address + offset as u64))
+                    .ok_or(anyhow!("Unmapped address"))?;
+            }
+            Ok(i64::from_le_bytes(bytes))
+        }
+
+        fn write_word(self, address: u64, word: i64) -> anyhow::Result<()> {
+            // Fails like a write with ptrace to an unmapped page
+            self.read_word(address)?;
+            if self.read_only {
+                anyhow::bail!("Read only memory");
+            }
+            if !self.ignore_writes {
+                let mut memory = self.memory.borrow_mut();
+                for (offset, byte) in word.to_le_bytes().into_iter().enumerate() {
+                    memory.insert(address + offset as u64, byte);
+                }
+            }
+            Ok(())
+        }
+
+        fn get_regs(self) -> anyhow::Result<user_regs_struct> {
+            Ok(*self.registers.borrow())
+        }
+
+        fn set_regs(self, registers: user_regs_struct) -> anyhow::Result<()> {
+            *self.registers.borrow_mut() = registers;
+            Ok(())
+        }
+
+        fn cont(self, _: Option<Signal>) -> anyhow::Result<()> {
+            anyhow::bail!("The mock can only be stepped")
+        }
+
+        fn step(self, _: Option<Signal>) -> anyhow::Result<()> {
+            let rip = self.registers.borrow().rip;
+            let opcode = self.read_word(rip)? as u8;
+            self.executed.borrow_mut().push(opcode);
+            let length = match opcode {
+                // push rbp
+                0x55 => {
+                    let frame_pointer = self.registers.borrow().rbp;
+                    self.push(frame_pointer)?;
+                    1
+                }
+                // call rel32
+                0xe8 => {
+                    self.push(rip + 5)?;
+                    5 + (self.read_word(rip + 1)? as i32) as u64
+                }
+                // mov rbp, rsp
+                0x48 => 3,
+                // mov eax, imm32
+                0xb8 => 5,
+                0xcc | 0x90 => 1,
+                _ => anyhow::bail!("The mock can't run {:#x}", opcode),
+            };
+            self.registers.borrow_mut().rip = rip.wrapping_add(length);
+            *self.status.borrow_mut() = Some(WaitStatus::Stopped(Pid::from_raw(1), SIGTRAP));
+            Ok(())
+        }
+
+        fn wait(self) -> anyhow::Result<WaitStatus> {
+            self.status
+                .borrow_mut()
+                .take()
+                .ok_or(anyhow!("The mock isn't running"))
+        }
+    }
+
+    // push rbp; mov rbp, rsp; mov eax, 0x2a; pop rbp; ret, and some padding
+    pub const CODE: [u8; 16] = [
+        0x55, 0x48, 0x89, 0xe5, 0xb8, 0x2a, 0x00, 0x00, 0x00, 0x5d, 0xc3, 0x90, 0x90, 0x90, 0x90,
+        0x90,
+    ];
+    pub const CODE_ADDRESS: u64 = 0x401000;
+}
@@ -0,0 +1,143 @@
+use anyhow::anyhow;
+use nix::unistd::Pid;
+
+use crate::{
+    LoadedBinary, ProgramContext, RunningProgram, Watchpoint,
+    binary::find_frame_debug_info,
+    dwarf,
+    frames::{get_selected_frame, print_address, print_location},
+    output::{self, Output},
+    print::{WATCH_PRINT_OPTIONS, format_value},
+    registers,
+    tracee::Tracee,
+};
+
+// Returns whether the program stopped because a watched variable changed
+pub fn report_watchpoint(
+    running_program: &mut RunningProgram,
+    binary: &LoadedBinary,
+) -> anyhow::Result<bool> {
+    let pid = running_program.thread;
+    let mut changed = false;
+    if let Some(index) = registers::get_triggered_watchpoint(pid)?
+        && let Some(watchpoint) = running_program.watchpoints[index].as_mut()
+    {
+        changed |= report_value_change(pid, watchpoint)?;
+    }
+    // The program is single-stepped while there are software watchpoints,
+    // so they are compared after every instruction
+    for watchpoint in &mut running_program.software_watchpoints {
+        changed |= report_value_change(pid, watchpoint)?;
+    }
+    if changed {
+        // Watchpoints stop after executing the instruction
+        let address = pid.get_regs()?.rip;
+        if print_location("Stopped at", running_program, binary, address).is_err() {
+            print_address(format!("Stopped at {:#x}", address), address);
+        }
+    }
+    Ok(changed)
+}
+
+// Returns whether the value of the variable changed, reporting both values
+fn report_value_change(pid: Pid, watchpoint: &mut Watchpoint) -> anyhow::Result<bool> {
+    let value = format_value(
+        pid,
+        watchpoint.address,
+        &watchpoint.type_info,
+        WATCH_PRINT_OPTIONS,
+    )?;
+    if value == watchpoint.last_value {
+        return Ok(false);
+    }
+    output::event(
+        "watchpoint",
+        Output::new(format!(
+            "Watchpoint {}: {}\nOld value = {}\nNew value = {}",
+            watchpoint.number, watchpoint.variable_name, watchpoint.last_value, value
+        ))
+        .with("watchpoint", watchpoint.number)
+        .with("variable", watchpoint.variable_name.as_str())
+        .with("old_value", watchpoint.last_value.as_str())
+        .with("new_value", value.as_str()),
+    );
+    watchpoint.last_value = value;
+    Ok(true)
+}
+
+pub fn add_watchpoint(context: &mut ProgramContext, variable_name: &str) -> anyhow::Result<String> {
+    let running_program = context
+        .running_program
+        .as_mut()
+        .ok_or(anyhow!("You need to run a program first"))?;
+    let binary = context.binary.as_ref().unwrap(); // If there's a pid, there's a binary
+    let pid = running_program.thread;
+    let frame = get_selected_frame(running_program, binary)?;
+    let (dwarf, load_bias) = find_frame_debug_info(running_program, binary, &frame);
+    let variable = dwarf.get_variable_info(
+        variable_name,
+        &frame,
+        load_bias,
+        WATCH_PRINT_OPTIONS.max_depth,
+    )?;
+    let address = match variable.location {
+        dwarf::VariableLocation::Address(address) => address,
+        dwarf::VariableLocation::Value(_) => {
+            anyhow::bail!("Variables stored in registers can't be watched")
+        }
+        dwarf::VariableLocation::OptimizedOut => {
+            anyhow::bail!("The variable is optimized out, it can't be watched")
+        }
+    };
+    let last_value = format_value(pid, address, &variable.type_info, WATCH_PRINT_OPTIONS)?;
+    let number = context.last_breakpoint_number + 1;
+    let watchpoint = Watchpoint {
+        number,
+        variable_name: variable_name.to_owned(),
+        address,
+        type_info: variable.type_info,
+        last_value,
+    };
+    // The debug registers only watch aligned 1, 2, 4 or 8 bytes
+    let length = watchpoint.type_info.size() / 8;
+    let index = running_program
+        .watchpoints
+        .iter()
+        .position(Option::is_none)
+        .filter(|_| [1, 2, 4, 8].contains(&length) && address % length == 0);
+    if let Some(index) = index
+        && set_watchpoint_on_threads(&running_program.threads, index, address, length)?
+    {
+        running_program.watchpoints[index] = Some(watchpoint);
+        context.last_breakpoint_number = number;
+        return Ok(format!("Watchpoint {} set on {}", number, variable_name));
+    }
+    running_program.software_watchpoints.push(watchpoint);
+    context.last_breakpoint_number = number;
+    Ok(format!(
+        "Software watchpoint {} set on {}, the program will run much slower",
+        number, variable_name
+    ))
+}
+
+// Returns whether the debug register could be set on every thread. If it
+// can't on one of them, it's cleared on the ones that were already set, so
+// none of them keeps a watchpoint that isn't in the list
+fn set_watchpoint_on_threads(
+    threads: &[Pid],
+    index: usize,
+    address: u64,
+    length: u64,
+) -> anyhow::Result<bool> {
+    for (count, &thread) in threads.iter().enumerate() {
+        if registers::set_watchpoint(thread, index, address, length).is_err() {
+            // The failed one could have been set partially
+            let _ = registers::clear_watchpoint(thread, index);
+            for &thread in &threads[..count] {
+                registers::clear_watchpoint(thread, index)?;
+            }
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
@@ -0,0 +1,208 @@
+#![cfg(all(target_os = "linux", target_arch = "x86_64"))]
+
+mod common;
+
+use std::fs;
+
+use common::{Session, compile, compile_static, field, line_of};
+
+const COUNTER: &str = r#"#include <stdio.h>
+
+int counter = 41;
+
+void increment(void) {
+    counter++; // increment
+}
+
+int main(void) {
+    increment();
+    printf("%d\n", counter); // print
+    return 0;
+}
+"#;
+
+#[test]
+fn stops_at_a_line_and_prints_a_global() {
+    let binary = compile("line_breakpoint", COUNTER, &[]);
+    let mut session = Session::new(&binary);
+    let line = line_of(COUNTER, "// print");
+    session.break_at(&format!("line_breakpoint.c:{}", line));
+    session.context.run(Vec::new()).unwrap();
+    assert_eq!(session.stopped_line(), Some(line as i64));
+    assert_eq!(session.value("counter"), "42");
+}
+
+#[test]
+fn continues_to_the_next_breakpoint_and_to_the_end() {
+    let binary = compile("continue_breakpoints", COUNTER, &[]);
+    let mut session = Session::new(&binary);
+    let first = line_of(COUNTER, "// increment");
+    let second = line_of(COUNTER, "// print");
+    session.break_at(&format!("continue_breakpoints.c:{}", first));
+    session.break_at(&format!("continue_breakpoints.c:{}", second));
+    session.context.run(Vec::new()).unwrap();
+    assert_eq!(session.stopped_line(), Some(first as i64));
+    assert_eq!(session.value("counter"), "41");
+    session.context.continue_execution().unwrap();
+    assert_eq!(session.stopped_line(), Some(second as i64));
+    assert_eq!(session.value("counter"), "42");
+    session.context.continue_execution().unwrap();
+    assert!(!session.context.is_running());
+}
+
+#[test]
+fn stops_at_a_line_of_a_static_executable() {
+    let binary = compile_static("static_breakpoint", COUNTER, &[]);
+    let mut session = Session::new(&binary);
+    let line = line_of(COUNTER, "// increment");
+    session.break_at(&format!("static_breakpoint.c:{}", line));
+    session.context.run(Vec::new()).unwrap();
+    assert_eq!(session.stopped_line(), Some(line as i64));
+    assert_eq!(session.value("counter"), "41");
+    session.context.continue_execution().unwrap();
+    assert!(!session.context.is_running());
+}
+
+const ARGUMENTS: &str = r#"int scale(int value, int factor) {
+    int result = value * factor; // body
+    return result;
+}
+
+int main(void) {
+    return scale(6, 7) - 42;
+}
+"#;
+
+// gcc doesn't mark the end of the prologue, the breakpoint still has to be
+// after the arguments are stored in the frame
+#[test]
+fn stops_after_the_prologue_of_a_function() {
+    let binary = compile("function_breakpoint", ARGUMENTS, &["-O0"]);
+    let mut session = Session::new(&binary);
+    session.break_at("scale");
+    session.context.run(Vec::new()).unwrap();
+    assert_eq!(
+        session.stopped_line(),
+        Some(line_of(ARGUMENTS, "// body") as i64)
+    );
+    assert_eq!(session.value("value"), "6");
+    assert_eq!(session.value("factor"), "7");
+}
+
+// The numbers stay the same after the temporary breakpoint is deleted
+#[test]
+fn keeps_the_numbers_of_the_breakpoints() {
+    let binary = compile("breakpoint_numbers", COUNTER, &[]);
+    let mut session = Session::new(&binary);
+    let first = line_of(COUNTER, "// increment");
+    let second = format!("breakpoint_numbers.c:{}", line_of(COUNTER, "// print"));
+    session
+        .context
+        .add_breakpoint(&format!("breakpoint_numbers.c:{}", first), true)
+        .unwrap();
+    session.break_at(&second);
+    session.context.run(Vec::new()).unwrap();
+    assert_eq!(session.stopped_line(), Some(first as i64));
+    assert!(
+        session
+            .context
+            .add_breakpoint(&second, false)
+            .unwrap()
+            .ends_with("breakpoint 2 is also there")
+    );
+    assert_eq!(
+        session.context.set_breakpoint_enabled(2, false).unwrap(),
+        "Breakpoint 2 disabled"
+    );
+    assert_eq!(
+        session.context.set_breakpoint_enabled(3, false).unwrap(),
+        "Breakpoint 3 disabled"
+    );
+    assert!(session.context.set_breakpoint_enabled(1, true).is_err());
+    session.context.continue_execution().unwrap();
+    assert!(!session.context.is_running());
+}
+
+// The breakpoints of the previous binary are added to the new one when they
+// have code there, and the rest wait for a binary that has it
+#[test]
+fn moves_the_breakpoints_to_another_binary() {
+    let first = compile("first_binary", COUNTER, &[]);
+    let second = compile("second_binary", ARGUMENTS, &[]);
+    let mut session = Session::new(&first);
+    let increment = line_of(COUNTER, "// increment");
+    session.break_at(&format!("first_binary.c:{}", increment));
+    session.break_at("main");
+    let message = session
+        .context
+        .load_binary(second.to_str().unwrap())
+        .unwrap();
+    assert!(
+        message.contains(&format!("first_binary.c:{} is pending", increment)),
+        "{}",
+        message
+    );
+    session.context.run(Vec::new()).unwrap();
+    assert_eq!(
+        session.stopped_line(),
+        Some(line_of(ARGUMENTS, "return scale") as i64)
+    );
+    session.context.kill().unwrap();
+    session
+        .context
+        .load_binary(first.to_str().unwrap())
+        .unwrap();
+    session.context.run(Vec::new()).unwrap();
+    assert_eq!(
+        session.stopped_line(),
+        Some(line_of(COUNTER, "    increment();") as i64)
+    );
+    session.context.continue_execution().unwrap();
+    assert_eq!(session.stopped_line(), Some(increment as i64));
+}
+
+// The breakpoints are set again in the new process after the previous one
+// exited
+#[test]
+fn stops_at_the_breakpoint_after_the_program_exits() {
+    let binary = compile("run_after_exit", COUNTER, &[]);
+    let mut session = Session::new(&binary);
+    let line = line_of(COUNTER, "// increment");
+    session.break_at(&format!("run_after_exit.c:{}", line));
+    session.context.run(Vec::new()).unwrap();
+    assert_eq!(session.stopped_line(), Some(line as i64));
+    let message = session.context.continue_execution().unwrap();
+    assert_eq!(message, "[Inferior exited normally with code 0]");
+    assert!(!session.context.is_running());
+    session.context.run(Vec::new()).unwrap();
+    assert_eq!(session.stopped_line(), Some(line as i64));
+    assert_eq!(session.value("counter"), "41");
+}
+
+// The path recorded in the DWARF is kept when the source can't be found, so
+// the stop is still reported without showing the line
+#[test]
+fn stops_at_a_line_whose_source_was_removed() {
+    let binary = compile("removed_source", COUNTER, &[]);
+    let source = binary.with_extension("c");
+    fs::remove_file(&source).unwrap();
+    let mut session = Session::new(&binary);
+    let line = line_of(COUNTER, "// increment");
+    session.break_at(&format!("removed_source.c:{}", line));
+    session.context.run(Vec::new()).unwrap();
+    let stop = session.last_stop().unwrap();
+    assert_eq!(
+        field(&stop, "file").and_then(|file| file.as_str()),
+        source.to_str()
+    );
+    assert_eq!(
+        field(&stop, "line").and_then(|line| line.as_i64()),
+        Some(line as i64)
+    );
+    assert!(!stop.text.contains('\n'), "{}", stop.text);
+    assert!(
+        stop.text.ends_with(&format!("removed_source.c:{})", line)),
+        "{}",
+        stop.text
+    );
+}
@@ -0,0 +1,137 @@
+// Helpers for the tests that debug real programs. The fixtures are small C
+// programs, compiled with gcc when the tests run
+#![allow(dead_code)]
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::{Mutex, MutexGuard, PoisonError},
+};
+
+use debugito::{
+    ProgramContext,
+    output::{self, Json, Output},
+};
+
+// The tests of a file run in threads of the same process, which share the
+// children that waitpid can return and the events, so only one program is
+// debugged at a time
+static SESSION: Mutex<()> = Mutex::new(());
+
+// Compiles the source with -g and the flags, the name has to be unique
+pub fn compile(name: &str, source: &str, flags: &[&str]) -> PathBuf {
+    let directory = Path::new(env!("CARGO_TARGET_TMPDIR")).join("fixtures");
+    fs::create_dir_all(&directory).unwrap();
+    let source_path = directory.join(format!("{}.c", name));
+    fs::write(&source_path, source).unwrap();
+    let binary = directory.join(name);
+    let status = Command::new("gcc")
+        .arg("-g")
+        .args(flags)
+        .arg(&source_path)
+        .arg("-o")
+        .arg(&binary)
+        .status()
+        .expect("gcc is needed to build the fixtures");
+    assert!(status.success(), "Failed to compile {}", name);
+    binary
+}
+
+// Static executables have no interpreter, the program starts at the entry
+// point of the binary and the libc code is part of it
+pub fn compile_static(name: &str, source: &str, flags: &[&str]) -> PathBuf {
+    compile(name, source, &[&["-static"], flags].concat())
+}
+
+// The number of the first line that contains the marker, usually a comment
+pub fn line_of(source: &str, marker: &str) -> u64 {
+    source
+        .lines()
+        .position(|line| line.contains(marker))
+        .unwrap_or_else(|| panic!("{} isn't in the source", marker)) as u64
+        + 1
+}
+
+pub fn field<'a>(output: &'a Output, key: &str) -> Option<&'a Json> {
+    output
+        .fields
+        .iter()
+        .find(|(name, _)| *name == key)
+        .map(|(_, value)| value)
+}
+
+pub struct Session {
+    pub context: ProgramContext,
+    _guard: MutexGuard<'static, ()>,
+}
+
+impl Session {
+    pub fn new(binary: &Path) -> Self {
+        // A failed test doesn't prevent running the others
+        let guard = SESSION.lock().unwrap_or_else(PoisonError::into_inner);
+        output::set_mode(output::Mode::Dap);
+        output::take_events();
+        let mut context = ProgramContext::default();
+        context.load_binary(binary.to_str().unwrap()).unwrap();
+        Session {
+            context,
+            _guard: guard,
+        }
+    }
+
+    // Where the program stopped last, since the previous call
+    pub fn stopped_line(&self) -> Option<i64> {
+        self.stopped_at().map(|(line, _)| line)
+    }
+
+    // The line and the address of the last stop, since the previous call.
+    // Addresses are given in hexadecimal
+    pub fn stopped_at(&self) -> Option<(i64, String)> {
+        let output = self.last_stop()?;
+        Some((
+            field(&output, "line")?.as_i64()?,
+            field(&output, "address")?.as_str()?.to_owned(),
+        ))
+    }
+
+    // The location event of the last stop, since the previous call
+    pub fn last_stop(&self) -> Option<Output> {
+        output::take_events()
+            .into_iter()
+            .rev()
+            .find(|(name, _)| *name == "location")
+            .map(|(_, output)| output)
+    }
+
+    // The signal of the last stop caused by one, since the previous call
+    pub fn received_signal(&self) -> Option<String> {
+        output::take_events()
+            .into_iter()
+            .rev()
+            .filter(|(name, _)| *name == "signal")
+            .find_map(|(_, output)| Some(field(&output, "signal")?.as_str()?.to_owned()))
+    }
+
+    pub fn break_at(&mut self, location: &str) {
+        self.context.add_breakpoint(location, false).unwrap();
+    }
+
+    // The value as print shows it, without the name and the type
+    pub fn value(&mut self, expression: &str) -> String {
+        let output = self.context.print(expression).unwrap();
+        match field(&output, "value") {
+            Some(Json::String(value)) => value.clone(),
+            Some(Json::Number(value)) => value.to_string(),
+            _ => panic!("print {} has no value: {}", expression, output.text),
+        }
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        if self.context.is_running() {
+            let _ = self.context.kill();
+        }
+    }
+}
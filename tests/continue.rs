@@ -0,0 +1,83 @@
+#![cfg(all(target_os = "linux", target_arch = "x86_64"))]
+
+mod common;
+
+use common::{Session, compile, line_of};
+
+const SIGNALS: &str = r#"#include <signal.h>
+
+int handled = 0;
+
+void handler(int signal) {
+    handled = signal;
+}
+
+int main(void) {
+    signal(SIGUSR1, handler);
+    for (int i = 0; i < 2; i++) {
+        raise(SIGUSR1);
+        handled += 100; // after
+    }
+    return 0;
+}
+"#;
+
+fn stop_at_the_signal() -> (Session, i64) {
+    let binary = compile("continue_signals", SIGNALS, &["-O0"]);
+    let mut session = Session::new(&binary);
+    let line = line_of(SIGNALS, "// after");
+    session.break_at(&format!("continue_signals.c:{}", line));
+    session.context.run(Vec::new()).unwrap();
+    assert_eq!(session.received_signal().as_deref(), Some("SIGUSR1"));
+    (session, line as i64)
+}
+
+// The program isn't stopped at a breakpoint, there is nothing to step over
+#[test]
+fn continues_after_a_signal_stop() {
+    let (mut session, line) = stop_at_the_signal();
+    session.context.continue_execution().unwrap();
+    assert_eq!(session.stopped_line(), Some(line));
+    assert_eq!(session.value("handled"), "10");
+}
+
+// The instruction of the breakpoint was already executed by the step
+#[test]
+fn continues_after_a_single_step() {
+    let (mut session, line) = stop_at_the_signal();
+    session.context.continue_execution().unwrap();
+    session.context.step_instruction().unwrap();
+    session.context.continue_execution().unwrap();
+    assert_eq!(session.received_signal().as_deref(), Some("SIGUSR1"));
+    session.context.continue_execution().unwrap();
+    assert_eq!(session.stopped_line(), Some(line));
+    assert_eq!(session.value("handled"), "10");
+    session.context.continue_execution().unwrap();
+    assert!(!session.context.is_running());
+}
+
+const LOOP: &str = r#"int main(void) {
+    int total = 0;
+    for (int i = 0; i < 4; i++) {
+        total += i; // body
+    }
+    return total;
+}
+"#;
+
+// The breakpoint is placed back after each continue, so it stops on every
+// iteration of the loop
+#[test]
+fn stops_at_each_iteration_of_a_loop() {
+    let binary = compile("continue_loop", LOOP, &["-O0"]);
+    let mut session = Session::new(&binary);
+    let line = line_of(LOOP, "// body");
+    session.break_at(&format!("continue_loop.c:{}", line));
+    session.context.run(Vec::new()).unwrap();
+    for i in 0..4 {
+        assert_eq!(session.stopped_line(), Some(line as i64));
+        assert_eq!(session.value("i"), i.to_string());
+        session.context.continue_execution().unwrap();
+    }
+    assert!(!session.context.is_running());
+}
@@ -0,0 +1,64 @@
+#![cfg(all(target_os = "linux", target_arch = "x86_64"))]
+
+mod common;
+
+use common::{Session, compile, line_of};
+
+const SQUARE: &str = r#"int side = 7;
+
+int square(int value) {
+    return value * value; // square
+}
+
+int main(void) {
+    return square(side) - 49;
+}
+"#;
+
+// The breakpoint is found by the name of the file, so its path has to come
+// out right from the line table
+fn stops_in_square(name: &str, flags: &[&str]) {
+    let binary = compile(name, SQUARE, flags);
+    let mut session = Session::new(&binary);
+    let line = line_of(SQUARE, "// square");
+    session.break_at(&format!("{}.c:{}", name, line));
+    session.context.run(Vec::new()).unwrap();
+    assert_eq!(session.stopped_line(), Some(line as i64));
+    assert_eq!(session.value("side"), "7");
+    assert_eq!(session.value("value"), "7");
+}
+
+#[test]
+fn reads_dwarf_4() {
+    stops_in_square("dwarf_4", &["-gdwarf-4"]);
+}
+
+// The file names are in .debug_line_str and the file indexes start at 0
+#[test]
+fn reads_dwarf_5() {
+    stops_in_square("dwarf_5", &["-gdwarf-5"]);
+}
+
+#[test]
+fn reads_compressed_debug_sections() {
+    stops_in_square("compressed", &["-gz"]);
+}
+
+// The registers and the trap instruction are the x86-64 ones, so binaries for
+// other architectures are refused instead of being debugged wrongly
+#[test]
+fn refuses_aarch64_binaries() {
+    let binary = compile("aarch64_machine", SQUARE, &[]);
+    let mut contents = std::fs::read(&binary).unwrap();
+    // e_machine, EM_AARCH64
+    contents[18..20].copy_from_slice(&183u16.to_le_bytes());
+    let patched = binary.with_file_name("aarch64_machine_patched");
+    std::fs::write(&patched, contents).unwrap();
+    let mut context = debugito::ProgramContext::default();
+    let error = context
+        .load_binary(patched.to_str().unwrap())
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("Aarch64"), "{}", error);
+    assert!(error.contains("isn't supported"), "{}", error);
+}
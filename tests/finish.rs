@@ -0,0 +1,39 @@
+#![cfg(all(target_os = "linux", target_arch = "x86_64"))]
+
+mod common;
+
+use common::{Session, compile, line_of};
+
+const WIDE: &str = r#"__int128 wide(void) {
+    return -((__int128)1 << 100); // wide
+}
+
+long double extended(void) {
+    return 2.5L; // extended
+}
+
+int main(void) {
+    wide();
+    extended();
+    return 0;
+}
+"#;
+
+// They don't fit in rax, long double comes in st0 and __int128 in rdx:rax
+#[test]
+fn finish_shows_values_wider_than_a_register() {
+    let binary = compile("finish_wide", WIDE, &[]);
+    let mut session = Session::new(&binary);
+    session.break_at(&format!("finish_wide.c:{}", line_of(WIDE, "// wide")));
+    session.break_at(&format!("finish_wide.c:{}", line_of(WIDE, "// extended")));
+    session.context.run(Vec::new()).unwrap();
+    assert_eq!(
+        session.context.finish().unwrap(),
+        "wide returned -1267650600228229401496703205376 (__int128)"
+    );
+    session.context.continue_execution().unwrap();
+    assert_eq!(
+        session.context.finish().unwrap(),
+        "extended returned 2.5 (long double)"
+    );
+}
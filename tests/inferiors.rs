@@ -0,0 +1,69 @@
+#![cfg(all(target_os = "linux", target_arch = "x86_64"))]
+
+mod common;
+
+use std::{fs, path::Path, process::Command};
+
+use common::{compile, line_of};
+
+// The child kills the program while it's stopped at the breakpoint
+const KILLED: &str = r#"#include <signal.h>
+#include <unistd.h>
+
+int main(void) {
+    pid_t parent = getpid();
+    if (fork() == 0) {
+        usleep(200000);
+        kill(parent, SIGKILL);
+        _exit(0);
+    }
+    return 0; // stop
+}
+"#;
+
+// Still running when the other program is killed
+const SLEEPER: &str = r#"#include <unistd.h>
+
+int main(void) {
+    usleep(600000);
+    return 0; // stop
+}
+"#;
+
+// The exit happens while waiting for the other program, which must not take
+// it as the exit of one of its threads
+#[test]
+fn reports_the_exit_of_an_inferior_that_isn_t_selected() {
+    let killed = compile("inferiors_killed", KILLED, &["-O0"]);
+    let sleeper = compile("inferiors_sleeper", SLEEPER, &["-O0"]);
+    let script = Path::new(env!("CARGO_TARGET_TMPDIR")).join("inferiors.script");
+    fs::write(
+        &script,
+        format!(
+            "break inferiors_killed.c:{}\nrun\nadd-inferior\ninferior 2\nload {}\n\
+            break inferiors_sleeper.c:{}\nrun\ninferior 1\ninfo inferiors\n",
+            line_of(KILLED, "// stop"),
+            sleeper.display(),
+            line_of(SLEEPER, "// stop"),
+        ),
+    )
+    .unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_debugito"))
+        .arg(&killed)
+        .arg("-x")
+        .arg(&script)
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}", stdout);
+    assert!(
+        stdout.contains("Selected inferior 1\n[Inferior terminated by signal SIGKILL]"),
+        "{}",
+        stdout
+    );
+    assert!(
+        stdout.contains(&format!("* 1: {}\n", killed.display())),
+        "{}",
+        stdout
+    );
+}
@@ -0,0 +1,85 @@
+#![cfg(all(target_os = "linux", target_arch = "x86_64"))]
+
+mod common;
+
+use common::{Session, compile, line_of};
+
+const LOOP: &str = r#"int add(int a, int b) {
+    int sum = a + b;
+    return sum; // return
+}
+
+int main(void) {
+    int total = 3;
+    for (int i = 0; i < 2; i++) {
+        int inner = i * 10;
+        total += add(inner, i); // call
+    }
+    return total;
+}
+"#;
+
+// gcc computes the frame base from the canonical frame address, with
+// DW_OP_call_frame_cfa, even without optimizations
+#[test]
+fn prints_the_locals_and_arguments_of_gcc_functions() {
+    let binary = compile("locals_loop", LOOP, &["-O0"]);
+    let mut session = Session::new(&binary);
+    session.break_at(&format!("locals_loop.c:{}", line_of(LOOP, "// return")));
+    session.context.run(Vec::new()).unwrap();
+    session.context.continue_execution().unwrap();
+    assert_eq!(session.value("a"), "10");
+    assert_eq!(session.value("b"), "1");
+    assert_eq!(session.value("sum"), "11");
+}
+
+#[test]
+fn prints_the_variables_of_nested_blocks() {
+    let binary = compile("locals_blocks", LOOP, &["-O0"]);
+    let mut session = Session::new(&binary);
+    session.break_at(&format!("locals_blocks.c:{}", line_of(LOOP, "// call")));
+    session.context.run(Vec::new()).unwrap();
+    session.context.continue_execution().unwrap();
+    assert_eq!(session.value("total"), "3");
+    assert_eq!(session.value("i"), "1");
+    assert_eq!(session.value("inner"), "10");
+}
+
+const SHADOWING: &str = r#"int main(void) {
+    int value = 1;
+    int result = 0;
+    if (value > 0) {
+        int scoped = value + 1;
+        int value = 5;
+        result = scoped + value; // inside
+    }
+    return result - 7; // outside
+}
+"#;
+
+// The variables of a block are only visible inside it, where they hide the
+// ones with the same name of the enclosing scopes
+#[test]
+fn looks_up_the_variables_of_the_innermost_block() {
+    let binary = compile("locals_shadowing", SHADOWING, &["-O0"]);
+    let mut session = Session::new(&binary);
+    let inside = line_of(SHADOWING, "// inside");
+    let outside = line_of(SHADOWING, "// outside");
+    session.break_at(&format!("locals_shadowing.c:{}", inside));
+    session.break_at(&format!("locals_shadowing.c:{}", outside));
+    session.context.run(Vec::new()).unwrap();
+    assert_eq!(session.stopped_line(), Some(inside as i64));
+    assert_eq!(session.value("scoped"), "2");
+    assert_eq!(session.value("value"), "5");
+    session.context.continue_execution().unwrap();
+    assert_eq!(session.stopped_line(), Some(outside as i64));
+    assert_eq!(session.value("value"), "1");
+    assert_eq!(session.value("result"), "7");
+    let Err(error) = session.context.print("scoped") else {
+        panic!("scoped was printed outside of its block");
+    };
+    assert_eq!(
+        error.to_string(),
+        "The variable scoped is not in scope here"
+    );
+}
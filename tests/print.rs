@@ -0,0 +1,127 @@
+#![cfg(all(target_os = "linux", target_arch = "x86_64"))]
+
+mod common;
+
+use common::{Session, compile, line_of};
+
+const ARRAYS: &str = r#"int values[3] = {10, 20, 30};
+int *middle = &values[1];
+
+int main(void) {
+    return values[0]; // return
+}
+"#;
+
+fn stop_in_arrays() -> Session {
+    let binary = compile("print_arrays", ARRAYS, &[]);
+    let mut session = Session::new(&binary);
+    session.break_at(&format!("print_arrays.c:{}", line_of(ARRAYS, "// return")));
+    session.context.run(Vec::new()).unwrap();
+    session
+}
+
+#[test]
+fn indexes_pointers_with_negative_indexes() {
+    let mut session = stop_in_arrays();
+    assert_eq!(session.value("middle[-1]"), "10");
+    assert_eq!(session.value("middle[1]"), "30");
+}
+
+#[test]
+fn warns_about_negative_array_indexes() {
+    let mut session = stop_in_arrays();
+    let output = session.context.print("values[-1]").unwrap();
+    assert!(output.text.contains("out of bounds"), "{}", output.text);
+}
+
+// The offsets of these elements don't fit in an address
+#[test]
+fn rejects_indexes_out_of_the_address_space() {
+    let mut session = stop_in_arrays();
+    for expression in [
+        "values[0x4000000000000000]",
+        "middle[-0x4000000000000000]",
+        "middle[-0x8000000000000001]",
+    ] {
+        assert!(session.context.print(expression).is_err(), "{}", expression);
+    }
+}
+
+const BOOLEANS: &str = r#"#include <stdbool.h>
+
+struct flags {
+    bool off;
+    unsigned char rest[7];
+};
+
+int main(void) {
+    struct flags flags = {false, {0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff}};
+    bool done = false;
+    return done + flags.off; // return
+}
+"#;
+
+// A word read at the address of the bool also has the bytes after it
+#[test]
+fn ignores_the_bytes_after_a_bool() {
+    let binary = compile("print_booleans", BOOLEANS, &["-O0"]);
+    let mut session = Session::new(&binary);
+    session.break_at(&format!(
+        "print_booleans.c:{}",
+        line_of(BOOLEANS, "// return")
+    ));
+    session.context.run(Vec::new()).unwrap();
+    assert_eq!(session.value("flags.off"), "false");
+    assert_eq!(session.value("done"), "false");
+}
+
+const FLEXIBLE: &str = r#"#include <stdlib.h>
+
+struct numbers {
+    int length;
+    int values[];
+};
+
+int main(void) {
+    struct numbers *numbers = malloc(sizeof(struct numbers) + 2 * sizeof(int));
+    numbers->length = 2;
+    numbers->values[0] = 10;
+    numbers->values[1] = 20;
+    return numbers->length; // return
+}
+"#;
+
+// The compiler doesn't know how many elements the last member has
+#[test]
+fn prints_structs_with_flexible_array_members() {
+    let binary = compile("print_flexible", FLEXIBLE, &["-O0"]);
+    let mut session = Session::new(&binary);
+    session.break_at(&format!(
+        "print_flexible.c:{}",
+        line_of(FLEXIBLE, "// return")
+    ));
+    session.context.run(Vec::new()).unwrap();
+    assert_eq!(
+        session.value("*numbers"),
+        "{length = 2, values = <array of unknown length>}"
+    );
+    assert_eq!(session.value("numbers[0].values[1]"), "20");
+}
+
+const FLOATS: &str = r#"int main(void) {
+    double ratio = 2.5;
+    float half = 0.5f;
+    return (int)(ratio * half); // return
+}
+"#;
+
+// The width of the type tells how the bytes of the value are decoded
+#[test]
+fn prints_floating_point_locals() {
+    let binary = compile("print_floats", FLOATS, &["-O0"]);
+    let mut session = Session::new(&binary);
+    session.break_at(&format!("print_floats.c:{}", line_of(FLOATS, "// return")));
+    session.context.run(Vec::new()).unwrap();
+    assert_eq!(session.value("ratio"), "2.5");
+    assert_eq!(session.value("half"), "0.5");
+}
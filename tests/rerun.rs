@@ -0,0 +1,95 @@
+#![cfg(all(target_os = "linux", target_arch = "x86_64"))]
+
+// Runs the debugger on a script, so the program is debugged as the users do
+use std::{
+    fs,
+    io::Write,
+    path::Path,
+    process::{Command, Stdio},
+};
+
+const COUNTER: &str = r#"int counter = 0;
+
+int main(void) {
+    for (int i = 0; i < 3; i++) {
+        counter++; // increment
+    }
+    return 0;
+}
+"#;
+
+// The addresses of the frames shown by backtrace, one for each run
+fn stop_addresses(output: &str) -> Vec<&str> {
+    output
+        .lines()
+        .filter_map(|line| line.strip_prefix("#0 "))
+        .filter_map(|frame| frame.split_whitespace().next())
+        .collect()
+}
+
+// The binary is loaded somewhere else on every run, the breakpoints can't
+// keep the addresses of the previous one
+#[test]
+fn rearms_the_breakpoints_where_the_binary_is_loaded_again() {
+    let randomization = fs::read_to_string("/proc/sys/kernel/randomize_va_space");
+    if randomization.is_ok_and(|setting| setting.trim() == "0") {
+        eprintln!("ASLR is disabled, the binary is always loaded at the same address");
+        return;
+    }
+    let directory = Path::new(env!("CARGO_TARGET_TMPDIR")).join("rerun");
+    fs::create_dir_all(&directory).unwrap();
+    let source = directory.join("counter.c");
+    fs::write(&source, COUNTER).unwrap();
+    let binary = directory.join("counter");
+    let status = Command::new("gcc")
+        .args(["-g", "-gdwarf-4"])
+        .arg(&source)
+        .arg("-o")
+        .arg(&binary)
+        .status()
+        .expect("gcc is needed to build the fixture");
+    assert!(status.success());
+    let line = COUNTER
+        .lines()
+        .position(|line| line.contains("// increment"))
+        .unwrap()
+        + 1;
+    let script = directory.join("script");
+    fs::write(
+        &script,
+        format!("break counter.c:{line}\nrun\nbacktrace\nkill\nrun\nbacktrace\n"),
+    )
+    .unwrap();
+    let mut debugger = Command::new(env!("CARGO_BIN_EXE_debugito"))
+        .arg(&binary)
+        .arg("-x")
+        .arg(&script)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // Confirms every question, like killing the program
+    debugger
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"y\ny\ny\n")
+        .unwrap();
+    let output = debugger.wait_with_output().unwrap();
+    assert!(output.status.success());
+    let output = String::from_utf8(output.stdout).unwrap();
+    let location = format!("counter.c:{line})");
+    assert_eq!(
+        output.matches("Reached breakpoint").count(),
+        2,
+        "{}",
+        output
+    );
+    assert_eq!(output.matches(&location).count(), 4, "{}", output);
+    let addresses = stop_addresses(&output);
+    assert_eq!(addresses.len(), 2, "{}", output);
+    assert_ne!(
+        addresses[0], addresses[1],
+        "ASLR should load the binary elsewhere"
+    );
+}
@@ -0,0 +1,67 @@
+#![cfg(all(target_os = "linux", target_arch = "x86_64"))]
+
+mod common;
+
+use common::{Session, compile, line_of};
+
+const LOOP: &str = r#"int main(void) {
+    int sum = 0; // start
+    for (int i = 0; i < 2; i++) { // header
+        sum += i; // body
+    }
+    return sum; // end
+}
+"#;
+
+// The header has the initialization, the condition and the increment, each
+// of them is a statement where next stops
+#[test]
+fn steps_through_a_for_loop_header() {
+    let binary = compile("step_loop", LOOP, &["-O0"]);
+    let mut session = Session::new(&binary);
+    let start = line_of(LOOP, "// start");
+    let header = line_of(LOOP, "// header") as i64;
+    let body = line_of(LOOP, "// body") as i64;
+    let end = line_of(LOOP, "// end") as i64;
+    session.break_at(&format!("step_loop.c:{}", start));
+    session.context.run(Vec::new()).unwrap();
+    let mut lines = Vec::new();
+    while lines.last() != Some(&end) && lines.len() < 20 {
+        session.context.step_over().unwrap();
+        lines.push(session.stopped_line().unwrap());
+    }
+    assert_eq!(
+        lines,
+        [header, body, header, body, header, end],
+        "lines at each next"
+    );
+}
+
+const CALLBACK: &str = r#"#include <stdlib.h>
+
+int compare(const void *a, const void *b) {
+    return *(const int *)a - *(const int *)b; // compare
+}
+
+int main(void) {
+    int values[] = {3, 1, 2};
+    qsort(values, 3, sizeof(int), compare); // sort
+    return values[0]; // return
+}
+"#;
+
+// qsort doesn't have line info, so it runs until returning instead of
+// stepping through it into the comparison function
+#[test]
+fn steps_over_calls_to_functions_without_line_info() {
+    let binary = compile("step_callback", CALLBACK, &["-O0"]);
+    let mut session = Session::new(&binary);
+    session.break_at(&format!("step_callback.c:{}", line_of(CALLBACK, "// sort")));
+    session.context.run(Vec::new()).unwrap();
+    session.context.step_into().unwrap();
+    assert_eq!(
+        session.stopped_line(),
+        Some(line_of(CALLBACK, "// return") as i64)
+    );
+    assert_eq!(session.value("values[0]"), "1");
+}